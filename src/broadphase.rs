@@ -0,0 +1,290 @@
+//! Broad phase of collision detection - cheap search of pairs of bodies whose bounding
+//! boxes overlap, only such pairs are passed to exact (and expensive) narrow phase.
+
+use rg3d_core::{
+    math::{
+        aabb::AxisAlignedBoundingBox,
+        ray::Ray,
+    },
+    pool::Handle,
+};
+use std::collections::HashMap;
+use crate::rigid_body::RigidBody;
+
+/// Acceleration structure which tracks bounding boxes of bodies. Physics owns one, see
+/// [`Physics::set_broadphase`](crate::Physics::set_broadphase), and keeps it in sync with
+/// its bodies: bodies are inserted when they start participating in collision detection,
+/// their boxes are updated every step and they're removed when they're disabled or
+/// removed from world.
+pub trait Broadphase: Send {
+    /// Starts tracking of body with given bounding box.
+    fn insert(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox);
+
+    /// Stops tracking of body, does nothing if body is not tracked.
+    fn remove(&mut self, handle: Handle<RigidBody>);
+
+    /// Reserves space for at least `additional` more bodies. Does nothing by default.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Replaces bounding box of tracked body.
+    fn update(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox);
+
+    /// Writes every pair of bodies with overlapping boxes into `pairs`, each pair once in
+    /// any order. `pairs` is not cleared.
+    fn query_pairs(&mut self, pairs: &mut Vec<(Handle<RigidBody>, Handle<RigidBody>)>);
+
+    /// Writes handles of bodies whose boxes overlap given box into `handles`. `handles`
+    /// is not cleared.
+    fn query_aabb(&self, aabb: &AxisAlignedBoundingBox, handles: &mut Vec<Handle<RigidBody>>);
+
+    /// Writes handles of bodies whose boxes are hit by given ray into `handles`.
+    /// `handles` is not cleared.
+    fn query_ray(&self, ray: &Ray, handles: &mut Vec<Handle<RigidBody>>);
+}
+
+/// Returns true if boxes overlap or touch. Works for infinite boxes of half-spaces too.
+pub fn is_aabbs_overlap(a: &AxisAlignedBoundingBox, b: &AxisAlignedBoundingBox) -> bool {
+    a.min.x <= b.max.x && a.max.x >= b.min.x &&
+        a.min.y <= b.max.y && a.max.y >= b.min.y &&
+        a.min.z <= b.max.z && a.max.z >= b.min.z
+}
+
+/// Default broad phase. Boxes are kept sorted by their minimum along X axis, so pairs are
+/// found by single sweep over sorted list. Bodies usually move a little per step, so
+/// order barely changes and insertion sort restores it in almost linear time.
+#[derive(Clone, Debug, Default)]
+pub struct SweepAndPrune {
+    entries: Vec<(Handle<RigidBody>, AxisAlignedBoundingBox)>,
+    indices: HashMap<Handle<RigidBody>, usize>,
+    /// Indices of entries sorted by minimum along X axis.
+    order: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns amount of tracked bodies.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn sort(&mut self) {
+        let entries = &self.entries;
+        for i in 1..self.order.len() {
+            let mut j = i;
+            while j > 0 && entries[self.order[j - 1]].1.min.x > entries[self.order[j]].1.min.x {
+                self.order.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+}
+
+impl Broadphase for SweepAndPrune {
+    fn insert(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox) {
+        if let Some(index) = self.indices.get(&handle) {
+            self.entries[*index].1 = aabb;
+        } else {
+            self.indices.insert(handle, self.entries.len());
+            self.order.push(self.entries.len());
+            self.entries.push((handle, aabb));
+        }
+    }
+
+    fn remove(&mut self, handle: Handle<RigidBody>) {
+        if let Some(index) = self.indices.remove(&handle) {
+            let last = self.entries.len() - 1;
+            self.entries.swap_remove(index);
+            self.order.retain(|i| *i != index);
+            if index != last {
+                // Last entry took place of removed one.
+                self.indices.insert(self.entries[index].0, index);
+                for i in self.order.iter_mut().filter(|i| **i == last) {
+                    *i = index;
+                }
+            }
+        }
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+        self.indices.reserve(additional);
+        self.order.reserve(additional);
+    }
+
+    fn update(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox) {
+        if let Some(index) = self.indices.get(&handle) {
+            self.entries[*index].1 = aabb;
+        }
+    }
+
+    fn query_pairs(&mut self, pairs: &mut Vec<(Handle<RigidBody>, Handle<RigidBody>)>) {
+        self.sort();
+        for (k, a) in self.order.iter().map(|i| &self.entries[*i]).enumerate() {
+            for b in self.order[k + 1..].iter().map(|i| &self.entries[*i]) {
+                // Rest of boxes start even further along X.
+                if b.1.min.x > a.1.max.x {
+                    break;
+                }
+                if is_aabbs_overlap(&a.1, &b.1) {
+                    pairs.push((a.0, b.0));
+                }
+            }
+        }
+    }
+
+    fn query_aabb(&self, aabb: &AxisAlignedBoundingBox, handles: &mut Vec<Handle<RigidBody>>) {
+        handles.extend(self.entries.iter()
+            .filter(|(_, entry)| is_aabbs_overlap(entry, aabb))
+            .map(|(handle, _)| *handle));
+    }
+
+    fn query_ray(&self, ray: &Ray, handles: &mut Vec<Handle<RigidBody>>) {
+        handles.extend(self.entries.iter()
+            .filter(|(_, entry)| ray.aabb_intersection(entry).is_some())
+            .map(|(handle, _)| *handle));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::{
+        math::{aabb::AxisAlignedBoundingBox, ray::Ray, vec3::Vec3},
+        pool::Handle,
+    };
+    use crate::{
+        Physics,
+        broadphase::{Broadphase, is_aabbs_overlap},
+        rigid_body::{RigidBody, CollisionFlags},
+        convex_shape::{ConvexShape, BoxShape, SphereShape},
+        test::{DT, sphere, floor},
+    };
+
+    /// Tests every box against every other one.
+    #[derive(Default)]
+    struct BruteForce {
+        entries: Vec<(Handle<RigidBody>, AxisAlignedBoundingBox)>,
+    }
+
+    impl Broadphase for BruteForce {
+        fn insert(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox) {
+            self.remove(handle);
+            self.entries.push((handle, aabb));
+        }
+
+        fn remove(&mut self, handle: Handle<RigidBody>) {
+            self.entries.retain(|(other, _)| *other != handle);
+        }
+
+        fn update(&mut self, handle: Handle<RigidBody>, aabb: AxisAlignedBoundingBox) {
+            for entry in self.entries.iter_mut().filter(|(other, _)| *other == handle) {
+                entry.1 = aabb;
+            }
+        }
+
+        fn query_pairs(&mut self, pairs: &mut Vec<(Handle<RigidBody>, Handle<RigidBody>)>) {
+            for (i, a) in self.entries.iter().enumerate() {
+                for b in self.entries[i + 1..].iter().filter(|b| is_aabbs_overlap(&a.1, &b.1)) {
+                    pairs.push((a.0, b.0));
+                }
+            }
+        }
+
+        fn query_aabb(&self, aabb: &AxisAlignedBoundingBox, handles: &mut Vec<Handle<RigidBody>>) {
+            handles.extend(self.entries.iter().filter(|(_, entry)| is_aabbs_overlap(entry, aabb)).map(|(handle, _)| *handle));
+        }
+
+        fn query_ray(&self, ray: &Ray, handles: &mut Vec<Handle<RigidBody>>) {
+            handles.extend(self.entries.iter().filter(|(_, entry)| ray.aabb_intersection(entry).is_some()).map(|(handle, _)| *handle));
+        }
+    }
+
+    fn pile() -> (Physics, Vec<Handle<RigidBody>>) {
+        let mut physics = Physics::new();
+        let mut handles = vec![physics.add_body(floor())];
+        for i in 0..16 {
+            let position = Vec3::new((i % 4) as f32 * 0.6, 0.5 + i as f32 * 0.7, (i % 3) as f32 * 0.4);
+            let body = if i % 2 == 0 {
+                sphere(position, 0.4)
+            } else {
+                let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.3, 0.3, 0.3))));
+                body.set_position(position);
+                body
+            };
+            handles.push(physics.add_body(body));
+        }
+        (physics, handles)
+    }
+
+    #[test]
+    fn custom_broadphase_gives_same_simulation_as_default() {
+        let (mut default, handles) = pile();
+        let (mut custom, _) = pile();
+        custom.set_broadphase(Box::new(BruteForce::default()));
+        // Body removed after broad phase was set must leave it too.
+        let removed = custom.add_body(sphere(Vec3::new(50.0, 1.0, 0.0), 0.5));
+        custom.remove_body(removed);
+
+        for _ in 0..180 {
+            default.step(DT);
+            custom.step(DT);
+        }
+
+        for handle in handles.iter() {
+            assert_eq!(default.borrow_body(*handle).get_position(), custom.borrow_body(*handle).get_position());
+            assert_eq!(default.borrow_body(*handle).get_rotation(), custom.borrow_body(*handle).get_rotation());
+        }
+        assert_eq!(default.neighbors(handles[1], 2.0), custom.neighbors(handles[1], 2.0));
+    }
+
+    #[test]
+    fn custom_broadphase_finds_query_only_and_aabb_only_bodies() {
+        fn world(custom: bool) -> (Physics, Vec<Handle<RigidBody>>) {
+            let (mut physics, _) = pile();
+            if custom {
+                physics.set_broadphase(Box::new(BruteForce::default()));
+            }
+            let mut query_only = sphere(Vec3::new(20.0, 1.0, 0.0), 0.5);
+            query_only.collision_flags = CollisionFlags::QUERY_ONLY;
+            let mut aabb_only = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+            aabb_only.set_position(Vec3::new(23.0, 1.0, 0.0)).set_gravity(Vec3::ZERO);
+            aabb_only.collision_flags = CollisionFlags::AABB_ONLY;
+            let mut probe = sphere(Vec3::new(21.0, 1.0, 0.0), 0.4);
+            probe.set_gravity(Vec3::ZERO);
+            let handles = vec![physics.add_body(query_only), physics.add_body(aabb_only), physics.add_body(probe)];
+            // Lands on box of AABB-only body, so it gets contact.
+            physics.add_body(sphere(Vec3::new(23.0, 2.2, 0.0), 0.3));
+            (physics, handles)
+        }
+
+        let (mut default, handles) = world(false);
+        let (mut custom, _) = world(true);
+        for _ in 0..60 {
+            default.step(DT);
+            custom.step(DT);
+        }
+
+        let neighbors = default.neighbors(handles[2], 2.5);
+        assert!(neighbors.contains(&handles[0]) && neighbors.contains(&handles[1]));
+        assert_eq!(custom.neighbors(handles[2], 2.5), neighbors);
+
+        let shape = ConvexShape::Sphere(SphereShape::new(0.1));
+        let sweep = |physics: &Physics| physics.sweep_shape_all(&shape, Vec3::new(18.0, 1.0, 0.0), Vec3::new(8.0, 0.0, 0.0), u64::MAX)
+            .iter().map(|hit| hit.body).collect::<Vec<_>>();
+        let hits = vec![handles[0], handles[2], handles[1]];
+        assert_eq!(sweep(&default), hits);
+        assert_eq!(sweep(&custom), hits);
+
+        assert!(!default.borrow_body(handles[1]).get_contacts().is_empty());
+        for handle in handles.iter() {
+            assert_eq!(default.borrow_body(*handle).get_position(), custom.borrow_body(*handle).get_position());
+            assert_eq!(default.borrow_body(*handle).get_contacts().len(), custom.borrow_body(*handle).get_contacts().len());
+        }
+    }
+}
@@ -0,0 +1,739 @@
+//! Constraints between rigid bodies. Constraints are solved after collisions on each step
+//! by moving bodies directly, Verlet integration turns position change into velocity.
+
+use rg3d_core::{
+    math::{
+        vec3::Vec3,
+        mat3::Mat3,
+        quat::Quat,
+    },
+    pool::{Handle, Pool},
+    visitor::{Visit, VisitResult, Visitor},
+};
+use std::collections::HashMap;
+use crate::{
+    rigid_body::{RigidBody, CollisionFlags},
+    convex_shape::ShapeTransform,
+    float_ops::{Backend, FloatOps},
+};
+
+/// Rigidly binds two bodies so they move and rotate together as a single unit. Relative
+/// transform of bodies is captured at creation. Welded bodies don't collide with each
+/// other, but still collide with the rest of world. Half-space, disabled and query-only
+/// bodies are not moved by weld, other body takes whole correction.
+#[derive(Clone, Debug)]
+pub struct WeldConstraint {
+    pub(in crate) body_a: Handle<RigidBody>,
+    pub(in crate) body_b: Handle<RigidBody>,
+    /// Position of body B in local space of body A.
+    pub(in crate) local_offset: Vec3,
+    /// Rotation of body B relative to body A.
+    pub(in crate) relative_rotation: Quat,
+    pub(in crate) break_threshold: Option<f32>,
+    pub(in crate) broken: bool,
+    /// Weld was created by contact of sticky body, see `CollisionFlags::STICKY`.
+    pub(in crate) sticky: bool,
+}
+
+impl Default for WeldConstraint {
+    fn default() -> Self {
+        Self {
+            body_a: Handle::NONE,
+            body_b: Handle::NONE,
+            local_offset: Vec3::ZERO,
+            relative_rotation: Quat::default(),
+            break_threshold: None,
+            broken: false,
+            sticky: false,
+        }
+    }
+}
+
+impl Visit for WeldConstraint {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.body_a.visit("BodyA", visitor)?;
+        self.body_b.visit("BodyB", visitor)?;
+        self.local_offset.visit("LocalOffset", visitor)?;
+        self.relative_rotation.visit("RelativeRotation", visitor)?;
+        self.break_threshold.visit("BreakThreshold", visitor)?;
+        self.broken.visit("Broken", visitor)?;
+        let _ = self.sticky.visit("Sticky", visitor); // let _ for backward compatibility.
+
+        visitor.leave_region()
+    }
+}
+
+impl WeldConstraint {
+    pub fn new(body_a: Handle<RigidBody>, a: &RigidBody, body_b: Handle<RigidBody>, b: &RigidBody) -> Self {
+        let inv_rotation_a = conjugate(a.rotation);
+        Self {
+            body_a,
+            body_b,
+            local_offset: a.get_transform().inverse_transform_direction(b.position - a.position),
+            relative_rotation: inv_rotation_a * b.rotation,
+            break_threshold: None,
+            broken: false,
+            sticky: false,
+        }
+    }
+
+    #[inline]
+    pub fn get_bodies(&self) -> (Handle<RigidBody>, Handle<RigidBody>) {
+        (self.body_a, self.body_b)
+    }
+
+    /// Sets maximum correction (in units per step) that weld can apply, weld will break
+    /// if it has to move bodies farther. `None` means that weld is unbreakable.
+    pub fn set_break_threshold(&mut self, threshold: Option<f32>) -> &mut Self {
+        self.break_threshold = threshold;
+        self
+    }
+
+    #[inline]
+    pub fn get_break_threshold(&self) -> Option<f32> {
+        self.break_threshold
+    }
+
+    /// Returns true if weld was broken, broken weld has no effect on bodies.
+    #[inline]
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Returns true if weld was created automatically by contact of sticky body.
+    #[inline]
+    pub fn is_sticky(&self) -> bool {
+        self.sticky
+    }
+
+    fn solve(&mut self, bodies: &mut Pool<RigidBody>) {
+        if self.broken || !bodies.is_valid_handle(self.body_a) || !bodies.is_valid_handle(self.body_b) ||
+            self.body_a.index() == self.body_b.index() {
+            return;
+        }
+
+        let (a, b) = bodies.borrow_two_mut((self.body_a, self.body_b));
+
+        let (share_a, share_b) = match (Self::is_movable(a), Self::is_movable(b)) {
+            (true, true) => (0.5, 0.5),
+            (true, false) => (1.0, 0.0),
+            (false, true) => (0.0, 1.0),
+            (false, false) => return,
+        };
+
+        let target = ShapeTransform::new(a.position, a.rotation).transform_point(self.local_offset);
+        if self.break_threshold.is_some_and(|threshold| (target - b.position).len() > threshold) {
+            self.broken = true;
+            return;
+        }
+
+        // Rotation which brings B to its welded orientation is split between bodies. Bodies
+        // turn around common pivot, so B swings around A on its lever arm and vice versa.
+        let correction = a.rotation * self.relative_rotation * conjugate(b.rotation);
+        let pivot = a.position.scale(share_b) + b.position.scale(share_a);
+        let turn_a = fraction_of_rotation(conjugate(correction), share_a);
+        let turn_b = fraction_of_rotation(correction, share_b);
+        a.rotation = turn_a * a.rotation;
+        a.position = pivot + Mat3::from_quat(turn_a).transform_vector(a.position - pivot);
+        b.rotation = turn_b * b.rotation;
+        b.position = pivot + Mat3::from_quat(turn_b).transform_vector(b.position - pivot);
+
+        let error = ShapeTransform::new(a.position, a.rotation).transform_point(self.local_offset) - b.position;
+        a.position -= error.scale(share_a);
+        b.position += error.scale(share_b);
+    }
+
+    /// Returns true if weld may move body.
+    fn is_movable(body: &RigidBody) -> bool {
+        body.enabled && !body.shape.is_half_space() && !body.collision_flags.contains(CollisionFlags::QUERY_ONLY)
+    }
+}
+
+/// Drives body along axis with target velocity, like motor of elevator or moving platform.
+/// Each step velocity of body along axis is changed towards target, but no more than
+/// given maximum force allows, so loaded body may move slower than target. Velocity
+/// across axis is not affected. Bodies have unit mass, so force is equal to acceleration.
+#[derive(Clone, Debug)]
+pub struct MotorConstraint {
+    pub(in crate) body: Handle<RigidBody>,
+    pub(in crate) axis: Vec3,
+    pub(in crate) target_velocity: f32,
+    pub(in crate) max_force: f32,
+}
+
+impl Default for MotorConstraint {
+    fn default() -> Self {
+        Self {
+            body: Handle::NONE,
+            axis: Vec3::UP,
+            target_velocity: 0.0,
+            max_force: 0.0,
+        }
+    }
+}
+
+impl Visit for MotorConstraint {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.body.visit("Body", visitor)?;
+        self.axis.visit("Axis", visitor)?;
+        self.target_velocity.visit("TargetVelocity", visitor)?;
+        self.max_force.visit("MaxForce", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl MotorConstraint {
+    /// Creates motor which drives body along given axis with target velocity (in units per
+    /// second). Degenerated axis is replaced with up vector.
+    pub fn new(body: Handle<RigidBody>, axis: Vec3, target_velocity: f32, max_force: f32) -> Self {
+        Self {
+            body,
+            axis: Backend::normalize(axis).unwrap_or(Vec3::UP),
+            target_velocity,
+            max_force: max_force.abs(),
+        }
+    }
+
+    #[inline]
+    pub fn get_body(&self) -> Handle<RigidBody> {
+        self.body
+    }
+
+    #[inline]
+    pub fn get_axis(&self) -> Vec3 {
+        self.axis
+    }
+
+    /// Sets target velocity along axis (in units per second), negative value drives body
+    /// in opposite direction.
+    pub fn set_target_velocity(&mut self, target_velocity: f32) -> &mut Self {
+        self.target_velocity = target_velocity;
+        self
+    }
+
+    #[inline]
+    pub fn get_target_velocity(&self) -> f32 {
+        self.target_velocity
+    }
+
+    pub fn set_max_force(&mut self, max_force: f32) -> &mut Self {
+        self.max_force = max_force.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_max_force(&self) -> f32 {
+        self.max_force
+    }
+
+    fn solve(&mut self, bodies: &mut Pool<RigidBody>, delta_time: f32) {
+        if let Some(body) = bodies.try_borrow_mut(self.body) {
+            // Velocity of body is in units per step and includes collision response of this
+            // step. Body is moved instead of changing its last position, so motor compensates
+            // pushes of collisions as well.
+            let current = body.get_velocity().dot(&self.axis);
+            let target = self.target_velocity * delta_time;
+            let max_change = self.max_force * delta_time * delta_time;
+            let change = (target - current).clamp(-max_change, max_change);
+            body.position += self.axis.scale(change);
+        }
+    }
+}
+
+/// Damped spring between two bodies, or between body and fixed point of world. Unlike
+/// weld, spring does not fix positions of bodies but pushes them with force along line
+/// between their centers, so bodies overshoot rest length and oscillate until damping
+/// stops them. Useful for suspensions and bouncy ropes. Bodies have unit mass, so force
+/// is equal to acceleration.
+#[derive(Clone, Debug)]
+pub struct SpringConstraint {
+    pub(in crate) body_a: Handle<RigidBody>,
+    /// Second body or `Handle::NONE` if spring is attached to anchor.
+    pub(in crate) body_b: Handle<RigidBody>,
+    /// World space point to which spring is attached when there is no second body.
+    pub(in crate) anchor: Vec3,
+    pub(in crate) rest_length: f32,
+    pub(in crate) stiffness: f32,
+    pub(in crate) damping: f32,
+}
+
+impl Default for SpringConstraint {
+    fn default() -> Self {
+        Self {
+            body_a: Handle::NONE,
+            body_b: Handle::NONE,
+            anchor: Vec3::ZERO,
+            rest_length: 0.0,
+            stiffness: 0.0,
+            damping: 0.0,
+        }
+    }
+}
+
+impl Visit for SpringConstraint {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.body_a.visit("BodyA", visitor)?;
+        self.body_b.visit("BodyB", visitor)?;
+        self.anchor.visit("Anchor", visitor)?;
+        self.rest_length.visit("RestLength", visitor)?;
+        self.stiffness.visit("Stiffness", visitor)?;
+        self.damping.visit("Damping", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl SpringConstraint {
+    /// Creates spring between two bodies, pass `Handle::NONE` as second body to attach
+    /// spring to anchor point (see [`set_anchor`](Self::set_anchor)).
+    pub fn new(body_a: Handle<RigidBody>, body_b: Handle<RigidBody>, rest_length: f32, stiffness: f32, damping: f32) -> Self {
+        Self {
+            body_a,
+            body_b,
+            anchor: Vec3::ZERO,
+            rest_length: rest_length.abs(),
+            stiffness: stiffness.abs(),
+            damping: damping.abs(),
+        }
+    }
+
+    #[inline]
+    pub fn get_bodies(&self) -> (Handle<RigidBody>, Handle<RigidBody>) {
+        (self.body_a, self.body_b)
+    }
+
+    /// Sets world space point to which spring is attached, used only when spring has no
+    /// second body.
+    pub fn set_anchor(&mut self, anchor: Vec3) -> &mut Self {
+        self.anchor = anchor;
+        self
+    }
+
+    #[inline]
+    pub fn get_anchor(&self) -> Vec3 {
+        self.anchor
+    }
+
+    pub fn set_rest_length(&mut self, rest_length: f32) -> &mut Self {
+        self.rest_length = rest_length.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_rest_length(&self) -> f32 {
+        self.rest_length
+    }
+
+    /// Sets force per unit of stretch of spring.
+    pub fn set_stiffness(&mut self, stiffness: f32) -> &mut Self {
+        self.stiffness = stiffness.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    /// Sets force per unit of speed (in units per second) at which spring stretches or
+    /// contracts.
+    pub fn set_damping(&mut self, damping: f32) -> &mut Self {
+        self.damping = damping.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_damping(&self) -> f32 {
+        self.damping
+    }
+
+    fn solve(&mut self, bodies: &mut Pool<RigidBody>, delta_time: f32) {
+        if delta_time <= 0.0 || !bodies.is_valid_handle(self.body_a) ||
+            (self.body_b.is_some() && self.body_a.index() == self.body_b.index()) {
+            return;
+        }
+
+        let (end, end_velocity) = match bodies.try_borrow(self.body_b) {
+            Some(b) => (b.position, b.get_velocity()),
+            None => (self.anchor, Vec3::ZERO),
+        };
+        let a = bodies.borrow(self.body_a);
+        let delta = end - a.position;
+        let direction = match Backend::normalize(delta) {
+            Some(direction) => direction,
+            None => return,
+        };
+        // Velocities of bodies are in units per step.
+        let stretch_speed = (end_velocity - a.get_velocity()).dot(&direction) / delta_time;
+        let stretch = Backend::sqrt(delta.sqr_len()) - self.rest_length;
+        let force = direction.scale(self.stiffness * stretch + self.damping * stretch_speed);
+
+        // Force is applied on next integration.
+        bodies.borrow_mut(self.body_a).acceleration += force;
+        if let Some(b) = bodies.try_borrow_mut(self.body_b) {
+            b.acceleration -= force;
+        }
+    }
+}
+
+/// Drives body towards target position with force of PD controller (see
+/// [`Physics::grab`](crate::Physics::grab)), like hands or tractor beam. Unlike teleporting
+/// body to target, force is limited, so grabbed body still collides with everything,
+/// shoves light obstacles and stops at walls. Controller compensates gravity of body,
+/// so body does not sag below target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KinematicTarget {
+    pub(in crate) target: Vec3,
+    pub(in crate) max_force: f32,
+    pub(in crate) stiffness: f32,
+    pub(in crate) damping: f32,
+}
+
+impl Default for KinematicTarget {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            max_force: 0.0,
+            stiffness: Self::DEFAULT_STIFFNESS,
+            damping: Self::DEFAULT_DAMPING,
+        }
+    }
+}
+
+impl Visit for KinematicTarget {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.target.visit("Target", visitor)?;
+        self.max_force.visit("MaxForce", visitor)?;
+        self.stiffness.visit("Stiffness", visitor)?;
+        self.damping.visit("Damping", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl KinematicTarget {
+    pub const DEFAULT_STIFFNESS: f32 = 100.0;
+    /// Critical damping for default stiffness, body approaches target without overshoot.
+    pub const DEFAULT_DAMPING: f32 = 20.0;
+
+    pub fn new(target: Vec3, max_force: f32) -> Self {
+        Self {
+            target,
+            max_force: max_force.abs(),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_target(&mut self, target: Vec3) -> &mut Self {
+        self.target = target;
+        self
+    }
+
+    #[inline]
+    pub fn get_target(&self) -> Vec3 {
+        self.target
+    }
+
+    pub fn set_max_force(&mut self, max_force: f32) -> &mut Self {
+        self.max_force = max_force.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_max_force(&self) -> f32 {
+        self.max_force
+    }
+
+    /// Sets force per unit of distance to target.
+    pub fn set_stiffness(&mut self, stiffness: f32) -> &mut Self {
+        self.stiffness = stiffness.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    /// Sets force per unit of speed (in units per second) of body.
+    pub fn set_damping(&mut self, damping: f32) -> &mut Self {
+        self.damping = damping.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_damping(&self) -> f32 {
+        self.damping
+    }
+
+    /// Returns force which drives body to target, compensating given gravity. Length of
+    /// force does not exceed max force.
+    pub(in crate) fn force(&self, body: &RigidBody, gravity: Vec3, delta_time: f32) -> Vec3 {
+        if delta_time <= 0.0 {
+            return Vec3::ZERO;
+        }
+        // Velocity of body is in units per step.
+        let velocity = body.get_velocity().scale(1.0 / delta_time);
+        let force = (self.target - body.position).scale(self.stiffness) - velocity.scale(self.damping) - gravity;
+        let length = Backend::sqrt(force.sqr_len());
+        if length > self.max_force {
+            force.scale(self.max_force / length)
+        } else {
+            force
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    Weld(WeldConstraint),
+    Motor(MotorConstraint),
+    Spring(SpringConstraint),
+}
+
+impl Default for Constraint {
+    fn default() -> Self {
+        Constraint::Weld(Default::default())
+    }
+}
+
+impl Visit for Constraint {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        let mut id = self.id();
+        id.visit("Kind", visitor)?;
+        if visitor.is_reading() {
+            *self = Constraint::new(id)?;
+        }
+        match self {
+            Constraint::Weld(weld) => weld.visit("Data", visitor)?,
+            Constraint::Motor(motor) => motor.visit("Data", visitor)?,
+            Constraint::Spring(spring) => spring.visit("Data", visitor)?,
+        }
+
+        visitor.leave_region()
+    }
+}
+
+impl Constraint {
+    pub fn id(&self) -> i32 {
+        match self {
+            Constraint::Weld(_) => 0,
+            Constraint::Motor(_) => 1,
+            Constraint::Spring(_) => 2,
+        }
+    }
+
+    pub fn new(id: i32) -> Result<Self, String> {
+        match id {
+            0 => Ok(Constraint::Weld(Default::default())),
+            1 => Ok(Constraint::Motor(Default::default())),
+            2 => Ok(Constraint::Spring(Default::default())),
+            _ => Err("Invalid constraint id!".to_owned())
+        }
+    }
+
+    /// Returns handles of bodies bound by constraint, second handle is `Handle::NONE` for
+    /// constraints which affect single body.
+    pub fn get_bodies(&self) -> (Handle<RigidBody>, Handle<RigidBody>) {
+        match self {
+            Constraint::Weld(weld) => weld.get_bodies(),
+            Constraint::Motor(motor) => (motor.body, Handle::NONE),
+            Constraint::Spring(spring) => spring.get_bodies(),
+        }
+    }
+
+    pub(in crate) fn solve(&mut self, bodies: &mut Pool<RigidBody>, delta_time: f32) {
+        match self {
+            Constraint::Weld(weld) => weld.solve(bodies),
+            Constraint::Motor(motor) => motor.solve(bodies, delta_time),
+            Constraint::Spring(spring) => spring.solve(bodies, delta_time),
+        }
+    }
+
+    /// Replaces handles of bodies using given map, handles which are not in map become
+    /// invalid.
+    pub(in crate) fn remap_bodies(&mut self, remap: &HashMap<Handle<RigidBody>, Handle<RigidBody>>) {
+        let remap_handle = |handle: &mut Handle<RigidBody>| *handle = remap.get(handle).copied().unwrap_or(Handle::NONE);
+        match self {
+            Constraint::Weld(weld) => {
+                remap_handle(&mut weld.body_a);
+                remap_handle(&mut weld.body_b);
+            }
+            Constraint::Motor(motor) => remap_handle(&mut motor.body),
+            Constraint::Spring(spring) => {
+                remap_handle(&mut spring.body_a);
+                remap_handle(&mut spring.body_b);
+            }
+        }
+    }
+}
+
+/// Inverse of unit quaternion.
+fn conjugate(q: Quat) -> Quat {
+    Quat {
+        x: -q.x,
+        y: -q.y,
+        z: -q.z,
+        w: q.w,
+    }
+}
+
+/// Returns rotation around same axis as given unit quaternion by given fraction of its
+/// angle, along shortest arc.
+fn fraction_of_rotation(q: Quat, fraction: f32) -> Quat {
+    let q = if q.w < 0.0 { q * -1.0 } else { q };
+    let axis = Vec3::new(q.x, q.y, q.z);
+    match Backend::normalize(axis) {
+        Some(axis) => Quat::from_axis_angle(axis, 2.0 * q.w.min(1.0).acos() * fraction),
+        None => Quat::IDENTITY,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::{
+        math::{vec3::Vec3, quat::Quat},
+        visitor::{Visit, Visitor},
+    };
+    use crate::{
+        Physics,
+        rigid_body::RigidBody,
+        convex_shape::{ConvexShape, BoxShape},
+        constraint::{Constraint, SpringConstraint},
+        test::{DT, sphere, floor},
+    };
+    use super::conjugate;
+
+    fn cube(position: Vec3, rotation: Quat) -> RigidBody {
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        body.set_position(position).set_rotation(rotation);
+        body
+    }
+
+    #[test]
+    fn welded_boxes_keep_relative_transform_on_landing() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        // Boxes overlap a bit, welded bodies don't collide with each other.
+        let a = physics.add_body(cube(Vec3::new(0.0, 3.0, 0.0), Quat::IDENTITY));
+        let b = physics.add_body(cube(Vec3::new(0.9, 3.5, 0.0), Quat::from_axis_angle(Vec3::UP, 0.3)));
+        physics.weld(a, b);
+
+        let relative = |physics: &Physics| {
+            let (a, b) = (physics.borrow_body(a), physics.borrow_body(b));
+            (a.get_transform().inverse_transform_point(b.get_position()), conjugate(a.get_rotation()) * b.get_rotation())
+        };
+        let (offset, rotation) = relative(&physics);
+
+        for _ in 0..300 {
+            physics.step(DT);
+        }
+
+        // Landed and at rest.
+        assert!(physics.borrow_body(a).get_position().y < 1.5);
+        assert!(physics.borrow_body(a).get_velocity().len() < 1.0e-3);
+        let (landed_offset, landed_rotation) = relative(&physics);
+        assert!(landed_offset.distance(&offset) < 0.01);
+        assert!(landed_rotation.dot(&rotation).abs() > 0.9999);
+    }
+
+    #[test]
+    fn weld_does_not_move_half_space() {
+        let mut physics = Physics::new();
+        let floor = physics.add_body(floor());
+        let ball = physics.add_body(sphere(Vec3::new(0.0, 2.0, 0.0), 0.5));
+        physics.weld(floor, ball);
+
+        for _ in 0..60 {
+            physics.step(DT);
+        }
+
+        assert_eq!(physics.borrow_body(floor).get_position(), Vec3::ZERO);
+        // Ball hangs on weld.
+        assert!((physics.borrow_body(ball).get_position().y - 2.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn pendulum_keeps_links_after_reload() {
+        let mut physics = Physics::new();
+        let upper = physics.add_body(sphere(Vec3::new(1.0, -1.0, 0.0), 0.2));
+        let lower = physics.add_body(sphere(Vec3::new(2.0, -2.0, 0.0), 0.2));
+        let mut anchor_link = SpringConstraint::new(upper, Default::default(), 1.5, 2000.0, 5.0);
+        anchor_link.set_anchor(Vec3::ZERO);
+        physics.add_constraint(Constraint::Spring(anchor_link));
+        physics.add_constraint(Constraint::Spring(SpringConstraint::new(upper, lower, 1.5, 2000.0, 5.0)));
+        for _ in 0..30 {
+            physics.step(DT);
+        }
+
+        let path = std::env::temp_dir().join("rg3d_physics_pendulum.bin");
+        let mut visitor = Visitor::new();
+        physics.visit("Physics", &mut visitor).unwrap();
+        visitor.save_binary(&path).unwrap();
+        let mut visitor = Visitor::load_binary(&path).unwrap();
+        let mut loaded = Physics::new();
+        loaded.visit("Physics", &mut visitor).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.constraints.iter().count(), 2);
+        let links = |physics: &Physics| {
+            let (upper, lower) = (physics.borrow_body(upper).get_position(), physics.borrow_body(lower).get_position());
+            (upper.len(), upper.distance(&lower))
+        };
+        for _ in 0..120 {
+            physics.step(DT);
+            loaded.step(DT);
+            let (anchor_link, lower_link) = links(&loaded);
+            // Stiff springs stretch only a little under weight of bodies.
+            assert!((anchor_link - 1.5).abs() < 0.1 && (lower_link - 1.5).abs() < 0.1);
+        }
+        let (expected_anchor_link, expected_lower_link) = links(&physics);
+        let (anchor_link, lower_link) = links(&loaded);
+        assert!((anchor_link - expected_anchor_link).abs() < 1.0e-4);
+        assert!((lower_link - expected_lower_link).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn spring_oscillates_and_decays_to_rest_length() {
+        let mut physics = Physics::new();
+        let mut body = sphere(Vec3::new(3.0, 0.0, 0.0), 0.2);
+        body.set_gravity(Vec3::ZERO);
+        let body = physics.add_body(body);
+        let mut spring = SpringConstraint::new(body, Default::default(), 2.0, 50.0, 1.0);
+        spring.set_anchor(Vec3::ZERO);
+        physics.add_constraint(Constraint::Spring(spring));
+
+        let mut crossings = 0;
+        let mut min_length = f32::MAX;
+        let mut stretched = true;
+        let mut late_amplitude = 0.0f32;
+        for step in 0..600 {
+            physics.step(DT);
+            let length = physics.borrow_body(body).get_position().len();
+            min_length = min_length.min(length);
+            if (length > 2.0) != stretched {
+                stretched = !stretched;
+                crossings += 1;
+            }
+            if step >= 540 {
+                late_amplitude = late_amplitude.max((length - 2.0).abs());
+            }
+        }
+
+        // Unlike rigid link, spring overshoots rest length and swings around it.
+        assert!(min_length < 1.5);
+        assert!(crossings >= 4);
+        assert!(late_amplitude < 0.1);
+    }
+}
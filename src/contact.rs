@@ -3,18 +3,71 @@ use rg3d_core::{
     pool::Handle,
     visitor::{Visit, VisitResult, Visitor}
 };
+use std::{
+    cell::UnsafeCell,
+    fmt::{Debug, Formatter},
+    mem::MaybeUninit,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 use crate::{
     rigid_body::RigidBody,
     static_geometry::StaticGeometry
 };
 
+/// Contact information which is passed into contact filter before collision response
+/// is applied. Everything is given from point of view of `body`.
+#[derive(Debug, Clone)]
+pub struct ContactInfo {
+    /// Body that is being pushed out.
+    pub body: Handle<RigidBody>,
+    /// Other body of contact, or `Handle::NONE` if contact is with static geometry.
+    pub other_body: Handle<RigidBody>,
+    /// Static geometry of contact, or `Handle::NONE` if contact is with other body.
+    pub static_geom: Handle<StaticGeometry>,
+    /// Index of static triangle, meaningful only for contacts with static geometry.
+    pub triangle_index: u32,
+    /// World space contact point.
+    pub position: Vec3,
+    /// Direction in which `body` will be pushed, same convention as [`Contact::normal`].
+    /// Filter may change it.
+    pub normal: Vec3,
+    /// Penetration depth along normal. Filter may change it.
+    pub depth: f32,
+    /// Velocity of `body` relative to other party of contact.
+    pub relative_velocity: Vec3,
+}
+
+/// Contact filter is called for each generated contact before collision response,
+/// returning `false` drops the contact completely.
+pub type ContactFilter = dyn Fn(&mut ContactInfo) -> bool + Send;
+
+/// Response filter is called for each accepted contact, returning `false` keeps the
+/// contact but skips position correction for it.
+pub type ResponseFilter = dyn Fn(&ContactInfo) -> bool + Send;
+
 #[derive(Debug, Clone)]
 pub struct Contact {
     pub body: Handle<RigidBody>,
     pub position: Vec3,
+    /// Unit normal which points away from other party of contact towards body that owns
+    /// the contact: it points up for body resting on floor, and for two bodies side by
+    /// side normals of their contacts point away from each other.
     pub normal: Vec3,
     pub triangle_index: u32,
-    pub static_geom: Handle<StaticGeometry>
+    pub static_geom: Handle<StaticGeometry>,
+    /// Contact point in local space of body which owns the contact. It moves together
+    /// with body, unlike world space position.
+    pub local_position_a: Vec3,
+    /// Contact point in local space of other body, or in world space if contact is
+    /// with static geometry.
+    pub local_position_b: Vec3,
+    /// Speed (in units per step) at which parties of contact were approaching each other
+    /// along normal when contact was generated. Bodies have unit mass, so this is also
+    /// magnitude of impulse which stops the approach.
+    pub impulse: f32,
 }
 
 impl Default for Contact {
@@ -25,10 +78,28 @@ impl Default for Contact {
             normal: Vec3::new(0.0, 1.0, 0.0),
             triangle_index: 0,
             static_geom: Handle::NONE,
+            local_position_a: Vec3::ZERO,
+            local_position_b: Vec3::ZERO,
+            impulse: 0.0,
         }
     }
 }
 
+impl Contact {
+    /// Returns true if contact surface is walkable - angle between contact normal and
+    /// given up vector does not exceed given slope limit (in degrees). Up vector must
+    /// be normalized, see [`Physics::get_up_vector`](crate::Physics::get_up_vector).
+    pub fn is_ground(&self, up: Vec3, max_slope_deg: f32) -> bool {
+        self.normal.dot(&up) >= max_slope_deg.to_radians().cos()
+    }
+
+    /// Returns true if contact is with static geometry, false if it is with other body.
+    #[inline]
+    pub fn is_with_static(&self) -> bool {
+        self.body.is_none()
+    }
+}
+
 impl Visit for Contact {
     fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
         visitor.enter_region(name)?;
@@ -37,7 +108,203 @@ impl Visit for Contact {
         self.position.visit("Position", visitor)?;
         self.normal.visit("Normal", visitor)?;
         self.triangle_index.visit("TriangleIndex", visitor)?;
+        let _ = self.local_position_a.visit("LocalPositionA", visitor); // let _ for backward compatibility.
+        let _ = self.local_position_b.visit("LocalPositionB", visitor);
+        let _ = self.impulse.visit("Impulse", visitor);
 
         visitor.leave_region()
     }
-}
\ No newline at end of file
+}
+
+/// Event which is generated when two parties start touching, see [`ContactEventQueue`].
+#[derive(Debug, Clone)]
+pub struct ContactEvent {
+    pub body: Handle<RigidBody>,
+    /// Other body, or `Handle::NONE` if contact is with static geometry.
+    pub other_body: Handle<RigidBody>,
+    /// Static geometry, or `Handle::NONE` if contact is with other body.
+    pub static_geom: Handle<StaticGeometry>,
+    /// World space contact point.
+    pub position: Vec3,
+    /// Normal of contact from point of view of `body`, see [`Contact::normal`].
+    pub normal: Vec3,
+    /// See [`Contact::impulse`].
+    pub impulse: f32,
+}
+
+struct EventSlot {
+    /// Position in queue for which slot is ready: write position when slot is free,
+    /// write position + 1 when slot holds event.
+    sequence: AtomicUsize,
+    event: UnsafeCell<MaybeUninit<ContactEvent>>,
+}
+
+/// Bounded multi-producer multi-consumer ring buffer, every slot has sequence number
+/// which tells whether slot can be written or read at current position.
+struct EventQueueState {
+    slots: Box<[EventSlot]>,
+    // Amount of events queue may hold, there is always one spare slot so that
+    // single-event queue can tell written slot from free one.
+    capacity: usize,
+    read_position: AtomicUsize,
+    write_position: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+// Slot is accessed only by thread which claimed its position, see `try_push` and `pop`.
+unsafe impl Sync for EventQueueState {}
+
+impl Drop for EventQueueState {
+    fn drop(&mut self) {
+        let mut position = *self.read_position.get_mut();
+        let end = *self.write_position.get_mut();
+        while position != end {
+            let slot = &mut self.slots[position % self.slots.len()];
+            unsafe { slot.event.get_mut().as_mut_ptr().drop_in_place() };
+            position = position.wrapping_add(1);
+        }
+    }
+}
+
+/// Bounded queue of contact events which is filled by physics and can be drained from
+/// other thread, see [`Physics::set_contact_event_queue`](crate::Physics::set_contact_event_queue).
+/// Queue is lock-free and cheap to clone, every clone refers to same queue. When queue
+/// is full, oldest event is dropped to make room for new one, so stalled consumer can't
+/// make queue grow without bounds.
+#[derive(Clone)]
+pub struct ContactEventQueue {
+    state: Arc<EventQueueState>,
+}
+
+impl Debug for ContactEventQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContactEventQueue")
+            .field("len", &self.len())
+            .field("capacity", &self.get_capacity())
+            .field("dropped", &self.get_dropped_count())
+            .finish()
+    }
+}
+
+impl ContactEventQueue {
+    /// Creates queue which holds at most given amount of events (at least one).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let slots = (0..capacity.max(2))
+            .map(|i| EventSlot {
+                sequence: AtomicUsize::new(i),
+                event: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        Self {
+            state: Arc::new(EventQueueState {
+                slots,
+                capacity,
+                read_position: AtomicUsize::new(0),
+                write_position: AtomicUsize::new(0),
+                dropped: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns event back if queue is full.
+    fn try_push(&self, event: ContactEvent) -> Result<(), ContactEvent> {
+        let state = &*self.state;
+        let mut position = state.write_position.load(Ordering::Relaxed);
+        loop {
+            let slot = &state.slots[position % state.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            if sequence == position {
+                match state.write_position.compare_exchange_weak(position, position.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        unsafe { (*slot.event.get()).as_mut_ptr().write(event) };
+                        slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => position = current,
+                }
+            } else if (sequence.wrapping_sub(position) as isize) < 0 {
+                // Slot still holds event written one lap ago.
+                return Err(event);
+            } else {
+                position = state.write_position.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(in crate) fn push(&self, mut event: ContactEvent) {
+        while self.len() >= self.state.capacity {
+            if self.pop().is_some() {
+                self.state.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        loop {
+            match self.try_push(event) {
+                Ok(()) => return,
+                Err(returned) => {
+                    event = returned;
+                    // Consumer may take oldest event first, then there is room anyway.
+                    if self.pop().is_some() {
+                        self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes and returns oldest event.
+    pub fn pop(&self) -> Option<ContactEvent> {
+        let state = &*self.state;
+        let mut position = state.read_position.load(Ordering::Relaxed);
+        loop {
+            let slot = &state.slots[position % state.slots.len()];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let ready = position.wrapping_add(1);
+            if sequence == ready {
+                match state.read_position.compare_exchange_weak(position, ready, Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        let event = unsafe { (*slot.event.get()).as_ptr().read() };
+                        slot.sequence.store(position.wrapping_add(state.slots.len()), Ordering::Release);
+                        return Some(event);
+                    }
+                    Err(current) => position = current,
+                }
+            } else if (sequence.wrapping_sub(ready) as isize) < 0 {
+                // Slot is not written yet.
+                return None;
+            } else {
+                position = state.read_position.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes and returns every event in order of generation.
+    pub fn drain(&self) -> Vec<ContactEvent> {
+        let mut events = Vec::with_capacity(self.len());
+        while let Some(event) = self.pop() {
+            events.push(event);
+        }
+        events
+    }
+
+    /// Returns amount of events in queue, it may be outdated already if queue is used
+    /// by other threads.
+    pub fn len(&self) -> usize {
+        let read = self.state.read_position.load(Ordering::Relaxed);
+        let write = self.state.write_position.load(Ordering::Relaxed);
+        (write.wrapping_sub(read) as isize).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_capacity(&self) -> usize {
+        self.state.capacity
+    }
+
+    /// Returns total amount of events that were dropped because queue was full.
+    pub fn get_dropped_count(&self) -> usize {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -1,11 +1,17 @@
 use rg3d_core::{
     math::{
         vec3::Vec3,
+        mat3::Mat3,
+        quat::Quat,
         self,
         aabb::AxisAlignedBoundingBox
     },
     visitor::{Visit, VisitResult, Visitor, VisitError},
 };
+use crate::{
+    float_ops::{Backend, FloatOps},
+    gjk_epa,
+};
 
 #[derive(Clone, Debug)]
 pub struct SphereShape {
@@ -16,6 +22,91 @@ pub trait CircumRadius {
     fn circumradius(&self) -> f32;
 }
 
+/// Position and orientation of a shape in world space.
+#[derive(Copy, Clone, Debug)]
+pub struct ShapeTransform {
+    pub position: Vec3,
+    /// Rotation matrix, columns are local axes of shape in world space.
+    pub basis: Mat3,
+}
+
+impl Default for ShapeTransform {
+    fn default() -> Self {
+        Self::from_position(Vec3::ZERO)
+    }
+}
+
+impl ShapeTransform {
+    pub fn new(position: Vec3, rotation: Quat) -> Self {
+        Self {
+            position,
+            basis: Mat3::from_quat(rotation),
+        }
+    }
+
+    pub fn from_position(position: Vec3) -> Self {
+        Self {
+            position,
+            basis: Mat3::identity(),
+        }
+    }
+
+    /// Transforms point from local space of shape to world space.
+    #[inline]
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.position + self.basis.transform_vector(point)
+    }
+
+    /// Transforms point from world space to local space of shape.
+    #[inline]
+    pub fn inverse_transform_point(&self, point: Vec3) -> Vec3 {
+        self.inverse_transform_direction(point - self.position)
+    }
+
+    /// Transforms direction from world space to local space of shape. Basis is
+    /// orthonormal, so its inverse is transpose.
+    #[inline]
+    pub fn inverse_transform_direction(&self, direction: Vec3) -> Vec3 {
+        Vec3::new(
+            Backend::dot(&self.basis.side(), &direction),
+            Backend::dot(&self.basis.up(), &direction),
+            Backend::dot(&self.basis.look(), &direction))
+    }
+}
+
+/// Anything that can provide support point for GJK/EPA. Support function is all that
+/// collision detection needs to know about a convex shape.
+pub trait SupportMapping {
+    /// Returns point of shape in its local space which is farthest along given local
+    /// space direction.
+    fn local_support_point(&self, direction: Vec3) -> Vec3;
+
+    /// Returns world space point of shape with given transform which is farthest along
+    /// given world space direction.
+    fn support_point(&self, transform: &ShapeTransform, direction: Vec3) -> Vec3 {
+        transform.transform_point(self.local_support_point(transform.inverse_transform_direction(direction)))
+    }
+}
+
+/// Shape inflated by given margin in every direction. Margin gives flat and thin shapes
+/// a volume, so their Minkowski difference never degenerates.
+pub struct Inflated<'a, S: ?Sized> {
+    pub shape: &'a S,
+    pub margin: f32,
+}
+
+impl<'a, S: SupportMapping + ?Sized> SupportMapping for Inflated<'a, S> {
+    fn local_support_point(&self, direction: Vec3) -> Vec3 {
+        let offset = Backend::normalize(direction).unwrap_or(Vec3::RIGHT).scale(self.margin);
+        self.shape.local_support_point(direction) + offset
+    }
+
+    fn support_point(&self, transform: &ShapeTransform, direction: Vec3) -> Vec3 {
+        let offset = Backend::normalize(direction).unwrap_or(Vec3::RIGHT).scale(self.margin);
+        self.shape.support_point(transform, direction) + offset
+    }
+}
+
 impl Visit for SphereShape {
     fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
         visitor.enter_region(name)?;
@@ -56,7 +147,7 @@ impl SphereShape {
     }
 
     pub fn get_farthest_point(&self, direction: Vec3) -> Vec3 {
-        let norm_dir = direction.normalized().unwrap_or_else(|| Vec3::new(1.0, 0.0, 0.0));
+        let norm_dir = Backend::normalize(direction).unwrap_or_else(|| Vec3::new(1.0, 0.0, 0.0));
         norm_dir.scale(self.radius)
     }
 }
@@ -111,6 +202,12 @@ impl TriangleShape {
     }
 }
 
+impl SupportMapping for TriangleShape {
+    fn local_support_point(&self, direction: Vec3) -> Vec3 {
+        self.get_farthest_point(direction)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BoxShape {
     half_extents: Vec3,
@@ -172,7 +269,7 @@ impl BoxShape {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct PointCloudShape {
     points: Vec<Vec3>
 }
@@ -194,14 +291,6 @@ impl Visit for PointCloudShape {
     }
 }
 
-impl Default for PointCloudShape {
-    fn default() -> Self {
-        Self {
-            points: Vec::new(),
-        }
-    }
-}
-
 impl PointCloudShape {
     pub fn new(points: Vec<Vec3>) -> Self {
         Self {
@@ -212,9 +301,15 @@ impl PointCloudShape {
     pub fn get_farthest_point(&self, direction: Vec3) -> Vec3 {
         math::get_farthest_point(&self.points, direction)
     }
+
+    /// Average of points, it always lies inside of hull.
+    fn center(&self) -> Vec3 {
+        let sum = self.points.iter().fold(Vec3::ZERO, |sum, point| sum + *point);
+        sum.scale(1.0 / self.points.len().max(1) as f32)
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Axis {
     X = 0,
     Y = 1,
@@ -305,6 +400,14 @@ impl CapsuleShape {
         self.axis
     }
 
+    fn axis_vector(&self) -> Vec3 {
+        match self.axis {
+            Axis::X => Vec3::RIGHT,
+            Axis::Y => Vec3::UP,
+            Axis::Z => Vec3::LOOK,
+        }
+    }
+
     pub fn get_cap_centers(&self) -> (Vec3, Vec3) {
         let half_height = self.height * 0.5;
 
@@ -325,7 +428,7 @@ impl CapsuleShape {
     }
 
     pub fn get_farthest_point(&self, direction: Vec3) -> Vec3 {
-        let norm_dir = direction.normalized().unwrap_or_else(|| Vec3::new(1.0, 0.0, 0.0));
+        let norm_dir = Backend::normalize(direction).unwrap_or_else(|| Vec3::new(1.0, 0.0, 0.0));
         let half_height = self.height * 0.5;
 
         let positive_cap_position = match self.axis {
@@ -334,11 +437,11 @@ impl CapsuleShape {
             Axis::Z => Vec3::new(0.0, 0.0, half_height),
         };
 
-        let mut max = -std::f32::MAX;
+        let mut max = -f32::MAX;
         let mut farthest = Vec3::ZERO;
         for cap_center in [positive_cap_position, -positive_cap_position].iter() {
             let vertex = *cap_center + norm_dir.scale(self.radius);
-            let dot = norm_dir.dot(&vertex);
+            let dot = Backend::dot(&norm_dir, &vertex);
             if dot > max {
                 max = dot;
                 farthest = vertex;
@@ -349,6 +452,133 @@ impl CapsuleShape {
     }
 }
 
+/// Infinite half-space below plane which goes through position of body and has given
+/// normal (in local space of body). Half-space has no bounded support point, so it is
+/// not handled by GJK, bodies are tested against its plane directly. Half-space bodies
+/// are never pushed by collisions and do not collide with static geometry, so they
+/// should be used as kinematic bodies - with zero gravity and velocity set explicitly.
+#[derive(Clone, Debug)]
+pub struct HalfSpaceShape {
+    normal: Vec3,
+}
+
+impl CircumRadius for HalfSpaceShape {
+    fn circumradius(&self) -> f32 {
+        f32::MAX
+    }
+}
+
+impl Default for HalfSpaceShape {
+    fn default() -> Self {
+        Self {
+            normal: Vec3::UP,
+        }
+    }
+}
+
+impl Visit for HalfSpaceShape {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.normal.visit("Normal", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl HalfSpaceShape {
+    /// Creates half-space with given normal, degenerated normal is replaced with up vector.
+    pub fn new(normal: Vec3) -> Self {
+        Self {
+            normal: Backend::normalize(normal).unwrap_or(Vec3::UP),
+        }
+    }
+
+    pub fn set_normal(&mut self, normal: Vec3) {
+        self.normal = Backend::normalize(normal).unwrap_or(Vec3::UP);
+    }
+
+    pub fn get_normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    /// Half-space has no farthest point, so point on its boundary plane is returned.
+    pub fn get_farthest_point(&self, _direction: Vec3) -> Vec3 {
+        Vec3::ZERO
+    }
+}
+
+/// Flat circular disk of zero thickness, centered at position of body and orthogonal
+/// to given normal (in local space of body). Disk has no volume, so it relies on
+/// collision margin of body, see [`ConvexShape::default_margin`].
+#[derive(Clone, Debug)]
+pub struct DiskShape {
+    radius: f32,
+    normal: Vec3,
+}
+
+impl CircumRadius for DiskShape {
+    fn circumradius(&self) -> f32 {
+        self.radius
+    }
+}
+
+impl Default for DiskShape {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            normal: Vec3::UP,
+        }
+    }
+}
+
+impl Visit for DiskShape {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.radius.visit("Radius", visitor)?;
+        self.normal.visit("Normal", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl DiskShape {
+    /// Creates disk with given radius and normal, degenerated normal is replaced with up vector.
+    pub fn new(radius: f32, normal: Vec3) -> Self {
+        Self {
+            radius: radius.abs(),
+            normal: Backend::normalize(normal).unwrap_or(Vec3::UP),
+        }
+    }
+
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius.abs();
+    }
+
+    pub fn get_radius(&self) -> f32 {
+        self.radius
+    }
+
+    pub fn set_normal(&mut self, normal: Vec3) {
+        self.normal = Backend::normalize(normal).unwrap_or(Vec3::UP);
+    }
+
+    pub fn get_normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    /// Returns point on rim of disk in direction projected onto plane of disk, or center
+    /// of disk if direction is parallel to normal.
+    pub fn get_farthest_point(&self, direction: Vec3) -> Vec3 {
+        let in_plane = direction - self.normal.scale(Backend::dot(&self.normal, &direction));
+        match Backend::normalize(in_plane) {
+            Some(in_plane) => in_plane.scale(self.radius),
+            None => Vec3::ZERO,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ConvexShape {
     Dummy,
@@ -357,6 +587,8 @@ pub enum ConvexShape {
     Capsule(CapsuleShape),
     Triangle(TriangleShape),
     PointCloud(PointCloudShape),
+    HalfSpace(HalfSpaceShape),
+    Disk(DiskShape),
 }
 
 macro_rules! define_is_as {
@@ -396,10 +628,18 @@ impl CircumRadius for ConvexShape {
             Self::Capsule(capsule) => capsule.circumradius(),
             Self::Triangle(triangle) => triangle.circumradius(),
             Self::PointCloud(point_cloud) => point_cloud.circumradius(),
+            Self::HalfSpace(half_space) => half_space.circumradius(),
+            Self::Disk(disk) => disk.circumradius(),
         }
     }
 }
 
+impl SupportMapping for ConvexShape {
+    fn local_support_point(&self, direction: Vec3) -> Vec3 {
+        self.get_farthest_point(Vec3::ZERO, direction)
+    }
+}
+
 impl ConvexShape {
     pub fn get_farthest_point(&self, position: Vec3, direction: Vec3) -> Vec3 {
         position + match self {
@@ -409,6 +649,394 @@ impl ConvexShape {
             Self::Capsule(capsule) => capsule.get_farthest_point(direction),
             Self::Triangle(triangle) => triangle.get_farthest_point(direction),
             Self::PointCloud(point_cloud) => point_cloud.get_farthest_point(direction),
+            Self::HalfSpace(half_space) => half_space.get_farthest_point(direction),
+            Self::Disk(disk) => disk.get_farthest_point(direction),
+        }
+    }
+
+    /// Computes world space axis-aligned bounding box of shape with given transform.
+    /// Uses support function along each axis, so box is exact for any convex shape.
+    /// Half-space has unbounded box which covers whole space.
+    pub fn compute_aabb(&self, transform: &ShapeTransform) -> AxisAlignedBoundingBox {
+        if self.is_half_space() {
+            return AxisAlignedBoundingBox::from_min_max(Vec3::new(-f32::MAX, -f32::MAX, -f32::MAX),
+                                                        Vec3::new(f32::MAX, f32::MAX, f32::MAX));
+        }
+
+        let mut aabb = AxisAlignedBoundingBox::default();
+        for axis in [Vec3::RIGHT, Vec3::UP, Vec3::LOOK].iter() {
+            aabb.add_point(self.support_point(transform, *axis));
+            aabb.add_point(self.support_point(transform, -*axis));
+        }
+        aabb
+    }
+
+    /// Approximates surface of shape with given transform by world space triangles. Vertices
+    /// of UV sphere with given amount of segments are mapped through support function, so
+    /// boxes are reproduced exactly while curved shapes are approximated. Triangles are
+    /// wound counterclockwise when looking from outside, zero-area triangles are skipped.
+    /// Half-space has infinite surface, so empty list is returned for it.
+    pub fn triangulate(&self, transform: &ShapeTransform, segments: usize) -> Vec<[Vec3; 3]> {
+        match self {
+            Self::Dummy | Self::HalfSpace(_) => return Vec::new(),
+            Self::Triangle(triangle) => return vec![[
+                transform.transform_point(triangle.vertices[0]),
+                transform.transform_point(triangle.vertices[1]),
+                transform.transform_point(triangle.vertices[2]),
+            ]],
+            _ => (),
+        }
+
+        let sectors = segments.max(3);
+        let rings = (segments / 2).max(2);
+        let mut points = Vec::with_capacity((rings + 1) * sectors);
+        for i in 0..=rings {
+            let theta = std::f32::consts::PI * i as f32 / rings as f32;
+            for j in 0..sectors {
+                let phi = 2.0 * std::f32::consts::PI * j as f32 / sectors as f32;
+                let direction = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                points.push(self.support_point(transform, direction));
+            }
+        }
+
+        let mut triangles = Vec::new();
+        let mut add_triangle = |a: Vec3, b: Vec3, c: Vec3| {
+            let normal = (b - a).cross(&(c - a));
+            if normal.sqr_len() <= f32::EPSILON * f32::EPSILON {
+                return;
+            }
+            if normal.dot(&(a - transform.position)) >= 0.0 {
+                triangles.push([a, b, c]);
+            } else {
+                triangles.push([a, c, b]);
+            }
+        };
+        for i in 0..rings {
+            for j in 0..sectors {
+                let next_j = (j + 1) % sectors;
+                let a = points[i * sectors + j];
+                let b = points[i * sectors + next_j];
+                let c = points[(i + 1) * sectors + j];
+                let d = points[(i + 1) * sectors + next_j];
+                add_triangle(a, c, d);
+                add_triangle(a, d, b);
+            }
+        }
+        triangles
+    }
+
+    /// Creates capsule which cap centers are at given world space points. Returns shape
+    /// together with position and rotation which place it between the points. Capsule
+    /// axis is Y in local space, coincident points give capsule of zero height.
+    pub fn capsule_between(a: Vec3, b: Vec3, radius: f32) -> (ConvexShape, Vec3, Quat) {
+        let position = (a + b).scale(0.5);
+        let (height, rotation) = match Backend::normalize(a - b) {
+            Some(direction) => {
+                let cos = direction.dot(&Vec3::UP).clamp(-1.0, 1.0);
+                let rotation = match Backend::normalize(Vec3::UP.cross(&direction)) {
+                    Some(axis) => Quat::from_axis_angle(axis, cos.acos()),
+                    // Direction is parallel to Y axis.
+                    None if cos > 0.0 => Quat::IDENTITY,
+                    None => Quat::from_axis_angle(Vec3::RIGHT, std::f32::consts::PI),
+                };
+                (Backend::sqrt((a - b).sqr_len()), rotation)
+            }
+            None => (0.0, Quat::IDENTITY),
+        };
+        (ConvexShape::Capsule(CapsuleShape::new(radius.abs(), height, Axis::Y)), position, rotation)
+    }
+
+    /// Returns world space point of shape which is farthest along given direction, shape
+    /// is placed at given position with given rotation. This is support function used
+    /// by GJK and EPA.
+    pub fn support(&self, direction: Vec3, (position, rotation): (Vec3, Quat)) -> Vec3 {
+        self.support_point(&ShapeTransform::new(position, rotation), direction)
+    }
+
+    /// Checks if shapes with given transforms intersect. This runs GJK only, so it is
+    /// much cheaper than computing penetration. Half-spaces are not supported here, see
+    /// [`RigidBody::intersects`](crate::rigid_body::RigidBody::intersects).
+    pub fn intersects(&self, transform: &ShapeTransform, other: &ConvexShape, other_transform: &ShapeTransform) -> bool {
+        gjk_epa::gjk_is_intersects(self, transform, other, other_transform).is_some()
+    }
+
+    /// Returns random point inside of shape in local space of shape. Given generator must
+    /// return uniformly distributed numbers in `[0; 1)` range. Spheres, boxes and capsules
+    /// are sampled analytically, point clouds use rejection sampling within their bounding
+    /// box. Flat shapes (triangles and disks) have no volume, so points on their surface
+    /// are returned. Dummy and half-space give origin.
+    pub fn random_point_inside<R>(&self, rng: &mut R) -> Vec3 where R: FnMut() -> f32 {
+        match self {
+            Self::Dummy | Self::HalfSpace(_) => Vec3::ZERO,
+            Self::PointCloud(point_cloud) if point_cloud.points.is_empty() => Vec3::ZERO,
+            Self::Sphere(sphere) => random_unit_vector(rng).scale(sphere.radius * rng().cbrt()),
+            Self::Box(box_shape) => {
+                let h = box_shape.half_extents;
+                Vec3::new(h.x * (2.0 * rng() - 1.0), h.y * (2.0 * rng() - 1.0), h.z * (2.0 * rng() - 1.0))
+            }
+            Self::Capsule(capsule) => {
+                // Volume of cylinder is pi*r^2*h, volume of both caps is 4/3*pi*r^3.
+                let (top, bottom) = capsule.get_cap_centers();
+                let axis = capsule.axis_vector();
+                let cylinder = capsule.height;
+                let caps = 4.0 / 3.0 * capsule.radius;
+                if rng() * (cylinder + caps) < cylinder {
+                    let (u, v) = perpendicular_basis(axis);
+                    let angle = 2.0 * std::f32::consts::PI * rng();
+                    let r = capsule.radius * Backend::sqrt(rng());
+                    axis.scale(capsule.height * (rng() - 0.5)) + u.scale(r * angle.cos()) + v.scale(r * angle.sin())
+                } else {
+                    let offset = random_unit_vector(rng).scale(capsule.radius * rng().cbrt());
+                    if offset.dot(&axis) >= 0.0 { top + offset } else { bottom + offset }
+                }
+            }
+            Self::Triangle(triangle) => random_point_in_triangle(&triangle.vertices, rng),
+            Self::Disk(disk) => random_point_in_disk(disk, rng),
+            Self::PointCloud(point_cloud) => {
+                let aabb = self.compute_aabb(&ShapeTransform::default());
+                let size = aabb.max - aabb.min;
+                for _ in 0..Self::MAX_REJECTION_ATTEMPTS {
+                    let point = aabb.min + Vec3::new(size.x * rng(), size.y * rng(), size.z * rng());
+                    if self.contains_local_point(point) {
+                        return point;
+                    }
+                }
+                // Hull is flat or degenerated, center is inside in any case.
+                point_cloud.center()
+            }
+        }
+    }
+
+    /// Returns random point on surface of shape in local space of shape, see
+    /// [`random_point_inside`](Self::random_point_inside) for requirements for generator.
+    /// Spheres, boxes, capsules, triangles and disks are sampled uniformly by area. Point
+    /// on surface of point cloud is found by casting random direction from center of
+    /// cloud, so it is not uniform for elongated hulls. Dummy and half-space give origin.
+    pub fn random_point_on_surface<R>(&self, rng: &mut R) -> Vec3 where R: FnMut() -> f32 {
+        match self {
+            Self::Dummy | Self::HalfSpace(_) => Vec3::ZERO,
+            Self::PointCloud(point_cloud) if point_cloud.points.is_empty() => Vec3::ZERO,
+            Self::Sphere(sphere) => random_unit_vector(rng).scale(sphere.radius),
+            Self::Box(box_shape) => {
+                let h = box_shape.half_extents;
+                // Pick face proportionally to its area.
+                let areas = [h.y * h.z, h.x * h.z, h.x * h.y];
+                let total = areas[0] + areas[1] + areas[2];
+                let side = if rng() < 0.5 { 1.0 } else { -1.0 };
+                let mut point = Vec3::new(h.x * (2.0 * rng() - 1.0), h.y * (2.0 * rng() - 1.0), h.z * (2.0 * rng() - 1.0));
+                let pick = rng() * total;
+                if pick < areas[0] {
+                    point.x = h.x * side;
+                } else if pick < areas[0] + areas[1] {
+                    point.y = h.y * side;
+                } else {
+                    point.z = h.z * side;
+                }
+                point
+            }
+            Self::Capsule(capsule) => {
+                // Area of cylinder is 2*pi*r*h, area of both caps is 4*pi*r^2.
+                let (top, bottom) = capsule.get_cap_centers();
+                let axis = capsule.axis_vector();
+                let cylinder = capsule.height;
+                let caps = 2.0 * capsule.radius;
+                if rng() * (cylinder + caps) < cylinder {
+                    let (u, v) = perpendicular_basis(axis);
+                    let angle = 2.0 * std::f32::consts::PI * rng();
+                    axis.scale(capsule.height * (rng() - 0.5)) +
+                        u.scale(capsule.radius * angle.cos()) + v.scale(capsule.radius * angle.sin())
+                } else {
+                    let offset = random_unit_vector(rng).scale(capsule.radius);
+                    if offset.dot(&axis) >= 0.0 { top + offset } else { bottom + offset }
+                }
+            }
+            Self::Triangle(triangle) => random_point_in_triangle(&triangle.vertices, rng),
+            Self::Disk(disk) => random_point_in_disk(disk, rng),
+            Self::PointCloud(point_cloud) => {
+                let center = point_cloud.center();
+                let aabb = self.compute_aabb(&ShapeTransform::default());
+                let direction = random_unit_vector(rng);
+                // Center is inside of hull and point at distance of diagonal is outside, find
+                // boundary between them by bisection.
+                let (mut inside, mut outside) = (0.0, Backend::sqrt((aabb.max - aabb.min).sqr_len()));
+                for _ in 0..Self::SURFACE_BISECTION_STEPS {
+                    let t = (inside + outside) * 0.5;
+                    if self.contains_local_point(center + direction.scale(t)) {
+                        inside = t;
+                    } else {
+                        outside = t;
+                    }
+                }
+                center + direction.scale(inside)
+            }
+        }
+    }
+
+    /// Returns inertia tensor of solid shape with given mass about origin of shape, in
+    /// local space of shape. Spheres, boxes, capsules and disks use textbook formulas,
+    /// triangle is treated as thin plate. Point cloud is integrated over a grid of points
+    /// inside of its hull (or treated as set of point masses if hull is flat). Dummy and
+    /// half-space have zero tensor.
+    pub fn inertia_tensor(&self, mass: f32) -> Mat3 {
+        match self {
+            Self::Dummy | Self::HalfSpace(_) => diagonal(Vec3::ZERO),
+            Self::Sphere(sphere) => {
+                let i = 0.4 * mass * sphere.radius * sphere.radius;
+                diagonal(Vec3::new(i, i, i))
+            }
+            Self::Box(box_shape) => {
+                let h = box_shape.half_extents;
+                let k = mass / 3.0;
+                diagonal(Vec3::new(k * (h.y * h.y + h.z * h.z), k * (h.x * h.x + h.z * h.z), k * (h.x * h.x + h.y * h.y)))
+            }
+            Self::Capsule(capsule) => {
+                let (r, h) = (capsule.radius, capsule.height);
+                // Mass is split between cylinder and two hemispheres by their volumes.
+                let cylinder_volume = std::f32::consts::PI * r * r * h;
+                let spheres_volume = 4.0 / 3.0 * std::f32::consts::PI * r * r * r;
+                let total_volume = cylinder_volume + spheres_volume;
+                if total_volume <= 0.0 {
+                    return diagonal(Vec3::ZERO);
+                }
+                let cylinder_mass = mass * cylinder_volume / total_volume;
+                let spheres_mass = mass * spheres_volume / total_volume;
+                let axial = cylinder_mass * r * r * 0.5 + spheres_mass * 0.4 * r * r;
+                let lateral = cylinder_mass * (h * h / 12.0 + r * r / 4.0) +
+                    spheres_mass * (0.4 * r * r + h * h / 4.0 + 3.0 * h * r / 8.0);
+                diagonal(match capsule.axis {
+                    Axis::X => Vec3::new(axial, lateral, lateral),
+                    Axis::Y => Vec3::new(lateral, axial, lateral),
+                    Axis::Z => Vec3::new(lateral, lateral, axial),
+                })
+            }
+            Self::Disk(disk) => {
+                // Thin disk: m*r^2/2 around normal, m*r^2/4 around any axis in its plane.
+                let k = mass * disk.radius * disk.radius / 4.0;
+                let mut f = outer(disk.normal, disk.normal);
+                for (i, value) in f.iter_mut().enumerate() {
+                    *value = k * (*value + if i % 4 == 0 { 1.0 } else { 0.0 });
+                }
+                Mat3 { f }
+            }
+            Self::Triangle(triangle) => {
+                // Second moment of triangle plate: m/12 * (sum of v*v^T + s*s^T), where s
+                // is sum of vertices.
+                let [a, b, c] = triangle.vertices;
+                let mut covariance = [0.0; 9];
+                for v in [a, b, c, a + b + c].iter() {
+                    for (sum, value) in covariance.iter_mut().zip(outer(*v, *v).iter()) {
+                        *sum += value * mass / 12.0;
+                    }
+                }
+                inertia_from_covariance(covariance)
+            }
+            Self::PointCloud(point_cloud) => {
+                if point_cloud.points.is_empty() {
+                    return diagonal(Vec3::ZERO);
+                }
+                let aabb = self.compute_aabb(&ShapeTransform::default());
+                let size = aabb.max - aabb.min;
+                let n = Self::INERTIA_GRID_RESOLUTION;
+                let mut samples = Vec::new();
+                for i in 0..n {
+                    for j in 0..n {
+                        for k in 0..n {
+                            let cell = Vec3::new((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32, (k as f32 + 0.5) / n as f32);
+                            let point = aabb.min + size * cell;
+                            if self.contains_local_point(point) {
+                                samples.push(point);
+                            }
+                        }
+                    }
+                }
+                // Flat hull has no volume, use points of cloud as point masses then.
+                if samples.is_empty() {
+                    samples.extend_from_slice(&point_cloud.points);
+                }
+                let point_mass = mass / samples.len() as f32;
+                let mut covariance = [0.0; 9];
+                for point in samples.iter() {
+                    for (sum, value) in covariance.iter_mut().zip(outer(*point, *point).iter()) {
+                        *sum += value * point_mass;
+                    }
+                }
+                inertia_from_covariance(covariance)
+            }
+        }
+    }
+
+    /// Amount of grid cells per axis used to integrate inertia of point cloud.
+    const INERTIA_GRID_RESOLUTION: usize = 16;
+
+    const MAX_REJECTION_ATTEMPTS: usize = 256;
+
+    const SURFACE_BISECTION_STEPS: usize = 24;
+
+    fn contains_local_point(&self, point: Vec3) -> bool {
+        gjk_epa::gjk_is_intersects(self, &ShapeTransform::default(), &ConvexShape::Dummy, &ShapeTransform::from_position(point)).is_some()
+    }
+
+    /// Returns default collision margin for shape. Only triangles and disks have non-zero
+    /// margin, since they have no volume.
+    pub fn default_margin(&self) -> f32 {
+        match self {
+            Self::Triangle(_) | Self::Disk(_) => 0.05,
+            _ => 0.0,
+        }
+    }
+
+    /// Scales shape uniformly by absolute value of given factor: radii, extents and
+    /// vertices are multiplied by it. Dummy and half-space are not affected.
+    pub fn scale_uniform(&mut self, factor: f32) {
+        let factor = factor.abs();
+        match self {
+            Self::Dummy | Self::HalfSpace(_) => {}
+            Self::Box(box_shape) => box_shape.half_extents = box_shape.half_extents.scale(factor),
+            Self::Sphere(sphere) => sphere.radius *= factor,
+            Self::Capsule(capsule) => {
+                capsule.radius *= factor;
+                capsule.height *= factor;
+            }
+            Self::Triangle(triangle) => {
+                for vertex in triangle.vertices.iter_mut() {
+                    *vertex = vertex.scale(factor);
+                }
+            }
+            Self::PointCloud(point_cloud) => {
+                for point in point_cloud.points.iter_mut() {
+                    *point = point.scale(factor);
+                }
+            }
+            Self::Disk(disk) => disk.radius *= factor,
+        }
+    }
+
+    /// Interpolates parameters (radii, extents, vertices, normals) of two shapes of same
+    /// kind, `t` is clamped to `[0; 1]` range. Returns `None` if shapes are of different
+    /// kinds or can't be blended: capsules along different axes or point clouds with
+    /// different amount of points. Inertia of body is computed from its shape, so it
+    /// follows interpolated shape once it is set with [`RigidBody::set_shape`](crate::rigid_body::RigidBody::set_shape).
+    pub fn lerp(a: &ConvexShape, b: &ConvexShape, t: f32) -> Option<ConvexShape> {
+        let t = t.clamp(0.0, 1.0);
+        let lerp_normal = |a: Vec3, b: Vec3| Backend::normalize(a.lerp(&b, t));
+        match (a, b) {
+            (Self::Dummy, Self::Dummy) => Some(Self::Dummy),
+            (Self::Box(a), Self::Box(b)) => Some(Self::Box(BoxShape::new(a.half_extents.lerp(&b.half_extents, t)))),
+            (Self::Sphere(a), Self::Sphere(b)) => Some(Self::Sphere(SphereShape::new(math::lerpf(a.radius, b.radius, t)))),
+            (Self::Capsule(a), Self::Capsule(b)) if a.axis == b.axis => Some(Self::Capsule(CapsuleShape::new(
+                math::lerpf(a.radius, b.radius, t), math::lerpf(a.height, b.height, t), a.axis))),
+            (Self::Triangle(a), Self::Triangle(b)) => Some(Self::Triangle(TriangleShape::new([
+                a.vertices[0].lerp(&b.vertices[0], t),
+                a.vertices[1].lerp(&b.vertices[1], t),
+                a.vertices[2].lerp(&b.vertices[2], t),
+            ]))),
+            (Self::PointCloud(a), Self::PointCloud(b)) if a.points.len() == b.points.len() => Some(Self::PointCloud(PointCloudShape::new(
+                a.points.iter().zip(b.points.iter()).map(|(a, b)| a.lerp(b, t)).collect()))),
+            (Self::HalfSpace(a), Self::HalfSpace(b)) => lerp_normal(a.normal, b.normal)
+                .map(|normal| Self::HalfSpace(HalfSpaceShape::new(normal))),
+            (Self::Disk(a), Self::Disk(b)) => lerp_normal(a.normal, b.normal)
+                .map(|normal| Self::Disk(DiskShape::new(math::lerpf(a.radius, b.radius, t), normal))),
+            _ => None,
         }
     }
 
@@ -420,6 +1048,8 @@ impl ConvexShape {
             Self::Capsule(_) => 3,
             Self::Triangle(_) => 4,
             Self::PointCloud(_) => 5,
+            Self::HalfSpace(_) => 6,
+            Self::Disk(_) => 7,
         }
     }
 
@@ -431,6 +1061,8 @@ impl ConvexShape {
             3 => Ok(Self::Capsule(Default::default())),
             4 => Ok(Self::Triangle(Default::default())),
             5 => Ok(Self::PointCloud(Default::default())),
+            6 => Ok(Self::HalfSpace(Default::default())),
+            7 => Ok(Self::Disk(Default::default())),
             _ => Err("Invalid shape id!".to_owned())
         }
     }
@@ -440,6 +1072,88 @@ impl ConvexShape {
     define_is_as!(is_sphere, as_sphere, as_sphere_mut, Sphere, SphereShape);
     define_is_as!(is_triangle, as_triangle, as_triangle_mut, Triangle, TriangleShape);
     define_is_as!(is_point_cloud, as_point_cloud, as_point_cloud_mut, PointCloud, PointCloudShape);
+    define_is_as!(is_half_space, as_half_space, as_half_space_mut, HalfSpace, HalfSpaceShape);
+    define_is_as!(is_disk, as_disk, as_disk_mut, Disk, DiskShape);
+
+    /// Returns description of problem if shape has invalid dimensions: non-positive or
+    /// non-finite radius, extents, and such.
+    #[cfg(feature = "debug-checks")]
+    pub(in crate) fn validate(&self) -> Result<(), String> {
+        let positive = |value: f32| value.is_finite() && value > 0.0;
+        let finite = |v: &Vec3| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        match self {
+            Self::Dummy => Ok(()),
+            Self::Sphere(sphere) if !positive(sphere.radius) =>
+                Err(format!("sphere has invalid radius {}", sphere.radius)),
+            Self::Box(box_shape) if !(positive(box_shape.half_extents.x) && positive(box_shape.half_extents.y) &&
+                positive(box_shape.half_extents.z)) =>
+                Err(format!("box has invalid half extents {:?}", box_shape.half_extents)),
+            Self::Capsule(capsule) if !(positive(capsule.radius) && capsule.height.is_finite() && capsule.height >= 0.0) =>
+                Err(format!("capsule has invalid radius {} or height {}", capsule.radius, capsule.height)),
+            Self::Triangle(triangle) if !triangle.vertices.iter().all(finite) =>
+                Err(format!("triangle has non-finite vertices {:?}", triangle.vertices)),
+            Self::PointCloud(point_cloud) if point_cloud.points.is_empty() || !point_cloud.points.iter().all(finite) =>
+                Err("point cloud is empty or has non-finite points".to_owned()),
+            Self::HalfSpace(half_space) if !finite(&half_space.normal) =>
+                Err(format!("half-space has non-finite normal {:?}", half_space.normal)),
+            Self::Disk(disk) if !positive(disk.radius) || !finite(&disk.normal) =>
+                Err(format!("disk has invalid radius {} or normal {:?}", disk.radius, disk.normal)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns uniformly distributed unit vector.
+fn random_unit_vector<R>(rng: &mut R) -> Vec3 where R: FnMut() -> f32 {
+    let z = 2.0 * rng() - 1.0;
+    let phi = 2.0 * std::f32::consts::PI * rng();
+    let r = Backend::sqrt((1.0 - z * z).max(0.0));
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Returns two unit vectors which are perpendicular to given unit vector and each other.
+fn perpendicular_basis(normal: Vec3) -> (Vec3, Vec3) {
+    let reference = if normal.x.abs() < 0.9 { Vec3::RIGHT } else { Vec3::UP };
+    let u = Backend::normalize(normal.cross(&reference)).unwrap_or(Vec3::LOOK);
+    (u, normal.cross(&u))
+}
+
+fn random_point_in_triangle<R>(vertices: &[Vec3; 3], rng: &mut R) -> Vec3 where R: FnMut() -> f32 {
+    let r1 = Backend::sqrt(rng());
+    let r2 = rng();
+    vertices[0].scale(1.0 - r1) + vertices[1].scale(r1 * (1.0 - r2)) + vertices[2].scale(r1 * r2)
+}
+
+fn diagonal(d: Vec3) -> Mat3 {
+    Mat3 {
+        f: [d.x, 0.0, 0.0,
+            0.0, d.y, 0.0,
+            0.0, 0.0, d.z]
+    }
+}
+
+fn outer(a: Vec3, b: Vec3) -> [f32; 9] {
+    [a.x * b.x, a.x * b.y, a.x * b.z,
+        a.y * b.x, a.y * b.y, a.y * b.z,
+        a.z * b.x, a.z * b.y, a.z * b.z]
+}
+
+/// Turns mass-weighted second moment `sum(m * p * p^T)` into inertia tensor
+/// `trace * I - covariance`.
+fn inertia_from_covariance(covariance: [f32; 9]) -> Mat3 {
+    let trace = covariance[0] + covariance[4] + covariance[8];
+    let mut f = [0.0; 9];
+    for (i, value) in f.iter_mut().enumerate() {
+        *value = if i % 4 == 0 { trace } else { 0.0 } - covariance[i];
+    }
+    Mat3 { f }
+}
+
+fn random_point_in_disk<R>(disk: &DiskShape, rng: &mut R) -> Vec3 where R: FnMut() -> f32 {
+    let (u, v) = perpendicular_basis(disk.normal);
+    let angle = 2.0 * std::f32::consts::PI * rng();
+    let r = disk.radius * Backend::sqrt(rng());
+    u.scale(r * angle.cos()) + v.scale(r * angle.sin())
 }
 
 impl Visit for ConvexShape {
@@ -451,8 +1165,124 @@ impl Visit for ConvexShape {
             Self::Capsule(capsule) => capsule.visit(name, visitor),
             Self::Triangle(triangle) => triangle.visit(name, visitor),
             Self::PointCloud(point_cloud) => point_cloud.visit(name, visitor),
+            Self::HalfSpace(half_space) => half_space.visit(name, visitor),
+            Self::Disk(disk) => disk.visit(name, visitor),
         }
     }
 }
 
+#[cfg(test)]
+mod test {
+    use rg3d_core::math::{vec3::Vec3, quat::Quat};
+    use crate::{
+        convex_shape::{ConvexShape, SphereShape, BoxShape, DiskShape, ShapeTransform},
+        gjk_epa::{self, test::count_epa_calls},
+        random::Prng,
+    };
+
+    #[test]
+    fn unit_sphere_support() {
+        let sphere = ConvexShape::Sphere(SphereShape::new(1.0));
+        let support = sphere.support(Vec3::new(1.0, 0.0, 0.0), (Vec3::ZERO, Quat::IDENTITY));
+        assert!((support - Vec3::new(1.0, 0.0, 0.0)).len() < 1e-6);
+
+        let rotation = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 1.0);
+        let support = sphere.support(Vec3::new(0.0, 2.0, 0.0), (Vec3::new(3.0, 0.0, 0.0), rotation));
+        assert!((support - Vec3::new(3.0, 1.0, 0.0)).len() < 1e-5);
+    }
+
+    #[test]
+    fn disk_support_is_on_rim() {
+        let disk = ConvexShape::Disk(DiskShape::new(2.0, Vec3::new(0.0, 1.0, 0.0)));
+        let support = disk.support(Vec3::new(1.0, 0.0, 0.0), (Vec3::ZERO, Quat::IDENTITY));
+        assert!((support - Vec3::new(2.0, 0.0, 0.0)).len() < 1e-6);
+
+        // Out of plane part of direction is ignored.
+        let support = disk.support(Vec3::new(0.0, 5.0, 1.0), (Vec3::ZERO, Quat::IDENTITY));
+        assert!((support - Vec3::new(0.0, 0.0, 2.0)).len() < 1e-6);
+    }
+
+    #[test]
+    fn capsule_between_points() {
+        let points = [
+            (Vec3::new(1.0, 2.0, 3.0), Vec3::new(-2.0, 0.5, 4.0)),
+            (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0)),
+            (Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+        ];
+        for (a, b) in points.iter() {
+            let (shape, position, rotation) = ConvexShape::capsule_between(*a, *b, 0.25);
+            let (cap_a, cap_b) = shape.as_capsule().get_cap_centers();
+            let transform = ShapeTransform::new(position, rotation);
+            assert!((transform.transform_point(cap_a) - *a).len() < 1e-4);
+            assert!((transform.transform_point(cap_b) - *b).len() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn random_points_inside_sphere() {
+        let mut rng = Prng::new(7);
+        let sphere = ConvexShape::Sphere(SphereShape::new(0.75));
+        for _ in 0..10000 {
+            let point = sphere.random_point_inside(&mut || rng.next_f32());
+            assert!(point.len() <= 0.75 + 1e-5);
+        }
+        for _ in 0..100 {
+            let point = sphere.random_point_on_surface(&mut || rng.next_f32());
+            assert!((point.len() - 0.75).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sphere_inertia_tensor() {
+        let tensor = ConvexShape::Sphere(SphereShape::new(0.5)).inertia_tensor(3.0);
+        let expected = 2.0 / 5.0 * 3.0 * 0.5 * 0.5;
+        for (i, value) in tensor.f.iter().enumerate() {
+            assert!((value - if i % 4 == 0 { expected } else { 0.0 }).abs() < 1e-6);
+        }
+    }
 
+    #[test]
+    fn box_inertia_tensor() {
+        // Full size of box is 2x4x6.
+        let tensor = ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 2.0, 3.0))).inertia_tensor(2.0);
+        let expected = [2.0 / 12.0 * (16.0 + 36.0), 2.0 / 12.0 * (4.0 + 36.0), 2.0 / 12.0 * (4.0 + 16.0)];
+        for (i, value) in tensor.f.iter().enumerate() {
+            let expected = if i % 4 == 0 { expected[i / 4] } else { 0.0 };
+            assert!((value - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn lerp_spheres() {
+        let a = ConvexShape::Sphere(SphereShape::new(1.0));
+        let b = ConvexShape::Sphere(SphereShape::new(3.0));
+        match ConvexShape::lerp(&a, &b, 0.5) {
+            Some(ConvexShape::Sphere(sphere)) => assert!((sphere.get_radius() - 2.0).abs() < 1e-6),
+            _ => panic!("spheres must blend into sphere"),
+        }
+        assert!(ConvexShape::lerp(&a, &ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 1.0, 1.0))), 0.5).is_none());
+    }
+
+    #[test]
+    fn intersects_does_not_run_epa() {
+        let a = ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5)));
+        let b = ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 0.25, 0.5)));
+        let rotation = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.3);
+        let transform = ShapeTransform::new(Vec3::ZERO, Quat::IDENTITY);
+        let overlapping = ShapeTransform::new(Vec3::new(0.9, 0.2, 0.0), rotation);
+        let separated = ShapeTransform::new(Vec3::new(2.5, 0.0, 0.0), rotation);
+
+        let calls = count_epa_calls(|| {
+            assert!(a.intersects(&transform, &b, &overlapping));
+            assert!(!a.intersects(&transform, &b, &separated));
+        });
+        assert_eq!(calls, 0);
+
+        // Counter does see EPA runs.
+        let calls = count_epa_calls(|| {
+            let simplex = gjk_epa::gjk_is_intersects(&a, &transform, &b, &overlapping).unwrap();
+            assert!(gjk_epa::epa_get_penetration_info(simplex, &a, &transform, &b, &overlapping).is_some());
+        });
+        assert_eq!(calls, 1);
+    }
+}
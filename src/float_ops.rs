@@ -0,0 +1,110 @@
+//! Floating point operations used by Verlet integrator and GJK/EPA.
+//!
+//! Physics uses only basic arithmetic and square root, which are correctly rounded
+//! by IEEE 754 and so give identical results on any conforming platform as long as
+//! evaluation order is fixed and no fused or extended precision operations are used.
+//! Verlet step, normalization, dot and cross products of GJK/EPA and support functions
+//! of shapes go through [`FloatOps`]. Backend is selected at compile time by features,
+//! so trait is not an extension point: implementing it outside of this crate has no
+//! effect on simulation. Both backends use native `f32`, they differ only in evaluation
+//! order: [`StrictOps`] (selected by `deterministic` feature or its older name
+//! `strict-math`, see [`Backend`]) spells order of every operation out instead of
+//! relying on math of rg3d-core. Neither of them is a soft-float implementation.
+//!
+//! Note that on 32-bit x86 targets without SSE2 intermediate results are kept in
+//! x87 extended precision, so lockstep simulation requires SSE2 to be enabled.
+
+use rg3d_core::math::vec3::Vec3;
+
+pub trait FloatOps {
+    fn sqrt(x: f32) -> f32;
+
+    /// Normalizes vector, returns `None` for degenerated vectors.
+    fn normalize(v: Vec3) -> Option<Vec3>;
+
+    fn dot(a: &Vec3, b: &Vec3) -> f32;
+
+    fn cross(a: &Vec3, b: &Vec3) -> Vec3;
+
+    /// Single axis of Verlet integration step with friction.
+    fn verlet(position: f32, last_position: f32, friction: f32, acceleration: f32, sqr_delta_time: f32) -> f32;
+}
+
+/// Backend that uses math of rg3d-core directly.
+pub struct NativeOps;
+
+impl FloatOps for NativeOps {
+    #[inline]
+    fn sqrt(x: f32) -> f32 {
+        x.sqrt()
+    }
+
+    #[inline]
+    fn normalize(v: Vec3) -> Option<Vec3> {
+        v.normalized()
+    }
+
+    #[inline]
+    fn dot(a: &Vec3, b: &Vec3) -> f32 {
+        a.dot(b)
+    }
+
+    #[inline]
+    fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+        a.cross(b)
+    }
+
+    #[inline]
+    fn verlet(position: f32, last_position: f32, friction: f32, acceleration: f32, sqr_delta_time: f32) -> f32 {
+        (2.0 - friction) * position - (1.0 - friction) * last_position + acceleration * sqr_delta_time
+    }
+}
+
+/// Backend with explicitly fixed evaluation order of every operation, sums are evaluated
+/// left to right. Normalization divides each component by length instead of multiplying
+/// by reciprocal, so every component is a single correctly rounded operation.
+pub struct StrictOps;
+
+impl FloatOps for StrictOps {
+    #[inline]
+    fn sqrt(x: f32) -> f32 {
+        x.sqrt()
+    }
+
+    #[inline]
+    fn normalize(v: Vec3) -> Option<Vec3> {
+        let len = Self::sqrt(Self::dot(&v, &v));
+        if len >= f32::EPSILON {
+            Some(Vec3::new(v.x / len, v.y / len, v.z / len))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn dot(a: &Vec3, b: &Vec3) -> f32 {
+        (a.x * b.x + a.y * b.y) + a.z * b.z
+    }
+
+    #[inline]
+    fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3::new(
+            a.y * b.z - a.z * b.y,
+            a.z * b.x - a.x * b.z,
+            a.x * b.y - a.y * b.x)
+    }
+
+    #[inline]
+    fn verlet(position: f32, last_position: f32, friction: f32, acceleration: f32, sqr_delta_time: f32) -> f32 {
+        let current = (2.0 - friction) * position;
+        let previous = (1.0 - friction) * last_position;
+        let acceleration = acceleration * sqr_delta_time;
+        (current - previous) + acceleration
+    }
+}
+
+#[cfg(not(feature = "strict-math"))]
+pub type Backend = NativeOps;
+
+#[cfg(feature = "strict-math")]
+pub type Backend = StrictOps;
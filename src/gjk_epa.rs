@@ -9,21 +9,28 @@
 ///
 /// Some ideas about contact point generation were taken from here
 /// https://www.gamedev.net/forums/topic/598678-contact-points-with-epa/
-
 use rg3d_core::{
     math::vec3::Vec3,
     math,
 };
-use crate::convex_shape::ConvexShape;
+use crate::{
+    convex_shape::{SupportMapping, ShapeTransform, TriangleShape},
+    float_ops::{Backend, FloatOps},
+};
 
 pub const GJK_MAX_ITERATIONS: usize = 64;
 pub const EPA_TOLERANCE: f32 = 0.0001;
 pub const EPA_MAX_ITERATIONS: usize = 64;
 pub const EPA_MAX_LOOSE_EDGES: usize = 32;
 pub const EPA_MAX_FACES: usize = 64;
+/// Amount of directions sampled by [`sampled_penetration_info`].
+pub const SAMPLED_PENETRATION_DIRECTIONS: usize = 128;
+/// Minimal cosine of angle between polytope face and penetration direction for face
+/// to be used as source of manifold points.
+pub const MANIFOLD_NORMAL_COS: f32 = 0.99;
 
 /// Vertex in space of Minkowski sum
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, Debug)]
 pub struct MinkowskiVertex {
     /// World space position of vertex of shape A. This position will be used
     /// to compute contact point in world space.
@@ -35,45 +42,18 @@ pub struct MinkowskiVertex {
     minkowski_dif: Vec3,
 }
 
-impl Default for MinkowskiVertex {
-    fn default() -> Self {
-        Self {
-            shape_a_world_space: Default::default(),
-            minkowski_dif: Default::default(),
-        }
-    }
-}
-
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, Debug)]
 struct PolytopeTriangle {
     vertices: [MinkowskiVertex; 3],
     normal: Vec3,
 }
 
-impl Default for PolytopeTriangle {
-    fn default() -> Self {
-        Self {
-            vertices: Default::default(),
-            normal: Default::default(),
-        }
-    }
-}
-
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default, Debug)]
 struct PolytopeEdge {
     begin: MinkowskiVertex,
     end: MinkowskiVertex,
 }
 
-impl Default for PolytopeEdge {
-    fn default() -> Self {
-        Self {
-            begin: Default::default(),
-            end: Default::default(),
-        }
-    }
-}
-
 impl PolytopeEdge {
     /// Returns true if edges are equal in combination like this:
     /// ab = ba
@@ -83,6 +63,24 @@ impl PolytopeEdge {
     }
 }
 
+/// Reusable storage for EPA polytope. Single instance can be used for any amount of
+/// penetration queries in a row, storage is cleared on each query but never freed.
+#[derive(Debug)]
+pub struct EpaScratch {
+    triangles: Vec<PolytopeTriangle>,
+    loose_edges: Vec<PolytopeEdge>,
+}
+
+impl Default for EpaScratch {
+    fn default() -> Self {
+        Self {
+            triangles: Vec::with_capacity(EPA_MAX_FACES),
+            loose_edges: Vec::with_capacity(EPA_MAX_LOOSE_EDGES),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Simplex {
     /// Vertices of simplex.
     /// Important: a is most recently added point (closest to origin)!
@@ -98,19 +96,19 @@ pub struct Simplex {
     rank: usize,
 }
 
-impl Default for Simplex {
-    fn default() -> Self {
-        Self {
-            a: Default::default(),
-            b: Default::default(),
-            c: Default::default(),
-            d: Default::default(),
-            rank: 0,
-        }
+impl Simplex {
+    /// Returns vertices of simplex in Minkowski difference space.
+    #[cfg(feature = "debug")]
+    pub fn points(&self) -> Vec<Vec3> {
+        // Tetrahedron uses every vertex, smaller simplex is kept in b, c, d.
+        let vertices = if self.rank >= 4 {
+            [self.a, self.b, self.c, self.d]
+        } else {
+            [self.b, self.c, self.d, self.a]
+        };
+        vertices[..self.rank.min(4)].iter().map(|vertex| vertex.minkowski_dif).collect()
     }
-}
 
-impl Simplex {
     fn update_triangle(&mut self) -> Vec3 {
         let ca = self.c.minkowski_dif - self.a.minkowski_dif;
         let ba = self.b.minkowski_dif - self.a.minkowski_dif;
@@ -120,23 +118,23 @@ impl Simplex {
 
         self.rank = 2;
 
-        let triangle_normal = ba.cross(&ca);
+        let triangle_normal = Backend::cross(&ba, &ca);
 
-        if ba.cross(&triangle_normal).is_same_direction_as(&ao) {
+        if Backend::dot(&Backend::cross(&ba, &triangle_normal), &ao) > 0.0 {
             // Closest to edge AB
             self.c = self.a;
-            return ba.cross(&ao).cross(&ba);
+            return Backend::cross(&Backend::cross(&ba, &ao), &ba);
         }
 
-        if triangle_normal.cross(&ca).is_same_direction_as(&ao) {
+        if Backend::dot(&Backend::cross(&triangle_normal, &ca), &ao) > 0.0 {
             // Closest to edge AC
             self.b = self.a;
-            return ca.cross(&ao).cross(&ca);
+            return Backend::cross(&Backend::cross(&ca, &ao), &ca);
         }
 
         self.rank = 3;
 
-        if triangle_normal.is_same_direction_as(&ao) {
+        if Backend::dot(&triangle_normal, &ao) > 0.0 {
             // Above triangle
             self.d = self.c;
             self.c = self.b;
@@ -166,8 +164,8 @@ impl Simplex {
         let ba = self.b.minkowski_dif - self.a.minkowski_dif;
         let ca = self.c.minkowski_dif - self.a.minkowski_dif;
 
-        let abc_normal = ba.cross(&ca);
-        if abc_normal.is_same_direction_as(&ao) {
+        let abc_normal = Backend::cross(&ba, &ca);
+        if Backend::dot(&abc_normal, &ao) > 0.0 {
             // In front of ABC
             self.d = self.c;
             self.c = self.b;
@@ -176,15 +174,15 @@ impl Simplex {
         }
 
         let da = self.d.minkowski_dif - self.a.minkowski_dif;
-        let acd_normal = ca.cross(&da);
-        if acd_normal.is_same_direction_as(&ao) {
+        let acd_normal = Backend::cross(&ca, &da);
+        if Backend::dot(&acd_normal, &ao) > 0.0 {
             // In front of ACD
             self.b = self.a;
             return Err(acd_normal);
         }
 
-        let adb_normal = da.cross(&ba);
-        if adb_normal.is_same_direction_as(&ao) {
+        let adb_normal = Backend::cross(&da, &ba);
+        if Backend::dot(&adb_normal, &ao) > 0.0 {
             // In front of ADB
             self.c = self.d;
             self.d = self.b;
@@ -197,10 +195,25 @@ impl Simplex {
     }
 }
 
-fn de_gjk_support(shape1: &ConvexShape, shape1_position: Vec3, shape2: &ConvexShape, shape2_position: Vec3, dir: &Vec3) -> MinkowskiVertex
+/// Scales vector so its largest component has unit length. Search directions become very
+/// short when origin is close to a simplex feature and support functions would treat them
+/// as degenerated, while direction itself is still meaningful.
+fn rescale_direction(dir: Vec3) -> Vec3 {
+    let max = dir.x.abs().max(dir.y.abs()).max(dir.z.abs());
+    if max > 0.0 {
+        dir.scale(1.0 / max)
+    } else {
+        dir
+    }
+}
+
+fn de_gjk_support<A, B>(shape1: &A, transform1: &ShapeTransform, shape2: &B, transform2: &ShapeTransform, dir: &Vec3) -> MinkowskiVertex
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
 {
-    let shape_a_world_space = shape1.get_farthest_point(shape1_position, *dir);
-    let b = shape2.get_farthest_point(shape2_position, -*dir);
+    let dir = rescale_direction(*dir);
+    let shape_a_world_space = shape1.support_point(transform1, dir);
+    let b = shape2.support_point(transform2, -dir);
 
     MinkowskiVertex {
         shape_a_world_space,
@@ -208,46 +221,71 @@ fn de_gjk_support(shape1: &ConvexShape, shape1_position: Vec3, shape2: &ConvexSh
     }
 }
 
-pub fn gjk_is_intersects(shape1: &ConvexShape, shape1_position: Vec3, shape2: &ConvexShape, shape2_position: Vec3) -> Option<Simplex> {
+pub fn gjk_is_intersects<A, B>(shape1: &A, transform1: &ShapeTransform, shape2: &B, transform2: &ShapeTransform) -> Option<Simplex>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    gjk_is_intersects_traced(shape1, transform1, shape2, transform2, |_| ())
+}
+
+/// GJK which passes simplex into given tracer after each change of it.
+fn gjk_is_intersects_traced<A, B, F>(shape1: &A, transform1: &ShapeTransform, shape2: &B, transform2: &ShapeTransform,
+                                     mut trace: F) -> Option<Simplex>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized,
+          F: FnMut(&Simplex)
+{
+    #[cfg(test)]
+    test::GJK_CALLS.with(|count| count.set(count.get() + 1));
+
     // This is good enough heuristic to choose initial search direction
-    let mut search_dir = shape1_position - shape2_position;
+    let mut search_dir = transform1.position - transform2.position;
 
     if search_dir.sqr_len() == 0.0 {
         search_dir.x = 1.0;
     }
 
     // Get initial point for simplex
-    let mut simplex = Simplex::default();
-    simplex.c = de_gjk_support(shape1, shape1_position, shape2, shape2_position, &search_dir);
+    let mut simplex = Simplex {
+        c: de_gjk_support(shape1, transform1, shape2, transform2, &search_dir),
+        ..Default::default()
+    };
     search_dir = -simplex.c.minkowski_dif; // Search in direction of origin
 
+    // Origin is support point - shapes are only touching.
+    if search_dir.sqr_len() == 0.0 {
+        return None;
+    }
+
     // Get second point for a line segment simplex
-    simplex.b = de_gjk_support(shape1, shape1_position, shape2, shape2_position, &search_dir);
+    simplex.b = de_gjk_support(shape1, transform1, shape2, transform2, &search_dir);
 
-    if !simplex.b.minkowski_dif.is_same_direction_as(&search_dir) {
+    if Backend::dot(&simplex.b.minkowski_dif, &search_dir) <= 0.0 {
         return None;
     }
 
     let cb = simplex.c.minkowski_dif - simplex.b.minkowski_dif;
 
     // Search perpendicular to line segment towards origin
-    search_dir = cb.cross(&(-simplex.b.minkowski_dif)).cross(&cb);
+    search_dir = Backend::cross(&Backend::cross(&cb, &(-simplex.b.minkowski_dif)), &cb);
 
-    // Origin is on this line segment - fix search direction.
-    if search_dir.sqr_len() == 0.0 {
+    // Origin is on this line segment (or so close to it that direction is just a noise of
+    // rounding errors) - fix search direction. Length of direction is |cb|^2 * distance.
+    if search_dir.sqr_len() <= f32::EPSILON * cb.sqr_len() * cb.sqr_len() {
         // Perpendicular with x-axis
-        search_dir = cb.cross(&Vec3::new(1.0, 0.0, 0.0));
+        search_dir = Backend::cross(&cb, &Vec3::new(1.0, 0.0, 0.0));
         if search_dir.sqr_len() == 0.0 {
             // Perpendicular with z-axis
-            search_dir = cb.cross(&Vec3::new(0.0, 0.0, -1.0));
+            search_dir = Backend::cross(&cb, &Vec3::new(0.0, 0.0, -1.0));
         }
     }
 
     simplex.rank = 2;
+    trace(&simplex);
     for _ in 0..GJK_MAX_ITERATIONS {
-        simplex.a = de_gjk_support(shape1, shape1_position, shape2, shape2_position, &search_dir);
+        simplex.a = de_gjk_support(shape1, transform1, shape2, transform2, &search_dir);
 
-        if !simplex.a.minkowski_dif.is_same_direction_as(&search_dir) {
+        if Backend::dot(&simplex.a.minkowski_dif, &search_dir) <= 0.0 {
             return None;
         }
 
@@ -256,20 +294,69 @@ pub fn gjk_is_intersects(shape1: &ConvexShape, shape1_position: Vec3, shape2: &C
             search_dir = simplex.update_triangle();
         } else {
             match simplex.update_tetrahedron() {
-                Ok(_) => return Some(simplex),
+                Ok(_) => {
+                    // Full tetrahedron encloses origin.
+                    simplex.rank = 4;
+                    trace(&simplex);
+                    return Some(simplex);
+                }
                 Err(dir) => search_dir = dir,
             }
         }
+        trace(&simplex);
+
+        // Origin lies exactly on simplex feature - shapes are touching but not penetrating.
+        if search_dir.sqr_len() == 0.0 {
+            return None;
+        }
     }
 
     // No convergence - no intersection
     None
 }
 
+/// Intermediate data of GJK and EPA, see [`gjk_is_intersects_debug`].
+#[cfg(feature = "debug")]
+#[derive(Clone, Debug, Default)]
+pub struct GjkDebugInfo {
+    /// Vertices of simplex (in Minkowski difference space) after each iteration of GJK.
+    /// Last simplex encloses origin if shapes intersect.
+    pub simplices: Vec<Vec<Vec3>>,
+    pub intersects: bool,
+    /// Faces of final EPA polytope in Minkowski difference space, empty if shapes do not
+    /// intersect.
+    pub polytope: Vec<[Vec3; 3]>,
+    /// Result of EPA, `None` if shapes do not intersect or EPA failed.
+    pub penetration_vector: Option<Vec3>,
+}
+
+/// Same as [`gjk_is_intersects`] followed by [`epa_get_penetration_info`], but records
+/// every simplex of GJK and final polytope of EPA, this is what GJK visualizer needs.
+/// Much slower than regular version, use it only to inspect suspicious results.
+#[cfg(feature = "debug")]
+pub fn gjk_is_intersects_debug<A, B>(shape1: &A, transform1: &ShapeTransform, shape2: &B, transform2: &ShapeTransform) -> GjkDebugInfo
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    let mut info = GjkDebugInfo::default();
+    let simplex = gjk_is_intersects_traced(shape1, transform1, shape2, transform2, |simplex| info.simplices.push(simplex.points()));
+    if let Some(simplex) = simplex {
+        info.intersects = true;
+        let mut scratch = EpaScratch::default();
+        info.penetration_vector = epa_get_penetration_info_with_scratch(simplex, shape1, transform1, shape2, transform2, &mut scratch)
+            .map(|penetration_info| penetration_info.penetration_vector);
+        // Scratch keeps polytope of last EPA iteration.
+        info.polytope = scratch.triangles.iter()
+            .map(|triangle| [triangle.vertices[0].minkowski_dif, triangle.vertices[1].minkowski_dif, triangle.vertices[2].minkowski_dif])
+            .collect();
+    }
+    info
+}
+
 fn epa_compute_contact_point(closest_triangle: PolytopeTriangle) -> Vec3 {
     // Project origin onto triangle's plane
     let proj = closest_triangle.normal.scale(
-        closest_triangle.vertices[0].minkowski_dif.dot(&closest_triangle.normal));
+        Backend::dot(&closest_triangle.vertices[0].minkowski_dif, &closest_triangle.normal));
 
     // Find barycentric coordinates of the projection in Minkowski difference space
     let (u, v, w) = math::get_barycentric_coords(
@@ -289,73 +376,69 @@ pub struct PenetrationInfo {
     pub contact_point: Vec3,
 }
 
-pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_position: Vec3,
-                                shape2: &ConvexShape, shape2_position: Vec3) -> Option<PenetrationInfo> {
-    let mut triangles = [PolytopeTriangle::default(); EPA_MAX_FACES];
-
-    // Reconstruct polytope from tetrahedron simplex points.
-
-    // ABC
-    let ba = simplex.b.minkowski_dif - simplex.a.minkowski_dif;
-    let ca = simplex.c.minkowski_dif - simplex.a.minkowski_dif;
-
-    let abc_normal = ba.cross(&ca);
-    if let Some(abc_normal) = abc_normal.normalized() {
-        triangles[0] = PolytopeTriangle {
-            vertices: [simplex.a, simplex.b, simplex.c],
-            normal: abc_normal,
-        };
-    } else {
-        return None;
-    }
-
-    // ACD
-    let da = simplex.d.minkowski_dif - simplex.a.minkowski_dif;
-
-    let acd_normal = ca.cross(&da);
-    if let Some(acd_normal) = acd_normal.normalized() {
-        triangles[1] = PolytopeTriangle {
-            vertices: [simplex.a, simplex.c, simplex.d],
-            normal: acd_normal,
-        };
-    } else {
-        return None;
-    }
+/// Convenience wrapper over [`epa_get_penetration_info_with_scratch`] which creates
+/// temporary polytope storage. Prefer scratch version when doing many queries.
+pub fn epa_get_penetration_info<A, B>(simplex: Simplex, shape1: &A, transform1: &ShapeTransform,
+                                      shape2: &B, transform2: &ShapeTransform) -> Option<PenetrationInfo>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    epa_get_penetration_info_with_scratch(simplex, shape1, transform1, shape2, transform2, &mut EpaScratch::default())
+}
 
-    // ADB
-    let adb_normal = da.cross(&ba);
-    if let Some(adb_normal) = adb_normal.normalized() {
-        triangles[2] = PolytopeTriangle {
-            vertices: [simplex.a, simplex.d, simplex.b],
-            normal: adb_normal,
-        };
-    } else {
-        return None;
-    }
+pub fn epa_get_penetration_info_with_scratch<A, B>(simplex: Simplex, shape1: &A, transform1: &ShapeTransform,
+                                                   shape2: &B, transform2: &ShapeTransform,
+                                                   scratch: &mut EpaScratch) -> Option<PenetrationInfo>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    #[cfg(test)]
+    test::EPA_CALLS.with(|count| count.set(count.get() + 1));
 
-    // BDC
-    let db = simplex.d.minkowski_dif - simplex.b.minkowski_dif;
-    let cb = simplex.c.minkowski_dif - simplex.b.minkowski_dif;
+    let EpaScratch { triangles, loose_edges } = scratch;
+    triangles.clear();
 
-    let bdc_normal = db.cross(&cb);
-    if let Some(bdc_normal) = bdc_normal.normalized() {
-        triangles[3] = PolytopeTriangle {
-            vertices: [simplex.b, simplex.d, simplex.c],
-            normal: bdc_normal,
-        };
-    } else {
-        return None;
+    // Reconstruct polytope from tetrahedron simplex points.
+    // Each face is given together with opposite vertex of tetrahedron.
+    let faces = [
+        ([simplex.a, simplex.b, simplex.c], simplex.d),
+        ([simplex.a, simplex.c, simplex.d], simplex.b),
+        ([simplex.a, simplex.d, simplex.b], simplex.c),
+        ([simplex.b, simplex.d, simplex.c], simplex.a)
+    ];
+    for ([a, b, c], opposite) in faces.iter() {
+        let ba = b.minkowski_dif - a.minkowski_dif;
+        let ca = c.minkowski_dif - a.minkowski_dif;
+
+        // Degenerated tetrahedron, nothing to expand.
+        let normal = Backend::normalize(Backend::cross(&ba, &ca))?;
+
+        // Normal of each face must look away from opposite vertex. Winding of simplex can
+        // be wrong for near-degenerated tetrahedrons, fix it to keep polytope consistent.
+        if Backend::dot(&(opposite.minkowski_dif - a.minkowski_dif), &normal) > 0.0 {
+            triangles.push(PolytopeTriangle {
+                vertices: [*b, *a, *c],
+                normal: -normal,
+            });
+        } else {
+            triangles.push(PolytopeTriangle {
+                vertices: [*a, *b, *c],
+                normal,
+            });
+        }
     }
 
-    let mut triangle_count = 4;
-    let mut closest_triangle_index = 0;
+    // Support distance along any direction is an upper bound of penetration depth and
+    // moving shape by it along that direction always resolves penetration. Best such
+    // bound will be used as result if polytope won't converge.
+    let mut best_bound: Option<(f32, PolytopeTriangle)> = None;
 
     for _ in 0..EPA_MAX_ITERATIONS {
         // Find triangle that is closest to origin
-        let mut min_dist = triangles[0].vertices[0].minkowski_dif.dot(&triangles[0].normal);
-        closest_triangle_index = 0;
-        for (i, triangle) in triangles.iter().enumerate().take(triangle_count).skip(1) {
-            let dist = triangle.vertices[0].minkowski_dif.dot(&triangle.normal);
+        let mut min_dist = Backend::dot(&triangles[0].vertices[0].minkowski_dif, &triangles[0].normal);
+        let mut closest_triangle_index = 0;
+        for (i, triangle) in triangles.iter().enumerate().skip(1) {
+            let dist = Backend::dot(&triangle.vertices[0].minkowski_dif, &triangle.normal);
             if dist < min_dist {
                 min_dist = dist;
                 closest_triangle_index = i;
@@ -365,9 +448,9 @@ pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_p
         // Search normal to triangle that's closest to origin
         let closest_triangle = triangles[closest_triangle_index];
         let search_dir = closest_triangle.normal;
-        let new_point = de_gjk_support(shape1, shape1_position, shape2, shape2_position, &search_dir);
+        let new_point = de_gjk_support(shape1, transform1, shape2, transform2, &search_dir);
 
-        let distance_to_origin = new_point.minkowski_dif.dot(&search_dir);
+        let distance_to_origin = Backend::dot(&new_point.minkowski_dif, &search_dir);
         if distance_to_origin - min_dist < EPA_TOLERANCE {
             return Some(PenetrationInfo {
                 penetration_vector: closest_triangle.normal.scale(distance_to_origin),
@@ -375,21 +458,25 @@ pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_p
             });
         }
 
+        if best_bound.is_none_or(|(bound, _)| distance_to_origin < bound) {
+            best_bound = Some((distance_to_origin, closest_triangle));
+        }
+
         // Loose edges after we remove triangle must give us list of edges we have
         // to stitch with new point to keep polytope convex.
-        let mut loose_edge_count = 0;
-        let mut loose_edges = [PolytopeEdge::default(); EPA_MAX_LOOSE_EDGES];
+        loose_edges.clear();
+        let mut overflow = false;
 
         // Find all triangles that are facing new point and remove them
         let mut i = 0;
-        while i < triangle_count {
+        while i < triangles.len() {
             let triangle = triangles[i];
 
             // If triangle i faces new point, remove it. Also search for adjacent edges of it
             // and remove them too to maintain loose edge list in correct state (see below).
             let to_new_point = new_point.minkowski_dif - triangle.vertices[0].minkowski_dif;
 
-            if triangle.normal.dot(&to_new_point) > 0.0 {
+            if Backend::dot(&triangle.normal, &to_new_point) > 0.0 {
                 for j in 0..3 {
                     let current_edge = PolytopeEdge {
                         begin: triangle.vertices[j],
@@ -398,8 +485,7 @@ pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_p
 
                     let mut already_in_list = false;
                     // Check if current edge is already in list
-                    let last_loose_edge = loose_edge_count;
-                    for k in 0..last_loose_edge {
+                    for k in 0..loose_edges.len() {
                         if loose_edges[k].eq_ccw(&current_edge) {
                             // If we found that current edge is same as other loose edge
                             // but in reverse order, then we need to replace the loose
@@ -459,8 +545,7 @@ pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_p
                             //       D
                             //
                             // Viola! We now have contour which we have to patch using new point.
-                            loose_edge_count -= 1;
-                            loose_edges.swap(k, loose_edge_count);
+                            loose_edges.swap_remove(k);
 
                             already_in_list = true;
                             break;
@@ -469,53 +554,566 @@ pub fn epa_get_penetration_info(simplex: Simplex, shape1: &ConvexShape, shape1_p
 
                     if !already_in_list {
                         // Add current edge to list
-                        if loose_edge_count >= EPA_MAX_LOOSE_EDGES {
+                        if loose_edges.len() >= EPA_MAX_LOOSE_EDGES {
+                            overflow = true;
                             break;
                         }
-                        loose_edges[loose_edge_count] = current_edge;
-                        loose_edge_count += 1;
+                        loose_edges.push(current_edge);
                     }
                 }
 
                 // Replace current triangle with last in list and discard last, so we will continue
                 // processing last triangle and then next to removed. This will effectively reduce
                 // amount of triangles in polytope.
-                triangle_count -= 1;
-                triangles.swap(i, triangle_count);
+                triangles.swap_remove(i);
             } else {
                 i += 1;
             }
         }
 
+        // Polytope can't be patched without holes, stop and use best result so far.
+        if overflow || triangles.len() + loose_edges.len() > EPA_MAX_FACES {
+            break;
+        }
+
         // Reconstruct polytope with new point added
-        for loose_edge in loose_edges[0..loose_edge_count].iter() {
-            if triangle_count >= EPA_MAX_FACES {
-                break;
-            }
-            let new_triangle = triangles.get_mut(triangle_count).unwrap();
-            new_triangle.vertices = [loose_edge.begin, loose_edge.end, new_point];
+        for loose_edge in loose_edges.iter() {
+            let mut new_triangle = PolytopeTriangle {
+                vertices: [loose_edge.begin, loose_edge.end, new_point],
+                normal: Vec3::ZERO,
+            };
 
             let edge_vector = loose_edge.begin.minkowski_dif - loose_edge.end.minkowski_dif;
             let begin_to_point = loose_edge.begin.minkowski_dif - new_point.minkowski_dif;
 
-            new_triangle.normal = edge_vector.cross(&begin_to_point).normalized().unwrap_or(Vec3::UP);
+            new_triangle.normal = Backend::normalize(Backend::cross(&edge_vector, &begin_to_point)).unwrap_or(Vec3::UP);
 
             // Check for wrong normal to maintain CCW winding
-            let bias = 2.0 * std::f32::EPSILON;
-            if new_triangle.vertices[0].minkowski_dif.dot(&new_triangle.normal) + bias < 0.0 {
+            let bias = 2.0 * f32::EPSILON;
+            if Backend::dot(&new_triangle.vertices[0].minkowski_dif, &new_triangle.normal) + bias < 0.0 {
                 // Swap vertices to make CCW winding and flip normal.
                 new_triangle.vertices.swap(0, 1);
                 new_triangle.normal = -new_triangle.normal;
             }
-            triangle_count += 1;
+            triangles.push(new_triangle);
         }
     }
 
-    // Return most recent closest point - this is still valid result but less accurate than
-    // if we would have total convergence.
-    let closest_triangle = triangles[closest_triangle_index];
+    // No convergence - return best bound, this is still valid result but less
+    // accurate than if we would have total convergence.
+    best_bound.map(|(distance, triangle)| PenetrationInfo {
+        penetration_vector: triangle.normal.scale(distance),
+        contact_point: epa_compute_contact_point(triangle),
+    })
+}
+
+/// Coarse penetration search which is used when EPA fails on near-degenerated shapes
+/// (usually exactly touching ones). Support distance of Minkowski difference is sampled
+/// along fixed set of directions (world axes, direction between shape centers and
+/// evenly distributed directions on unit sphere) and the smallest one is taken. Moving
+/// shape 1 by smallest support distance against its direction always resolves
+/// penetration, but depth may be overestimated and direction is quantized. Returns
+/// `None` only if shapes are separated along one of sampled directions.
+pub fn sampled_penetration_info<A, B>(shape1: &A, transform1: &ShapeTransform,
+                                      shape2: &B, transform2: &ShapeTransform) -> Option<PenetrationInfo>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    let (distance, direction) = sampled_min_support_distance(shape1, transform1, shape2, transform2);
+    if distance < 0.0 {
+        return None;
+    }
+
     Some(PenetrationInfo {
-        penetration_vector: closest_triangle.normal.scale(closest_triangle.vertices[0].minkowski_dif.dot(&closest_triangle.normal)),
-        contact_point: epa_compute_contact_point(closest_triangle),
+        penetration_vector: direction.scale(distance),
+        contact_point: shape1.support_point(transform1, direction),
+    })
+}
+
+/// Support distance of Minkowski difference of shapes along given unit direction.
+fn minkowski_support_distance<A, B>(shape1: &A, transform1: &ShapeTransform,
+                                    shape2: &B, transform2: &ShapeTransform, direction: Vec3) -> f32
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    Backend::dot(&(shape1.support_point(transform1, direction) - shape2.support_point(transform2, -direction)), &direction)
+}
+
+/// Returns smallest support distance of Minkowski difference among sampled directions
+/// (see [`sampled_penetration_info`]) together with its direction.
+fn sampled_min_support_distance<A, B>(shape1: &A, transform1: &ShapeTransform,
+                                      shape2: &B, transform2: &ShapeTransform) -> (f32, Vec3)
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    let mut best = (f32::MAX, Vec3::UP);
+    let mut check_direction = |direction: Vec3| {
+        let distance = minkowski_support_distance(shape1, transform1, shape2, transform2, direction);
+        if distance < best.0 {
+            best = (distance, direction);
+        }
+    };
+
+    for axis in [Vec3::RIGHT, Vec3::UP, Vec3::LOOK].iter() {
+        check_direction(*axis);
+        check_direction(-*axis);
+    }
+    if let Some(direction) = Backend::normalize(transform1.position - transform2.position) {
+        check_direction(direction);
+    }
+    // Fibonacci sphere.
+    let golden_angle = std::f32::consts::PI * (3.0 - Backend::sqrt(5.0));
+    for i in 0..SAMPLED_PENETRATION_DIRECTIONS {
+        let y = 1.0 - 2.0 * (i as f32 + 0.5) / SAMPLED_PENETRATION_DIRECTIONS as f32;
+        let r = Backend::sqrt(1.0 - y * y);
+        let angle = golden_angle * i as f32;
+        check_direction(Vec3::new(r * angle.cos(), y, r * angle.sin()));
+    }
+
+    best
+}
+
+/// Returns distance between shapes, or zero if shapes intersect. Smallest support distance
+/// of Minkowski difference is searched by sampling directions and refining best of them by
+/// local search. Result never exceeds true distance, so it is safe to move shapes towards
+/// each other by it (this is what conservative advancement needs).
+pub fn separation_distance<A, B>(shape1: &A, transform1: &ShapeTransform,
+                                 shape2: &B, transform2: &ShapeTransform) -> f32
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    let (mut best, mut best_direction) = sampled_min_support_distance(shape1, transform1, shape2, transform2);
+
+    let mut step = 0.25;
+    while step > 1.0e-4 && best < 0.0 {
+        let reference = if best_direction.x.abs() < 0.9 { Vec3::RIGHT } else { Vec3::UP };
+        let tangent = Backend::normalize(Backend::cross(&best_direction, &reference)).unwrap_or(Vec3::LOOK);
+        let bitangent = Backend::cross(&best_direction, &tangent);
+        let mut improved = false;
+        for offset in [tangent, -tangent, bitangent, -bitangent].iter() {
+            if let Some(direction) = Backend::normalize(best_direction + offset.scale(step)) {
+                let distance = minkowski_support_distance(shape1, transform1, shape2, transform2, direction);
+                if distance < best {
+                    best = distance;
+                    best_direction = direction;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            step *= 0.5;
+        }
+    }
+
+    (-best).max(0.0)
+}
+
+pub struct ContactManifold {
+    pub penetration_vector: Vec3,
+    /// World space contact points on shape A, deepest point goes first.
+    pub points: Vec<Vec3>,
+}
+
+/// Runs EPA and gathers contact manifold from faces of final polytope: every face which
+/// is parallel to deepest one and is within given tolerance from it contributes its
+/// vertices (on shape A) to manifold. Points closer to each other than tolerance are
+/// merged. Manifold is not clipped, so it is only a seed for stable stacking.
+pub fn epa_get_manifold<A, B>(simplex: Simplex, shape1: &A, transform1: &ShapeTransform,
+                              shape2: &B, transform2: &ShapeTransform,
+                              tolerance: f32, scratch: &mut EpaScratch) -> Option<ContactManifold>
+    where A: SupportMapping + ?Sized,
+          B: SupportMapping + ?Sized
+{
+    let info = epa_get_penetration_info_with_scratch(simplex, shape1, transform1, shape2, transform2, scratch)?;
+    let depth = info.penetration_vector.len();
+    let direction = Backend::normalize(info.penetration_vector)?;
+
+    let mut points = vec![info.contact_point];
+    for triangle in scratch.triangles.iter() {
+        let distance = Backend::dot(&triangle.vertices[0].minkowski_dif, &triangle.normal);
+        if distance - depth > tolerance || Backend::dot(&triangle.normal, &direction) < MANIFOLD_NORMAL_COS {
+            continue;
+        }
+
+        for vertex in triangle.vertices.iter() {
+            let point = vertex.shape_a_world_space;
+            if points.iter().all(|p| p.sqr_distance(&point) > tolerance * tolerance) {
+                points.push(point);
+            }
+        }
+    }
+
+    Some(ContactManifold {
+        penetration_vector: info.penetration_vector,
+        points,
     })
-}
\ No newline at end of file
+}
+
+/// Penetration info of two triangles with given transforms. Triangles have zero
+/// thickness, so they penetrate only when they cross each other. Coplanar triangles
+/// have flat Minkowski difference, which has no volume to expand, so they are never
+/// reported as intersecting.
+pub fn triangle_triangle_penetration(a: &TriangleShape, a_transform: &ShapeTransform,
+                                     b: &TriangleShape, b_transform: &ShapeTransform,
+                                     scratch: &mut EpaScratch) -> Option<PenetrationInfo> {
+    let simplex = gjk_is_intersects(a, a_transform, b, b_transform)?;
+    epa_get_penetration_info_with_scratch(simplex, a, a_transform, b, b_transform, scratch)
+}
+
+#[cfg(test)]
+pub(in crate) mod test {
+    use rg3d_core::math::{vec3::Vec3, quat::Quat};
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+    use crate::{
+        convex_shape::{ConvexShape, SphereShape, BoxShape, CapsuleShape, DiskShape, TriangleShape, Axis, ShapeTransform, Inflated},
+        gjk_epa::{gjk_is_intersects, epa_get_penetration_info, epa_get_penetration_info_with_scratch, epa_get_manifold,
+                  sampled_penetration_info, EpaScratch},
+    };
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+        pub(in crate) static EPA_CALLS: Cell<usize> = const { Cell::new(0) };
+        pub(in crate) static GJK_CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Counts allocations per thread, so tests running in parallel do not disturb each
+    /// other.
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Returns amount of allocations done by given function on current thread.
+    pub(in crate) fn count_allocations<F: FnOnce()>(func: F) -> usize {
+        let before = ALLOCATIONS.with(|count| count.get());
+        func();
+        ALLOCATIONS.with(|count| count.get()) - before
+    }
+
+    /// Returns amount of EPA runs done by given function on current thread.
+    pub(in crate) fn count_epa_calls<F: FnOnce()>(func: F) -> usize {
+        let before = EPA_CALLS.with(|count| count.get());
+        func();
+        EPA_CALLS.with(|count| count.get()) - before
+    }
+
+    /// Returns amount of GJK runs done by given function on current thread.
+    pub(in crate) fn count_gjk_calls<F: FnOnce()>(func: F) -> usize {
+        let before = GJK_CALLS.with(|count| count.get());
+        func();
+        GJK_CALLS.with(|count| count.get()) - before
+    }
+
+    /// Tiny deterministic generator, so failures are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next_f32(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * self.next_f32()
+        }
+
+        fn vec3(&mut self, extent: f32) -> Vec3 {
+            Vec3::new(self.range(-extent, extent), self.range(-extent, extent), self.range(-extent, extent))
+        }
+
+        fn shape(&mut self) -> ConvexShape {
+            match (self.next_f32() * 3.0) as u32 {
+                0 => ConvexShape::Sphere(SphereShape::new(self.range(0.1, 1.0))),
+                1 => ConvexShape::Box(BoxShape::new(Vec3::new(self.range(0.1, 1.0), self.range(0.1, 1.0), self.range(0.1, 1.0)))),
+                _ => ConvexShape::Capsule(CapsuleShape::new(self.range(0.1, 0.5), self.range(0.1, 1.0), Axis::Y)),
+            }
+        }
+    }
+
+    /// Evenly distributed directions on unit sphere (Fibonacci lattice).
+    fn sample_directions(count: usize) -> Vec<Vec3> {
+        let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        (0..count)
+            .map(|i| {
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let r = (1.0 - y * y).sqrt();
+                let theta = golden_angle * i as f32;
+                Vec3::new(r * theta.cos(), y, r * theta.sin())
+            })
+            .collect()
+    }
+
+    /// Support function of Minkowski difference A - B.
+    fn minkowski_support(a: &ConvexShape, a_pos: Vec3, b: &ConvexShape, b_pos: Vec3, dir: Vec3) -> f32 {
+        (a.get_farthest_point(a_pos, dir) - b.get_farthest_point(b_pos, -dir)).dot(&dir)
+    }
+
+    /// Brute-force penetration depth: minimum of support function of Minkowski
+    /// difference over sampled directions, refined by local search around best
+    /// sample. Negative if shapes are separated.
+    fn sampled_penetration_depth(a: &ConvexShape, a_pos: Vec3, b: &ConvexShape, b_pos: Vec3, directions: &[Vec3]) -> f32 {
+        let support = |dir: Vec3| minkowski_support(a, a_pos, b, b_pos, dir);
+
+        let mut best_dir = directions[0];
+        let mut best = support(best_dir);
+        for dir in directions.iter().skip(1) {
+            let value = support(*dir);
+            if value < best {
+                best = value;
+                best_dir = *dir;
+            }
+        }
+
+        let mut step = 0.05;
+        while step > 1.0e-5 {
+            let tangent = best_dir.cross(&Vec3::RIGHT).normalized()
+                .unwrap_or_else(|| best_dir.cross(&Vec3::UP).normalized().unwrap());
+            let bitangent = best_dir.cross(&tangent);
+            let mut improved = false;
+            for offset in [tangent, -tangent, bitangent, -bitangent].iter() {
+                let dir = (best_dir + offset.scale(step)).normalized().unwrap();
+                let value = support(dir);
+                if value < best {
+                    best = value;
+                    best_dir = dir;
+                    improved = true;
+                }
+            }
+            if !improved {
+                step *= 0.5;
+            }
+        }
+
+        best
+    }
+
+    #[test]
+    fn sphere_support_point() {
+        let sphere = ConvexShape::Sphere(SphereShape::new(1.0));
+        assert_eq!(sphere.get_farthest_point(Vec3::ZERO, Vec3::RIGHT), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(sphere.get_farthest_point(Vec3::UP, Vec3::new(0.0, -5.0, 0.0)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn box_support_point() {
+        let box_shape = ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(box_shape.get_farthest_point(Vec3::ZERO, Vec3::new(1.0, -1.0, 1.0)), Vec3::new(1.0, -2.0, 3.0));
+    }
+
+    #[test]
+    fn capsule_support_point() {
+        let capsule = ConvexShape::Capsule(CapsuleShape::new(0.5, 2.0, Axis::Y));
+        assert_eq!(capsule.get_farthest_point(Vec3::ZERO, Vec3::UP), Vec3::new(0.0, 1.5, 0.0));
+        assert_eq!(capsule.get_farthest_point(Vec3::ZERO, -Vec3::UP), Vec3::new(0.0, -1.5, 0.0));
+    }
+
+    #[test]
+    fn zero_length_direction_is_finite() {
+        let shapes = [
+            ConvexShape::Sphere(SphereShape::new(1.0)),
+            ConvexShape::Box(BoxShape::new(Vec3::UNIT)),
+            ConvexShape::Capsule(CapsuleShape::new(0.5, 1.0, Axis::Z)),
+        ];
+        for shape in shapes.iter() {
+            let point = shape.get_farthest_point(Vec3::ZERO, Vec3::ZERO);
+            assert!(point.x.is_finite() && point.y.is_finite() && point.z.is_finite());
+        }
+    }
+
+    #[test]
+    fn coincident_shapes_intersect() {
+        let a = ConvexShape::Box(BoxShape::new(Vec3::UNIT));
+        let b = ConvexShape::Box(BoxShape::new(Vec3::UNIT));
+        let transform = ShapeTransform::default();
+        let simplex = gjk_is_intersects(&a, &transform, &b, &transform).unwrap();
+        assert!(epa_get_penetration_info(simplex, &a, &transform, &b, &transform).is_some());
+    }
+
+    #[test]
+    fn rotated_box_touches_by_corner() {
+        let cube = ConvexShape::Box(BoxShape::new(Vec3::UNIT));
+        let point = ConvexShape::Sphere(SphereShape::new(0.05));
+        let point_transform = ShapeTransform::from_position(Vec3::new(0.0, -1.3, 0.0));
+
+        // Face of axis-aligned box is at -1.0, so it is too far from point.
+        assert!(gjk_is_intersects(&cube, &ShapeTransform::default(), &point, &point_transform).is_none());
+
+        // Box rotated by 45 degrees around Z has its corner at -sqrt(2).
+        let rotated = ShapeTransform::new(Vec3::ZERO, Quat::from_axis_angle(Vec3::LOOK, std::f32::consts::FRAC_PI_4));
+        let simplex = gjk_is_intersects(&cube, &rotated, &point, &point_transform).unwrap();
+        let info = epa_get_penetration_info(simplex, &cube, &rotated, &point, &point_transform).unwrap();
+        assert!(info.contact_point.x.abs() < 0.1);
+        assert!((info.contact_point.y + 1.3).abs() < 0.15);
+    }
+
+    #[test]
+    fn box_on_box_manifold() {
+        let small = ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5)));
+        let slab = ConvexShape::Box(BoxShape::new(Vec3::new(2.0, 0.5, 2.0)));
+        let small_transform = ShapeTransform::from_position(Vec3::new(0.1, 0.95, 0.2));
+        let slab_transform = ShapeTransform::default();
+
+        let simplex = gjk_is_intersects(&small, &small_transform, &slab, &slab_transform).unwrap();
+        let manifold = epa_get_manifold(simplex, &small, &small_transform, &slab, &slab_transform,
+                                        0.01, &mut EpaScratch::default()).unwrap();
+
+        assert!((manifold.penetration_vector.len() - 0.05).abs() < 0.001);
+        assert!(manifold.points.len() > 2);
+        for point in manifold.points.iter() {
+            assert!((point.y - 0.45).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn scratch_penetration_does_not_allocate() {
+        let mut rng = Lcg(0xA110C);
+        let pairs: Vec<(ConvexShape, ShapeTransform, ConvexShape, ShapeTransform)> = (0..1000)
+            .map(|_| (rng.shape(), ShapeTransform::from_position(rng.vec3(0.5)), rng.shape(), ShapeTransform::from_position(rng.vec3(0.5))))
+            .collect();
+
+        // Whole narrow phase with scratch (GJK and EPA) works without allocations.
+        let mut scratch = EpaScratch::default();
+        let mut found = 0;
+        let allocations = count_allocations(|| {
+            for (a, a_transform, b, b_transform) in pairs.iter() {
+                if let Some(simplex) = gjk_is_intersects(a, a_transform, b, b_transform) {
+                    if epa_get_penetration_info_with_scratch(simplex, a, a_transform, b, b_transform, &mut scratch).is_some() {
+                        found += 1;
+                    }
+                }
+            }
+        });
+        assert_eq!(allocations, 0);
+        assert!(found > 100);
+
+        // Convenience version allocates storage on each call.
+        let allocations = count_allocations(|| {
+            for (a, a_transform, b, b_transform) in pairs.iter() {
+                if let Some(simplex) = gjk_is_intersects(a, a_transform, b, b_transform) {
+                    let _ = epa_get_penetration_info(simplex, a, a_transform, b, b_transform);
+                }
+            }
+        });
+        assert!(allocations >= found);
+    }
+
+    #[test]
+    fn fuzz_penetration_against_brute_force() {
+        let directions = sample_directions(4096);
+        let mut rng = Lcg(0x5EED);
+        let mut checked = 0;
+
+        for _ in 0..2000 {
+            let a = rng.shape();
+            let b = rng.shape();
+            let a_pos = rng.vec3(1.5);
+            let b_pos = rng.vec3(1.5);
+            let a_transform = ShapeTransform::from_position(a_pos);
+            let b_transform = ShapeTransform::from_position(b_pos);
+
+            let expected = sampled_penetration_depth(&a, a_pos, &b, b_pos, &directions);
+
+            // Skip pairs which are almost touching - sampled depth is not precise enough
+            // to decide whether they intersect.
+            if expected.abs() < 0.02 {
+                continue;
+            }
+
+            let simplex = gjk_is_intersects(&a, &a_transform, &b, &b_transform);
+            assert_eq!(simplex.is_some(), expected > 0.0,
+                       "GJK mismatch: {:?} at {:?} vs {:?} at {:?}, depth {}", a, a_pos, b, b_pos, expected);
+
+            if let Some(simplex) = simplex {
+                let info = epa_get_penetration_info(simplex, &a, &a_transform, &b, &b_transform)
+                    .unwrap_or_else(|| panic!("EPA failed: {:?} at {:?} vs {:?} at {:?}", a, a_pos, b, b_pos));
+                let depth = info.penetration_vector.len();
+                assert!(depth.is_finite());
+                assert!((depth - expected).abs() < 0.01 + expected * 0.02,
+                        "EPA depth {} != {}: {:?} at {:?} vs {:?} at {:?}", depth, expected, a, a_pos, b, b_pos);
+
+                // Moving shape A back by penetration vector must resolve intersection.
+                let resolved = sampled_penetration_depth(&a, a_pos - info.penetration_vector, &b, b_pos, &directions);
+                assert!(resolved < 0.01, "penetration left: {}", resolved);
+
+                checked += 1;
+            }
+        }
+
+        assert!(checked > 100);
+    }
+
+    #[test]
+    fn sphere_on_disk() {
+        let disk = ConvexShape::Disk(DiskShape::new(2.0, Vec3::new(0.0, 1.0, 0.0)));
+        let inflated_disk = Inflated { shape: &disk, margin: disk.default_margin() };
+        let disk_transform = ShapeTransform::default();
+        let sphere = ConvexShape::Sphere(SphereShape::new(0.5));
+        let sphere_transform = ShapeTransform::from_position(Vec3::new(0.3, 0.5, 0.2));
+
+        let simplex = gjk_is_intersects(&inflated_disk, &disk_transform, &sphere, &sphere_transform).unwrap();
+        let info = epa_get_penetration_info(simplex, &inflated_disk, &disk_transform, &sphere, &sphere_transform).unwrap();
+        assert!(info.penetration_vector.x.abs() < 1e-3 && info.penetration_vector.z.abs() < 1e-3);
+        assert!((info.penetration_vector.len() - disk.default_margin()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sampled_fallback_on_degenerated_pair() {
+        // Sphere exactly touches vertex of triangle, EPA fails here.
+        let triangle = TriangleShape::new([
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 1.0)
+        ]);
+        let sphere = ConvexShape::Sphere(SphereShape::new(0.5));
+        let triangle_transform = ShapeTransform::default();
+        let sphere_transform = ShapeTransform::from_position(Vec3::new(-1.0, 0.5, -1.0));
+        let simplex = gjk_is_intersects(&triangle, &triangle_transform, &sphere, &sphere_transform).unwrap();
+        assert!(epa_get_penetration_info(simplex, &triangle, &triangle_transform, &sphere, &sphere_transform).is_none());
+
+        let info = sampled_penetration_info(&triangle, &triangle_transform, &sphere, &sphere_transform).unwrap();
+        assert!(info.penetration_vector.len() < 0.01);
+
+        // Fallback also works for real penetration.
+        let sphere_transform = ShapeTransform::from_position(Vec3::new(0.0, 0.3, 0.0));
+        let info = sampled_penetration_info(&triangle, &triangle_transform, &sphere, &sphere_transform).unwrap();
+        assert!((info.penetration_vector.len() - 0.2).abs() < 1e-4);
+        assert!(info.penetration_vector.y > 0.0);
+    }
+
+    #[cfg(feature = "debug")]
+    #[test]
+    fn debug_simplex_encloses_origin() {
+        let sphere = ConvexShape::Sphere(SphereShape::new(1.0));
+        let info = crate::gjk_epa::gjk_is_intersects_debug(&sphere, &ShapeTransform::new(Vec3::ZERO, Quat::default()),
+                                                           &sphere, &ShapeTransform::new(Vec3::new(1.5, 0.2, 0.1), Quat::default()));
+        assert!(info.intersects);
+        assert!(!info.polytope.is_empty());
+        assert!(info.simplices.iter().all(|simplex| (2..=4).contains(&simplex.len())));
+
+        // Origin is on same side of each face of tetrahedron as its opposite vertex.
+        let tetrahedron = info.simplices.last().unwrap();
+        assert_eq!(tetrahedron.len(), 4);
+        for i in 0..4 {
+            let (a, b, c, opposite) = (tetrahedron[i], tetrahedron[(i + 1) % 4], tetrahedron[(i + 2) % 4], tetrahedron[(i + 3) % 4]);
+            let normal = (b - a).cross(&(c - a));
+            assert!(normal.dot(&(opposite - a)) * normal.dot(&-a) >= 0.0);
+        }
+
+        let depth = info.penetration_vector.unwrap().len();
+        assert!((depth - (2.0 - Vec3::new(1.5, 0.2, 0.1).len())).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,167 @@
+//! World-wide gravity fields which are evaluated per body at its position.
+
+use rg3d_core::{
+    math::vec3::Vec3,
+    visitor::{Visit, VisitResult, Visitor},
+};
+use std::fmt::{Debug, Formatter};
+use crate::float_ops::{Backend, FloatOps};
+
+/// Magnitude of default gravity of bodies, direction is opposite to up vector of world,
+/// see [`Physics::set_up_vector`](crate::Physics::set_up_vector).
+pub const DEFAULT_GRAVITY: f32 = 9.81;
+
+/// Distance at which point gravity stops growing, prevents infinite acceleration of
+/// bodies which are very close to center.
+pub const MIN_GRAVITY_DISTANCE: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GravityFalloff {
+    /// Acceleration is `strength / distance^2`.
+    InverseSquare,
+    /// Acceleration is `strength / distance`.
+    InverseLinear,
+}
+
+impl GravityFalloff {
+    fn id(self) -> u32 {
+        match self {
+            GravityFalloff::InverseSquare => 0,
+            GravityFalloff::InverseLinear => 1,
+        }
+    }
+
+    fn from_id(id: u32) -> Result<Self, String> {
+        match id {
+            0 => Ok(GravityFalloff::InverseSquare),
+            1 => Ok(GravityFalloff::InverseLinear),
+            _ => Err("Invalid gravity falloff id!".to_owned()),
+        }
+    }
+}
+
+/// Returns acceleration towards center with given strength and falloff.
+fn point_acceleration(center: Vec3, strength: f32, falloff: GravityFalloff, position: Vec3) -> Vec3 {
+    let to_center = center - position;
+    let distance = Backend::sqrt(to_center.sqr_len());
+    match Backend::normalize(to_center) {
+        Some(direction) => {
+            let distance = distance.max(MIN_GRAVITY_DISTANCE);
+            let magnitude = match falloff {
+                GravityFalloff::InverseSquare => strength / (distance * distance),
+                GravityFalloff::InverseLinear => strength / distance,
+            };
+            direction.scale(magnitude)
+        }
+        None => Vec3::ZERO,
+    }
+}
+
+/// Point gravity source which is attached to rigid body (see [`RigidBody::set_attractor`](crate::rigid_body::RigidBody::set_attractor))
+/// and moves with it, like black hole or tractor beam. Attractor pulls every other body
+/// within its radius, pulls of multiple attractors are summed. Bodies are found by broad
+/// phase, which is updated at the end of each step, so bodies moved manually since then
+/// are found by their previous boxes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Attractor {
+    pub strength: f32,
+    pub radius: f32,
+    pub falloff: GravityFalloff,
+}
+
+impl Default for Attractor {
+    fn default() -> Self {
+        Self {
+            strength: 0.0,
+            radius: 0.0,
+            falloff: GravityFalloff::InverseSquare,
+        }
+    }
+}
+
+impl Visit for Attractor {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.strength.visit("Strength", visitor)?;
+        self.radius.visit("Radius", visitor)?;
+        let mut falloff = self.falloff.id();
+        falloff.visit("Falloff", visitor)?;
+        if visitor.is_reading() {
+            self.falloff = GravityFalloff::from_id(falloff)?;
+        }
+
+        visitor.leave_region()
+    }
+}
+
+impl Attractor {
+    pub fn new(strength: f32, radius: f32, falloff: GravityFalloff) -> Self {
+        Self {
+            strength,
+            radius: radius.abs(),
+            falloff,
+        }
+    }
+
+    /// Returns acceleration at given position caused by attractor at given center, zero
+    /// outside of radius.
+    pub fn evaluate(&self, center: Vec3, position: Vec3) -> Vec3 {
+        if center.sqr_distance(&position) > self.radius * self.radius {
+            Vec3::ZERO
+        } else {
+            point_acceleration(center, self.strength, self.falloff, position)
+        }
+    }
+}
+
+pub enum GravityField {
+    /// Same acceleration everywhere.
+    Constant(Vec3),
+    /// Acceleration towards given center.
+    Point {
+        center: Vec3,
+        strength: f32,
+        falloff: GravityFalloff,
+    },
+    /// Acceleration is computed by given function of position.
+    Custom(Box<dyn Fn(Vec3) -> Vec3 + Send>),
+}
+
+impl Debug for GravityField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GravityField::Constant(acceleration) => f.debug_tuple("Constant").field(acceleration).finish(),
+            GravityField::Point { center, strength, falloff } => f.debug_struct("Point")
+                .field("center", center)
+                .field("strength", strength)
+                .field("falloff", falloff)
+                .finish(),
+            GravityField::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl GravityField {
+    /// Returns acceleration at given position.
+    pub fn evaluate(&self, position: Vec3) -> Vec3 {
+        match self {
+            GravityField::Constant(acceleration) => *acceleration,
+            GravityField::Point { center, strength, falloff } => point_acceleration(*center, *strength, *falloff, position),
+            GravityField::Custom(func) => func(position),
+        }
+    }
+
+    /// Clones field, custom fields can't be cloned and `None` is returned for them.
+    pub fn try_clone(&self) -> Option<Self> {
+        match self {
+            GravityField::Constant(acceleration) => Some(GravityField::Constant(*acceleration)),
+            GravityField::Point { center, strength, falloff } => Some(GravityField::Point {
+                center: *center,
+                strength: *strength,
+                falloff: *falloff,
+            }),
+            GravityField::Custom(_) => None,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Collision layers - named collision groups with a matrix of who collides with whom.
+//! Matrix computes `collision_group` and `collision_mask` of bodies, so there is no
+//! need to do bit manipulations by hand.
+
+use crate::rigid_body::RigidBody;
+
+/// Collision layer, index of bit in collision group. There are 64 layers at most.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layer(u8);
+
+pub const WORLD: Layer = Layer(0);
+pub const PLAYER: Layer = Layer(1);
+pub const ENEMY: Layer = Layer(2);
+pub const PROJECTILE: Layer = Layer(3);
+pub const TRIGGER: Layer = Layer(4);
+
+impl Layer {
+    pub const MAX_LAYERS: usize = 64;
+
+    /// Creates layer with given index, which must be less than [`MAX_LAYERS`](Self::MAX_LAYERS).
+    pub fn new(index: u8) -> Result<Self, String> {
+        if (index as usize) < Self::MAX_LAYERS {
+            Ok(Self(index))
+        } else {
+            Err(format!("Layer index {} is out of range, there are {} layers at most!", index, Self::MAX_LAYERS))
+        }
+    }
+
+    #[inline]
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    /// Returns collision group with only this layer set.
+    #[inline]
+    pub fn group(self) -> u64 {
+        1 << self.0
+    }
+}
+
+/// Symmetric matrix of layer pairs which are allowed to collide. By default every layer
+/// collides with every other layer.
+#[derive(Clone, Debug)]
+pub struct CollisionMatrix {
+    masks: [u64; Layer::MAX_LAYERS],
+}
+
+impl Default for CollisionMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CollisionMatrix {
+    pub fn new() -> Self {
+        Self {
+            masks: [u64::MAX; Layer::MAX_LAYERS]
+        }
+    }
+
+    pub fn set_collides(&mut self, a: Layer, b: Layer, collides: bool) -> &mut Self {
+        if collides {
+            self.masks[a.0 as usize] |= b.group();
+            self.masks[b.0 as usize] |= a.group();
+        } else {
+            self.masks[a.0 as usize] &= !b.group();
+            self.masks[b.0 as usize] &= !a.group();
+        }
+        self
+    }
+
+    pub fn collides(&self, a: Layer, b: Layer) -> bool {
+        self.masks[a.0 as usize] & b.group() != 0
+    }
+
+    /// Returns collision mask of bodies on given layer.
+    pub fn mask(&self, layer: Layer) -> u64 {
+        self.masks[layer.0 as usize]
+    }
+
+    /// Puts body on given layer and sets its mask according to matrix. Bodies are not
+    /// updated automatically when matrix is changed, configure them again if needed.
+    pub fn configure(&self, body: &mut RigidBody, layer: Layer) {
+        body.collision_group = layer.group();
+        body.collision_mask = self.mask(layer);
+    }
+}
@@ -5,7 +5,10 @@ extern crate bitflags;
 use rg3d_core::{
     math::{
         vec3::Vec3,
+        quat::Quat,
         ray::Ray,
+        plane::Plane,
+        aabb::AxisAlignedBoundingBox,
     },
     pool::{
         Pool,
@@ -19,15 +22,28 @@ use rg3d_core::{
 };
 use std::{
     cmp::Ordering,
-    cell::RefCell
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use crate::{
-    rigid_body::RigidBody,
-    static_geometry::StaticGeometry,
+    rigid_body::{RigidBody, CollisionFlags, SolverContext, PenetrationSettings},
+    contact::{Contact, ContactInfo, ContactFilter, ResponseFilter, ContactEvent, ContactEventQueue},
+    static_geometry::{StaticGeometry, StaticTriangle},
+    soft_body::SoftBody,
+    constraint::{Constraint, WeldConstraint, KinematicTarget},
+    gravity::{GravityField, Attractor, DEFAULT_GRAVITY},
+    random::Prng,
     convex_shape::{
         ConvexShape,
-        CircumRadius
-    }
+        CircumRadius,
+        ShapeTransform,
+    },
+    gjk_epa::EpaScratch,
+    broadphase::{Broadphase, SweepAndPrune, is_aabbs_overlap},
+    float_ops::{Backend, FloatOps},
 };
 use rg3d_core::pool::Ticket;
 
@@ -36,17 +52,28 @@ pub mod convex_shape;
 pub mod rigid_body;
 pub mod contact;
 pub mod static_geometry;
+pub mod float_ops;
+pub mod soft_body;
+pub mod layers;
+pub mod constraint;
+pub mod gravity;
+pub mod random;
+pub mod broadphase;
 
 pub enum HitKind {
     Body(Handle<RigidBody>),
     StaticTriangle {
         static_geometry: Handle<StaticGeometry>,
         triangle_index: usize,
+        /// Barycentric coordinates of hit point within triangle, see [`StaticTriangle::barycentric`].
+        barycentric: Vec3,
     },
 }
 
 pub struct RayCastOptions {
     pub ignore_bodies: bool,
+    /// Skip bodies disabled by [`RigidBody::set_enabled`].
+    pub ignore_disabled_bodies: bool,
     pub ignore_static_geometries: bool,
     pub sort_results: bool,
 }
@@ -55,6 +82,7 @@ impl Default for RayCastOptions {
     fn default() -> Self {
         Self {
             ignore_bodies: false,
+            ignore_disabled_bodies: false,
             ignore_static_geometries: false,
             sort_results: true,
         }
@@ -63,17 +91,284 @@ impl Default for RayCastOptions {
 
 pub struct RayCastResult {
     pub kind: HitKind,
+    /// User flags of hit body or static geometry.
+    pub user_flags: u64,
+    /// Collision group of hit body, zero for static geometry.
+    pub collision_group: u64,
     pub position: Vec3,
     pub normal: Vec3,
     pub sqr_distance: f32,
 }
 
-#[derive(Debug)]
+/// Body touched by swept shape, see [`Physics::sweep_shape_all`].
+#[derive(Copy, Clone, Debug)]
+pub struct SweepHit {
+    pub body: Handle<RigidBody>,
+    /// Fraction of motion (in `[0; 1]` range) at which shape touches body, zero if shape
+    /// overlaps body at start.
+    pub toi: f32,
+    /// Position of shape at the moment of impact.
+    pub position: Vec3,
+}
+
+/// Defines how strength of radial impulse decreases with distance from its center.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Falloff {
+    /// Every body in radius receives full strength.
+    None,
+    /// Strength decreases linearly to zero at the edge of radius.
+    Linear,
+    /// Strength decreases with square of distance, to zero at the edge of radius.
+    Quadratic,
+}
+
+impl Falloff {
+    /// Returns strength multiplier in `[0; 1]` range for given distance.
+    pub fn factor(self, distance: f32, radius: f32) -> f32 {
+        let k = if radius > 0.0 {
+            (1.0 - distance / radius).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        match self {
+            Falloff::None => 1.0,
+            Falloff::Linear => k,
+            Falloff::Quadratic => k * k,
+        }
+    }
+}
+
+/// Region of world in which bodies are simulated, see [`Physics::set_bounds`].
+#[derive(Copy, Clone, Debug)]
+pub enum Bounds {
+    Box(AxisAlignedBoundingBox),
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+}
+
+impl Bounds {
+    /// Returns true if given point is inside of bounds, points on surface are inside.
+    pub fn contains(&self, point: Vec3) -> bool {
+        match self {
+            Bounds::Box(aabb) => aabb.is_contains_point(point),
+            Bounds::Sphere { center, radius } => center.sqr_distance(&point) <= radius * radius,
+        }
+    }
+}
+
+/// What happens with body which has left world bounds, see [`Physics::set_bounds`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutOfBoundsAction {
+    /// Body is removed from world, same as body with expired lifetime.
+    Remove,
+    /// Body is disabled and stays in world.
+    Disable,
+}
+
+/// Information about a body that was removed because its lifetime has expired, it has
+/// left world bounds or its removal was requested through [`Physics::removal_queue`].
+#[derive(Debug, Clone)]
+pub struct ExpiredBody {
+    /// Handle of the body. It is already invalid when this record is read.
+    pub handle: Handle<RigidBody>,
+    /// Final position of the body right before it was removed.
+    pub position: Vec3,
+    /// Final orientation of the body right before it was removed.
+    pub rotation: Quat,
+}
+
+/// Shared queue of bodies which must be removed after current step, see
+/// [`Physics::removal_queue`]. Queue is cheap to clone, every clone refers to same queue,
+/// so it can be captured by contact filter.
+#[derive(Clone, Debug, Default)]
+pub struct RemovalQueue {
+    handles: Arc<Mutex<Vec<Handle<RigidBody>>>>,
+}
+
+impl RemovalQueue {
+    /// Requests removal of body, body stays valid until end of step.
+    pub fn push(&self, handle: Handle<RigidBody>) {
+        self.handles.lock().unwrap_or_else(|e| e.into_inner()).push(handle);
+    }
+
+    fn take(&self) -> Vec<Handle<RigidBody>> {
+        std::mem::take(&mut *self.handles.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Limits amount of work done by collision solver in one step, see
+/// [`Physics::set_solver_budget`].
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum Budget {
+    #[default]
+    Unlimited,
+    /// Maximum time spent by collision solver in one step.
+    Time(Duration),
+    /// Maximum amount of solver iterations in one step. Iteration is resolution of collisions
+    /// of a single body with every other body and static geometry.
+    Iterations(usize),
+}
+
+impl Budget {
+    fn is_exhausted(self, iterations: usize, started: Instant) -> bool {
+        match self {
+            Budget::Unlimited => false,
+            Budget::Time(limit) => started.elapsed() >= limit,
+            Budget::Iterations(limit) => iterations >= limit,
+        }
+    }
+}
+
+/// Statistics of last step, see [`Physics::get_step_stats`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StepStats {
+    /// Amount of iterations done by collision solver, see [`Budget::Iterations`].
+    pub solver_iterations: usize,
+    /// True if collision solver has stopped because its budget was exhausted, some
+    /// penetrations were left for next step.
+    pub budget_exhausted: bool,
+    /// Amount of bodies which were collided in substeps because of their speed, see
+    /// [`Physics::set_substep_speed_threshold`].
+    pub substepped_bodies: usize,
+}
+
+/// Health of collision solver at end of last step, see [`Physics::stability_report`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StabilityReport {
+    /// Amount of bodies which have contacts and move slower than
+    /// [`Physics::RESTING_SPEED`].
+    pub resting_bodies: usize,
+    /// Amount of bodies which still penetrate their contact parties deeper than
+    /// [`Physics::SOLVER_TOLERANCE`].
+    pub penetrating_bodies: usize,
+    /// Deepest penetration left after collision response.
+    pub max_penetration: f32,
+    /// Average amount of contacts per simulated body.
+    pub average_contacts: f32,
+}
+
+/// Parties of contact, pair of bodies is stored once.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct ContactPair {
+    body: Handle<RigidBody>,
+    other_body: Handle<RigidBody>,
+    static_geom: Handle<StaticGeometry>,
+}
+
+/// Maps handles of objects of merged world to handles of same objects in world they
+/// were merged into, see [`Physics::merge`].
+#[derive(Debug, Clone, Default)]
+pub struct HandleRemap {
+    pub bodies: HashMap<Handle<RigidBody>, Handle<RigidBody>>,
+    pub static_geoms: HashMap<Handle<StaticGeometry>, Handle<StaticGeometry>>,
+    pub soft_bodies: HashMap<Handle<SoftBody>, Handle<SoftBody>>,
+    pub constraints: HashMap<Handle<Constraint>, Handle<Constraint>>,
+}
+
+/// Removes every object from pool, returns objects together with their old handles.
+fn drain_pool<T>(pool: &mut Pool<T>) -> Vec<(Handle<T>, T)> {
+    let handles: Vec<Handle<T>> = pool.pair_iter().map(|(handle, _)| handle).collect();
+    handles.into_iter().map(|handle| (handle, pool.free(handle))).collect()
+}
+
 pub struct Physics {
     bodies: Pool<RigidBody>,
     static_geoms: Pool<StaticGeometry>,
+    soft_bodies: Pool<SoftBody>,
+    constraints: Pool<Constraint>,
     query_buffer: RefCell<Vec<u32>>,
-    enabled: bool
+    enabled: bool,
+    up_vector: Vec3,
+    expired_bodies: Vec<ExpiredBody>,
+    bounds: Option<Bounds>,
+    out_of_bounds_action: OutOfBoundsAction,
+    out_of_bounds_bodies: Vec<Handle<RigidBody>>,
+    epa_scratch: EpaScratch,
+    contact_filter: Option<Box<ContactFilter>>,
+    response_filter: Option<Box<ResponseFilter>>,
+    gravity_field: Option<GravityField>,
+    /// Removed bodies kept only for their heap storage, see [`Physics::set_body_free_list_capacity`].
+    body_free_list: Vec<RigidBody>,
+    body_free_list_capacity: usize,
+    fixed_time_step: f32,
+    time_scale: f32,
+    time_accumulator: f32,
+    solver_relaxation: f32,
+    solver_iterations: usize,
+    substep_speed_threshold: Option<f32>,
+    max_substeps: usize,
+    restitution_threshold: f32,
+    contact_shrink_interval: Option<u32>,
+    steps_since_contact_shrink: u32,
+    rng: Prng,
+    contact_margin: f32,
+    /// Velocity changes of bounces of current step, see [`Physics::set_restitution_threshold`].
+    bounces: Vec<(u32, Vec3)>,
+    /// Time step of last [`Physics::integrate`], used to convert speed threshold of substeps.
+    step_delta_time: f32,
+    epa_fallback: bool,
+    resting_clearance: f32,
+    removal_queue: RemovalQueue,
+    contact_hysteresis_steps: u32,
+    contact_hysteresis_distance: f32,
+    contact_reduction_distance: f32,
+    contact_reduction_angle: f32,
+    /// Sizes of contact clusters, reused between calls of [`RigidBody::reduce_contacts`].
+    reduction_counts: Vec<u32>,
+    max_linear_velocity: f32,
+    solver_budget: Budget,
+    contact_event_queue: Option<ContactEventQueue>,
+    /// Pairs which were in contact on current and previous step, used to find contacts
+    /// which has begun.
+    contact_pairs: HashSet<ContactPair>,
+    previous_contact_pairs: HashSet<ContactPair>,
+    new_contacts: Vec<(Handle<RigidBody>, Handle<RigidBody>)>,
+    constraint_plane: Option<Plane>,
+    step_stats: StepStats,
+    /// Body from which collision solver starts, see [`Physics::set_solver_budget`]. Solve
+    /// order depends on positions of bodies, so position in it can't be used.
+    solver_resume_body: Handle<RigidBody>,
+    /// Indices of bodies in order in which collisions are solved.
+    solve_order: Vec<u32>,
+    broadphase: Box<dyn Broadphase>,
+    /// Bodies which are tracked by broad phase.
+    broadphase_bodies: HashSet<Handle<RigidBody>>,
+    broadphase_pairs: Vec<(Handle<RigidBody>, Handle<RigidBody>)>,
+    /// Pairs of welded bodies in both orders, see [`WeldConstraint`].
+    welded_pairs: HashSet<(Handle<RigidBody>, Handle<RigidBody>)>,
+    /// Pairs of positions in solve order of bodies with overlapping boxes, both ways.
+    candidate_links: Vec<(u32, u32)>,
+    /// Indices of bodies which could collide with body at given position in solve order,
+    /// candidates of body `k` are `candidates[candidate_offsets[k]..candidate_offsets[k + 1]]`.
+    candidates: Vec<u32>,
+    candidate_offsets: Vec<usize>,
+    /// Position of body in solve order by its index, `u32::MAX` for bodies out of order.
+    solve_ranks: Vec<u32>,
+    /// Attractors of bodies gathered at the beginning of step, see [`RigidBody::set_attractor`].
+    attractors: Vec<(Handle<RigidBody>, Vec3, Attractor)>,
+    /// Bodies found by broad phase within radius of attractor, reused between attractors.
+    attracted_bodies: Vec<Handle<RigidBody>>,
+    #[cfg(feature = "debug-history")]
+    contact_history_depth: usize,
+}
+
+impl Debug for Physics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Physics")
+            .field("bodies", &self.bodies)
+            .field("static_geoms", &self.static_geoms)
+            .field("soft_bodies", &self.soft_bodies)
+            .field("constraints", &self.constraints)
+            .field("enabled", &self.enabled)
+            .field("up_vector", &self.up_vector)
+            .field("expired_bodies", &self.expired_bodies)
+            .field("contact_filter", &self.contact_filter.is_some())
+            .field("response_filter", &self.response_filter.is_some())
+            .field("gravity_field", &self.gravity_field)
+            .finish()
+    }
 }
 
 impl Visit for Physics {
@@ -83,6 +378,17 @@ impl Visit for Physics {
         self.bodies.visit("Bodies", visitor)?;
         self.static_geoms.visit("StaticGeoms", visitor)?;
         let _ = self.enabled.visit("Enabled", visitor); // let _ for backward compatibility.
+        let _ = self.soft_bodies.visit("SoftBodies", visitor);
+        let _ = self.up_vector.visit("UpVector", visitor);
+        let _ = self.constraints.visit("Constraints", visitor);
+        let _ = self.rng.visit("Rng", visitor);
+
+        if visitor.is_reading() {
+            // Handles are preserved by pools, but constraints of bodies that failed to
+            // load must not stay in world.
+            self.remove_dangling_constraints();
+            self.sync_broadphase();
+        }
 
         visitor.leave_region()
     }
@@ -96,31 +402,354 @@ impl Default for Physics {
 
 impl Clone for Physics {
     fn clone(&self) -> Self {
-        Self {
+        let mut physics = Self {
             bodies: self.bodies.clone(),
             static_geoms: self.static_geoms.clone(),
+            soft_bodies: self.soft_bodies.clone(),
+            constraints: self.constraints.clone(),
             query_buffer: Default::default(),
-            enabled: self.enabled
-        }
+            enabled: self.enabled,
+            up_vector: self.up_vector,
+            expired_bodies: Default::default(),
+            bounds: self.bounds,
+            out_of_bounds_action: self.out_of_bounds_action,
+            out_of_bounds_bodies: Default::default(),
+            epa_scratch: Default::default(),
+            contact_filter: None,
+            response_filter: None,
+            gravity_field: self.gravity_field.as_ref().and_then(|field| field.try_clone()),
+            body_free_list: Vec::new(),
+            body_free_list_capacity: self.body_free_list_capacity,
+            fixed_time_step: self.fixed_time_step,
+            time_scale: self.time_scale,
+            time_accumulator: self.time_accumulator,
+            solver_relaxation: self.solver_relaxation,
+            solver_iterations: self.solver_iterations,
+            substep_speed_threshold: self.substep_speed_threshold,
+            max_substeps: self.max_substeps,
+            restitution_threshold: self.restitution_threshold,
+            contact_shrink_interval: self.contact_shrink_interval,
+            steps_since_contact_shrink: self.steps_since_contact_shrink,
+            rng: self.rng,
+            contact_margin: self.contact_margin,
+            bounces: Default::default(),
+            step_delta_time: self.step_delta_time,
+            epa_fallback: self.epa_fallback,
+            resting_clearance: self.resting_clearance,
+            removal_queue: Default::default(),
+            contact_hysteresis_steps: self.contact_hysteresis_steps,
+            contact_hysteresis_distance: self.contact_hysteresis_distance,
+            contact_reduction_distance: self.contact_reduction_distance,
+            contact_reduction_angle: self.contact_reduction_angle,
+            reduction_counts: Default::default(),
+            max_linear_velocity: self.max_linear_velocity,
+            solver_budget: self.solver_budget,
+            contact_event_queue: None,
+            contact_pairs: Default::default(),
+            previous_contact_pairs: Default::default(),
+            new_contacts: Default::default(),
+            constraint_plane: self.constraint_plane,
+            step_stats: self.step_stats,
+            solver_resume_body: self.solver_resume_body,
+            solve_order: Default::default(),
+            broadphase: Box::new(SweepAndPrune::new()),
+            broadphase_bodies: Default::default(),
+            broadphase_pairs: Default::default(),
+            welded_pairs: Default::default(),
+            candidate_links: Default::default(),
+            candidates: Default::default(),
+            candidate_offsets: Default::default(),
+            solve_ranks: Default::default(),
+            attractors: Default::default(),
+            attracted_bodies: Default::default(),
+            #[cfg(feature = "debug-history")]
+            contact_history_depth: self.contact_history_depth,
+        };
+        physics.sync_broadphase();
+        physics
     }
 }
 
 impl Physics {
+    const AIR_FRICTION: f32 = 0.003;
+
+    /// Amount of segments used to triangulate curved shapes by [`freeze_to_static`](Self::freeze_to_static).
+    pub const FREEZE_SEGMENTS: usize = 16;
+
+    pub const DEFAULT_FIXED_TIME_STEP: f32 = 1.0 / 60.0;
+
+    pub const DEFAULT_MAX_LINEAR_VELOCITY: f32 = 1000.0;
+
+    /// Maximum amount of fixed steps done by single [`update`](Self::update), rest of
+    /// time is dropped so slow frames won't make simulation fall further behind.
+    pub const MAX_STEPS_PER_UPDATE: usize = 8;
+
+    /// Distance at which bodies are considered touching by [`predict_toi`](Self::predict_toi).
+    pub const TOI_TOLERANCE: f32 = 1.0e-3;
+
+    const MAX_TOI_ITERATIONS: usize = 64;
+
+    /// Penetration depth below which extra solver passes are skipped, see [`Physics::set_solver_iterations`].
+    pub const SOLVER_TOLERANCE: f32 = 1.0e-3;
+
+    pub const DEFAULT_MAX_SUBSTEPS: usize = 16;
+
+    pub const DEFAULT_RESTITUTION_THRESHOLD: f32 = 1.0;
+
+    /// Amount of contacts per body for which space is reserved by [`finalize`](Self::finalize),
+    /// and which is kept when contact lists are shrunk, see [`set_contact_shrink_interval`](Self::set_contact_shrink_interval).
+    pub const RESERVED_CONTACTS_PER_BODY: usize = 4;
+
+    /// Speed (in units per second) below which body with contacts is considered resting,
+    /// see [`stability_report`](Self::stability_report).
+    pub const RESTING_SPEED: f32 = 0.05;
+
+    #[cfg(feature = "debug-history")]
+    pub const DEFAULT_CONTACT_HISTORY_DEPTH: usize = 16;
+
     pub fn new() -> Self {
         Self {
             bodies: Pool::new(),
             static_geoms: Pool::new(),
+            soft_bodies: Pool::new(),
+            constraints: Pool::new(),
             query_buffer: Default::default(),
-            enabled: true
+            enabled: true,
+            up_vector: Vec3::UP,
+            expired_bodies: Default::default(),
+            bounds: None,
+            out_of_bounds_action: OutOfBoundsAction::Remove,
+            out_of_bounds_bodies: Default::default(),
+            epa_scratch: Default::default(),
+            contact_filter: None,
+            response_filter: None,
+            gravity_field: None,
+            body_free_list: Vec::new(),
+            body_free_list_capacity: 0,
+            fixed_time_step: Self::DEFAULT_FIXED_TIME_STEP,
+            time_scale: 1.0,
+            time_accumulator: 0.0,
+            solver_relaxation: 1.0,
+            solver_iterations: 1,
+            substep_speed_threshold: None,
+            max_substeps: Self::DEFAULT_MAX_SUBSTEPS,
+            restitution_threshold: Self::DEFAULT_RESTITUTION_THRESHOLD,
+            contact_shrink_interval: None,
+            steps_since_contact_shrink: 0,
+            rng: Prng::default(),
+            contact_margin: 0.0,
+            bounces: Default::default(),
+            step_delta_time: Self::DEFAULT_FIXED_TIME_STEP,
+            epa_fallback: false,
+            resting_clearance: 0.0,
+            removal_queue: Default::default(),
+            contact_hysteresis_steps: 0,
+            contact_hysteresis_distance: 0.0,
+            contact_reduction_distance: 0.0,
+            contact_reduction_angle: 0.0,
+            reduction_counts: Default::default(),
+            max_linear_velocity: Self::DEFAULT_MAX_LINEAR_VELOCITY,
+            solver_budget: Budget::Unlimited,
+            contact_event_queue: None,
+            contact_pairs: Default::default(),
+            previous_contact_pairs: Default::default(),
+            new_contacts: Default::default(),
+            constraint_plane: None,
+            step_stats: Default::default(),
+            solver_resume_body: Handle::NONE,
+            solve_order: Default::default(),
+            broadphase: Box::new(SweepAndPrune::new()),
+            broadphase_bodies: Default::default(),
+            broadphase_pairs: Default::default(),
+            welded_pairs: Default::default(),
+            candidate_links: Default::default(),
+            candidates: Default::default(),
+            candidate_offsets: Default::default(),
+            solve_ranks: Default::default(),
+            attractors: Default::default(),
+            attracted_bodies: Default::default(),
+            #[cfg(feature = "debug-history")]
+            contact_history_depth: Self::DEFAULT_CONTACT_HISTORY_DEPTH,
+        }
+    }
+
+    pub fn add_body(&mut self, mut body: RigidBody) -> Handle<RigidBody> {
+        body.gravity = self.default_gravity(body.gravity);
+        if let Some(removed) = self.body_free_list.pop() {
+            body.reuse_storage(removed);
+        }
+        let handle = self.bodies.spawn(body);
+        self.track_body(handle);
+        handle
+    }
+
+    /// Replaces existing body with new one, reusing heap storage of old body, new body
+    /// usually takes pool slot of old one too. Old handle becomes invalid and constraints
+    /// which refer to old body are removed, new handle is returned. Returns `None` (and
+    /// drops new body) if handle is invalid. Useful for games which constantly create and
+    /// destroy lots of short-living bodies (projectiles).
+    pub fn recycle_body(&mut self, handle: Handle<RigidBody>, mut body: RigidBody) -> Option<Handle<RigidBody>> {
+        if !self.bodies.is_valid_handle(handle) {
+            return None;
+        }
+        body.gravity = self.default_gravity(body.gravity);
+        self.untrack_body(handle);
+        body.reuse_storage(self.bodies.free(handle));
+        self.remove_dangling_constraints();
+        let handle = self.bodies.spawn(body);
+        self.track_body(handle);
+        Some(handle)
+    }
+
+    /// Sets maximum amount of removed bodies which are kept to reuse their heap storage
+    /// by [`add_body`](Self::add_body). Zero (default) disables free list.
+    pub fn set_body_free_list_capacity(&mut self, capacity: usize) {
+        self.body_free_list_capacity = capacity;
+        self.body_free_list.truncate(capacity);
+    }
+
+    pub fn get_body_free_list_capacity(&self) -> usize {
+        self.body_free_list_capacity
+    }
+
+    /// Puts removed body into free list, if there is space.
+    fn release_body(&mut self, mut body: RigidBody) {
+        if self.body_free_list.len() < self.body_free_list_capacity {
+            // User data must not outlive body.
+            body.user_data = None;
+            self.body_free_list.push(body);
+        }
+    }
+
+    /// Adds copy of template body at given position with given velocity (see
+    /// [`RigidBody::launch`]). Useful for spawning projectiles from prototype.
+    pub fn spawn_copy(&mut self, template: &RigidBody, position: Vec3, velocity: Vec3) -> Handle<RigidBody> {
+        let mut body = template.clone();
+        body.launch(position, velocity);
+        self.add_body(body)
+    }
+
+    /// Reserves space for at least `additional` more bodies in broad phase and in per-body
+    /// buffers of solver, so that first step after insertion of `additional` bodies won't
+    /// cause their reallocation. Body pool itself has no way of reserving memory, it grows
+    /// on insertion as usual.
+    pub fn reserve(&mut self, additional: usize) {
+        let body_count = self.bodies.iter().count() + additional;
+        self.broadphase.reserve(additional);
+        self.broadphase_bodies.reserve(additional);
+        self.solve_order.reserve(body_count);
+        self.solve_ranks.reserve(self.bodies.get_capacity() + additional);
+        self.candidate_offsets.reserve(body_count + 1);
+        self.bounces.reserve(body_count);
+    }
+
+    /// Prepares world for simulation after bulk insertion of bodies and static geometry,
+    /// for example after level load: pre-sizes internal buffers of solver and contact
+    /// lists of bodies for current amount of objects, so first [`step`](Self::step) won't
+    /// stall on allocations. Octrees of static geometry are built on insertion, so they
+    /// are ready already. Call it once after inserts, it is cheap to call again after big
+    /// changes of world.
+    pub fn finalize(&mut self) {
+        let body_count = self.bodies.iter().count();
+        let contact_count = body_count * Self::RESERVED_CONTACTS_PER_BODY;
+
+        self.solve_order.reserve(body_count);
+        self.solve_ranks.reserve(self.bodies.get_capacity());
+        self.candidate_offsets.reserve(body_count + 1);
+        self.new_contacts.reserve(contact_count);
+        self.reduction_counts.reserve(Self::RESERVED_CONTACTS_PER_BODY);
+        self.contact_pairs.reserve(contact_count);
+        self.previous_contact_pairs.reserve(contact_count);
+        self.bounces.reserve(body_count);
+        self.attractors.reserve(self.bodies.iter().filter(|body| body.attractor.is_some()).count());
+        self.attracted_bodies.reserve(body_count);
+        self.expired_bodies.reserve(self.bodies.iter().filter(|body| body.lifetime.is_some()).count());
+
+        // Sphere query can't return more triangles than geometry has.
+        let max_triangles = self.static_geoms.iter().map(|static_geom| static_geom.triangles.len()).max().unwrap_or(0);
+        self.query_buffer.borrow_mut().reserve(max_triangles);
+
+        for body in self.bodies.iter_mut() {
+            body.contacts.reserve(Self::RESERVED_CONTACTS_PER_BODY);
         }
     }
 
-    pub fn add_body(&mut self, body: RigidBody) -> Handle<RigidBody> {
-        self.bodies.spawn(body)
+    /// Resets random generator of world, which is used by every sampling method of world
+    /// (like [`random_point_inside_body`](Self::random_point_inside_body)). Same seed and
+    /// same sequence of calls give same results, so replays are reproducible. State of
+    /// generator is serialized together with world.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Prng::new(seed);
+    }
+
+    /// Returns uniformly distributed random number in `[0; 1)` range, see [`set_seed`](Self::set_seed).
+    pub fn random_f32(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    /// Returns random point inside of shape of body in world space, see
+    /// [`ConvexShape::random_point_inside`].
+    pub fn random_point_inside_body(&mut self, handle: Handle<RigidBody>) -> Option<Vec3> {
+        let body = self.bodies.try_borrow(handle)?;
+        let rng = &mut self.rng;
+        let point = body.shape.random_point_inside(&mut || rng.next_f32());
+        Some(body.get_transform().transform_point(point))
+    }
+
+    /// Returns random point on surface of shape of body in world space, see
+    /// [`ConvexShape::random_point_on_surface`].
+    pub fn random_point_on_body_surface(&mut self, handle: Handle<RigidBody>) -> Option<Vec3> {
+        let body = self.bodies.try_borrow(handle)?;
+        let rng = &mut self.rng;
+        let point = body.shape.random_point_on_surface(&mut || rng.next_f32());
+        Some(body.get_transform().transform_point(point))
+    }
+
+    /// Adds every body from given iterator, returns handles in same order.
+    pub fn add_bodies<I: IntoIterator<Item=RigidBody>>(&mut self, bodies: I) -> Vec<Handle<RigidBody>> {
+        let bodies = bodies.into_iter();
+        let free_slots = self.bodies.get_capacity() - self.bodies.iter().count();
+        let (lower_bound, _) = bodies.size_hint();
+        if lower_bound > free_slots {
+            self.reserve(lower_bound - free_slots);
+        }
+        bodies.map(|body| self.add_body(body)).collect()
     }
 
+    /// Removes body from world, constraints which refer to the body are removed too.
     pub fn remove_body(&mut self, body_handle: Handle<RigidBody>) {
-        self.bodies.free(body_handle);
+        self.untrack_body(body_handle);
+        let body = self.bodies.free(body_handle);
+        self.release_body(body);
+        self.remove_dangling_constraints();
+    }
+
+    /// Removes constraints which refer to removed bodies.
+    fn remove_dangling_constraints(&mut self) {
+        let bodies = &self.bodies;
+        let dangling: Vec<Handle<Constraint>> = self.constraints.pair_iter()
+            .filter(|(_, constraint)| {
+                let (a, b) = constraint.get_bodies();
+                !bodies.is_valid_handle(a) || (b.is_some() && !bodies.is_valid_handle(b))
+            })
+            .map(|(handle, _)| handle)
+            .collect();
+        for handle in dangling {
+            self.constraints.free(handle);
+        }
+    }
+
+    /// Returns queue of deferred removals. Bodies put into the queue are removed at the
+    /// end of next step (in [`resolve`](Self::resolve)), so removal can be requested from
+    /// places where physics can't be borrowed mutably, like contact filter. Invalid and
+    /// duplicated handles in queue are ignored.
+    pub fn removal_queue(&self) -> RemovalQueue {
+        self.removal_queue.clone()
+    }
+
+    /// Puts body into queue of deferred removals, see [`removal_queue`](Self::removal_queue).
+    pub fn remove_body_deferred(&self, body_handle: Handle<RigidBody>) {
+        self.removal_queue.push(body_handle);
     }
 
     pub fn add_static_geometry(&mut self, static_geom: StaticGeometry) -> Handle<StaticGeometry> {
@@ -131,6 +760,88 @@ impl Physics {
         self.static_geoms.free(static_geom);
     }
 
+    pub fn add_soft_body(&mut self, mut soft_body: SoftBody) -> Handle<SoftBody> {
+        soft_body.gravity = self.default_gravity(soft_body.gravity);
+        self.soft_bodies.spawn(soft_body)
+    }
+
+    pub fn remove_soft_body(&mut self, soft_body: Handle<SoftBody>) {
+        self.soft_bodies.free(soft_body);
+    }
+
+    pub fn borrow_soft_body(&self, handle: Handle<SoftBody>) -> &SoftBody {
+        self.soft_bodies.borrow(handle)
+    }
+
+    pub fn borrow_soft_body_mut(&mut self, handle: Handle<SoftBody>) -> &mut SoftBody {
+        self.soft_bodies.borrow_mut(handle)
+    }
+
+    pub fn add_constraint(&mut self, constraint: Constraint) -> Handle<Constraint> {
+        self.constraints.spawn(constraint)
+    }
+
+    pub fn remove_constraint(&mut self, constraint: Handle<Constraint>) {
+        self.constraints.free(constraint);
+    }
+
+    pub fn borrow_constraint(&self, handle: Handle<Constraint>) -> &Constraint {
+        self.constraints.borrow(handle)
+    }
+
+    pub fn borrow_constraint_mut(&mut self, handle: Handle<Constraint>) -> &mut Constraint {
+        self.constraints.borrow_mut(handle)
+    }
+
+    /// Welds two bodies together in their current relative transform, see [`WeldConstraint`].
+    pub fn weld(&mut self, body_a: Handle<RigidBody>, body_b: Handle<RigidBody>) -> Handle<Constraint> {
+        let weld = WeldConstraint::new(body_a, self.bodies.borrow(body_a), body_b, self.bodies.borrow(body_b));
+        self.add_constraint(Constraint::Weld(weld))
+    }
+
+    /// Starts driving body towards target position with force limited by `max_force`,
+    /// see [`KinematicTarget`]. Body keeps colliding, so it can't be pushed through walls.
+    /// Previous grab of body is replaced.
+    pub fn grab(&mut self, handle: Handle<RigidBody>, target: Vec3, max_force: f32) {
+        self.bodies.borrow_mut(handle).grab = Some(KinematicTarget::new(target, max_force));
+    }
+
+    /// Moves target of grabbed body, does nothing if body is not grabbed.
+    pub fn update_grab_target(&mut self, handle: Handle<RigidBody>, target: Vec3) {
+        if let Some(grab) = self.bodies.borrow_mut(handle).grab.as_mut() {
+            grab.set_target(target);
+        }
+    }
+
+    pub fn release_grab(&mut self, handle: Handle<RigidBody>) {
+        self.bodies.borrow_mut(handle).grab = None;
+    }
+
+    fn is_stuck(&self, a: Handle<RigidBody>, b: Handle<RigidBody>) -> bool {
+        self.constraints.iter().any(|constraint| match constraint {
+            Constraint::Weld(weld) => weld.sticky && !weld.broken &&
+                ((weld.body_a == a && weld.body_b == b) || (weld.body_a == b && weld.body_b == a)),
+            _ => false,
+        })
+    }
+
+    /// Removes every weld which was created by contacts of given body with sticky bodies,
+    /// see [`CollisionFlags::STICKY`]. Released bodies won't stick again to each other until
+    /// they separate and touch again. Returns true if anything was released.
+    pub fn release_stuck(&mut self, handle: Handle<RigidBody>) -> bool {
+        let stuck: Vec<Handle<Constraint>> = self.constraints.pair_iter()
+            .filter(|(_, constraint)| match constraint {
+                Constraint::Weld(weld) => weld.sticky && (weld.body_a == handle || weld.body_b == handle),
+                _ => false,
+            })
+            .map(|(constraint_handle, _)| constraint_handle)
+            .collect();
+        for constraint_handle in stuck.iter() {
+            self.constraints.free(*constraint_handle);
+        }
+        !stuck.is_empty()
+    }
+
     pub fn borrow_body(&self, handle: Handle<RigidBody>) -> &RigidBody {
         self.bodies.borrow(handle)
     }
@@ -139,6 +850,24 @@ impl Physics {
         self.bodies.borrow_mut(handle)
     }
 
+    /// Calls given function for every body. This is cheaper than borrowing each body
+    /// by handle, useful to sync transforms of bodies with scene in bulk.
+    pub fn for_each_body<F>(&self, mut func: F) where F: FnMut(Handle<RigidBody>, &RigidBody) {
+        for (handle, body) in self.bodies.pair_iter() {
+            func(handle, body)
+        }
+    }
+
+    /// Mutable version of [`for_each_body`](Self::for_each_body).
+    pub fn for_each_body_mut<F>(&mut self, mut func: F) where F: FnMut(Handle<RigidBody>, &mut RigidBody) {
+        for (handle, body) in self.bodies.pair_iter_mut() {
+            func(handle, body)
+        }
+    }
+
+    /// Returns true if handle points to existing body. Handles of surviving bodies never
+    /// change, and handle of removed body stays invalid even if its slot is reused by
+    /// another body, since pool generation of slot changes.
     pub fn is_valid_body_handle(&self, handle: Handle<RigidBody>) -> bool {
         self.bodies.is_valid_handle(handle)
     }
@@ -163,163 +892,3830 @@ impl Physics {
         self.enabled
     }
 
-    pub fn step(&mut self, delta_time: f32) {
-        if !self.enabled {
-            return;
-        }
+    /// Sets gravity field of world. When set, field is evaluated at position of each
+    /// rigid body on each step and used instead of gravity of body. Soft bodies always
+    /// use their own gravity. Custom fields are not cloned together with world.
+    pub fn set_gravity_field(&mut self, field: GravityField) {
+        self.gravity_field = Some(field);
+    }
 
-        let dt2 = delta_time * delta_time;
-        let air_friction = 0.003;
+    /// Removes gravity field, bodies will use their own gravity.
+    pub fn remove_gravity_field(&mut self) {
+        self.gravity_field = None;
+    }
 
-        // Take second mutable reference to bodies, this is safe because:
-        // 1. We won't modify collection while iterating over it.
-        // 2. Simultaneous access to a body won't happen because of
-        //    pointer equality check down below.
-        let other_bodies = unsafe { &mut *(&mut self.bodies as *mut Pool<RigidBody>) };
+    pub fn get_gravity_field(&self) -> Option<&GravityField> {
+        self.gravity_field.as_ref()
+    }
 
-        for (body_handle, body) in self.bodies.pair_iter_mut() {
-            if let Some(ref mut lifetime) = body.lifetime {
-                *lifetime -= delta_time;
+    /// Sets direction which is considered "up" (Y axis by default), use it for Z-up
+    /// content. Up vector is used for ground detection (see [`Contact::is_ground`](contact::Contact::is_ground))
+    /// and as fallback direction of solver. It also defines default gravity: bodies which
+    /// have default gravity (see [`gravity::DEFAULT_GRAVITY`]) get gravity opposite to up
+    /// vector when added to world, gravity of bodies which are already in world is changed
+    /// too. Vector is normalized, degenerated vector is ignored.
+    pub fn set_up_vector(&mut self, up: Vec3) {
+        if let Some(up) = Backend::normalize(up) {
+            let old_gravity = self.up_vector.scale(-DEFAULT_GRAVITY);
+            self.up_vector = up;
+            for body in self.bodies.iter_mut().filter(|body| body.gravity == old_gravity) {
+                body.gravity = up.scale(-DEFAULT_GRAVITY);
+            }
+            for soft_body in self.soft_bodies.iter_mut().filter(|body| body.gravity == old_gravity) {
+                soft_body.gravity = up.scale(-DEFAULT_GRAVITY);
             }
+        }
+    }
 
-            body.acceleration += body.gravity;
-            body.verlet(dt2, air_friction);
+    /// Turns default Y-down gravity of new body into gravity opposite to up vector.
+    fn default_gravity(&self, gravity: Vec3) -> Vec3 {
+        if gravity == Vec3::UP.scale(-DEFAULT_GRAVITY) {
+            self.up_vector.scale(-DEFAULT_GRAVITY)
+        } else {
+            gravity
+        }
+    }
 
-            body.contacts.clear();
+    pub fn get_up_vector(&self) -> Vec3 {
+        self.up_vector
+    }
 
-            for (other_body_handle, other_body) in other_bodies.pair_iter_mut() {
-                // Enforce borrowing rules at runtime.
-                if !std::ptr::eq(body, other_body) &&
-                    ((other_body.collision_group & body.collision_mask) != 0) &&
-                    ((body.collision_group & other_body.collision_mask) != 0) {
-                    body.solve_rigid_body_collision(body_handle,other_body, other_body_handle);
-                }
-            }
+    /// Sets bounds of world, bodies which leave them (center of body is checked) at the
+    /// end of step are removed or disabled depending on [`set_out_of_bounds_action`](Self::set_out_of_bounds_action),
+    /// so bodies which fell off the level are not simulated forever. Removed bodies are
+    /// returned by [`step`](Self::step) together with expired ones. `None` (default)
+    /// means that world is infinite.
+    pub fn set_bounds(&mut self, bounds: Option<Bounds>) {
+        self.bounds = bounds;
+    }
 
-            for (handle, static_geometry) in self.static_geoms.pair_iter() {
-                let mut query_buffer = self.query_buffer.borrow_mut();
-                static_geometry.octree.sphere_query(body.position, body.shape.circumradius(), &mut query_buffer);
+    pub fn get_bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
 
-                for n in query_buffer.iter().map(|i| *i as usize) {
-                    let triangle = static_geometry.triangles.get(n).unwrap();
-                    body.solve_triangle_collision(&triangle, n, handle);
-                }
-            }
-        }
+    /// Sets what happens with bodies which left world bounds, default is [`OutOfBoundsAction::Remove`].
+    pub fn set_out_of_bounds_action(&mut self, action: OutOfBoundsAction) {
+        self.out_of_bounds_action = action;
+    }
 
-        self.bodies.retain(|body| body.lifetime.is_none() || body.lifetime.unwrap() > 0.0);
+    pub fn get_out_of_bounds_action(&self) -> OutOfBoundsAction {
+        self.out_of_bounds_action
     }
 
-    pub fn ray_cast(&self, ray: &Ray, options: RayCastOptions, result: &mut Vec<RayCastResult>) -> bool {
-        result.clear();
+    /// Returns handles of bodies which left world bounds during last step, handles of
+    /// removed bodies are no longer valid.
+    pub fn get_out_of_bounds_bodies(&self) -> &[Handle<RigidBody>] {
+        &self.out_of_bounds_bodies
+    }
 
-        // Check bodies
-        if !options.ignore_bodies {
-            for body_index in 0..self.bodies.get_capacity() {
-                let body = if let Some(body) = self.bodies.at(body_index) {
-                    body
-                } else {
-                    continue;
-                };
+    /// Returns list of bodies that were removed during last step because their lifetime
+    /// has expired, they left world bounds or their removal was requested through
+    /// [`removal_queue`](Self::removal_queue). Can be used to spawn effects at final position
+    /// of body, list is cleared at the beginning of each step.
+    pub fn get_expired_bodies(&self) -> &[ExpiredBody] {
+        &self.expired_bodies
+    }
 
-                let body_handle = self.bodies.handle_from_index(body_index);
+    /// Sets contact filter which will be called for each generated contact before
+    /// collision response. Filter may modify normal and depth of contact or reject
+    /// it completely by returning `false` (useful for one-way platforms and such).
+    /// Filter is not serialized and not cloned together with physics.
+    pub fn set_contact_modification(&mut self, filter: Box<dyn Fn(&mut ContactInfo) -> bool + Send>) {
+        self.contact_filter = Some(filter);
+    }
 
-                match &body.shape {
-                    ConvexShape::Dummy => {}
-                    ConvexShape::Box(box_shape) => {
-                        if let Some(points) = ray.box_intersection_points(&box_shape.get_min(), &box_shape.get_max()) {
-                            for point in points.iter() {
-                                result.push(RayCastResult {
-                                    kind: HitKind::Body(body_handle),
-                                    position: *point,
-                                    normal: *point - body.position, // TODO: Fix normal
-                                    sqr_distance: point.sqr_distance(&ray.origin),
-                                })
-                            }
-                        }
-                    }
-                    ConvexShape::Sphere(sphere_shape) => {
-                        if let Some(points) = ray.sphere_intersection_points(&body.position, sphere_shape.radius) {
-                            for point in points.iter() {
-                                result.push(RayCastResult {
-                                    kind: HitKind::Body(body_handle),
-                                    position: *point,
-                                    normal: *point - body.position,
-                                    sqr_distance: point.sqr_distance(&ray.origin),
-                                })
-                            }
-                        }
-                    }
-                    ConvexShape::Capsule(capsule_shape) => {
-                        let (pa, pb) = capsule_shape.get_cap_centers();
-                        let pa = pa + body.position;
-                        let pb = pb + body.position;
+    pub fn remove_contact_modification(&mut self) {
+        self.contact_filter = None;
+    }
 
-                        if let Some(points) = ray.capsule_intersection(&pa, &pb, capsule_shape.get_radius()) {
-                            for point in points.iter() {
-                                result.push(RayCastResult {
-                                    kind: HitKind::Body(body_handle),
-                                    position: *point,
-                                    normal: *point - body.position,
-                                    sqr_distance: point.sqr_distance(&ray.origin),
-                                })
-                            }
-                        }
-                    }
-                    ConvexShape::Triangle(triangle_shape) => {
-                        if let Some(point) = ray.triangle_intersection(&triangle_shape.vertices) {
-                            result.push(RayCastResult {
-                                kind: HitKind::Body(body_handle),
-                                position: point,
-                                normal: triangle_shape.get_normal().unwrap(),
-                                sqr_distance: point.sqr_distance(&ray.origin),
-                            })
-                        }
-                    }
-                    ConvexShape::PointCloud(_point_cloud) => {
-                        // TODO: Implement this. This requires to build convex hull from point cloud first
-                        // i.e. by gift wrapping algorithm or some other more efficient algorithms -
-                        // https://dccg.upc.edu/people/vera/wp-content/uploads/2014/11/GA2014-ConvexHulls3D-Roger-Hernando.pdf
-                    }
-                }
-            }
-        }
+    /// Sets response filter which is called for each contact accepted by contact filter
+    /// during collision solving. Returning `false` keeps the contact (it is reported as
+    /// usual) but skips position correction for this contact only, so bodies pass through
+    /// each other - useful for invincibility frames and pass-through abilities. This is
+    /// finer than [`CollisionFlags::DISABLE_COLLISION_RESPONSE`] which works for every
+    /// contact of body. Filter is not serialized and not cloned together with physics.
+    pub fn set_response_filter(&mut self, filter: Box<ResponseFilter>) {
+        self.response_filter = Some(filter);
+    }
 
-        // Check static geometries
-        if !options.ignore_static_geometries {
-            for (handle, geom) in self.static_geoms.pair_iter() {
-                let mut query_buffer = self.query_buffer.borrow_mut();
-                geom.octree.ray_query(ray, &mut query_buffer);
+    pub fn remove_response_filter(&mut self) {
+        self.response_filter = None;
+    }
 
-                for triangle_index in query_buffer.iter().map(|i| *i as usize) {
-                    let triangle = geom.triangles.get(triangle_index).unwrap();
-                    if let Some(point) = ray.triangle_intersection(&triangle.points) {
-                        result.push(RayCastResult {
-                            kind: HitKind::StaticTriangle {
-                                static_geometry: handle,
-                                triangle_index,
-                            },
-                            position: point,
-                            normal: triangle.plane.normal,
-                            sqr_distance: point.sqr_distance(&ray.origin),
-                        })
+    /// Sets amount of steps for which contacts of each body will be remembered.
+    #[cfg(feature = "debug-history")]
+    pub fn set_contact_history_depth(&mut self, depth: usize) {
+        self.contact_history_depth = depth;
+    }
+
+    #[cfg(feature = "debug-history")]
+    pub fn get_contact_history_depth(&self) -> usize {
+        self.contact_history_depth
+    }
+
+    /// Sets relaxation factor of contact solver, each penetration correction is multiplied
+    /// by it. Values above 1.0 (over-relaxation) make stacks converge faster but may add
+    /// energy and cause jitter or explosions, values below 1.0 (under-relaxation) damp
+    /// corrections, which reduces bouncing of resting bodies but leaves some penetration
+    /// for several steps. Value is clamped to `[0.1; 2.0]`, default is 1.0.
+    pub fn set_solver_relaxation(&mut self, relaxation: f32) {
+        self.solver_relaxation = relaxation.clamp(0.1, 2.0);
+    }
+
+    pub fn get_solver_relaxation(&self) -> f32 {
+        self.solver_relaxation
+    }
+
+    /// Sets amount of passes of collision solver per step. Single pass pushes each body
+    /// out of its neighbours once, so in a row of touching bodies push of one body may
+    /// shove it into next one, and penetration propagates along the row over several
+    /// steps. Each extra pass re-evaluates penetrations after previous pushes, so chains
+    /// and stacks resolve within one step at the cost of extra collision tests. Contact
+    /// filter is called on each pass. Passes stop early when penetrations fall below
+    /// [`SOLVER_TOLERANCE`](Self::SOLVER_TOLERANCE). Value is clamped to be at least one,
+    /// default is one.
+    pub fn set_solver_iterations(&mut self, iterations: usize) {
+        self.solver_iterations = iterations.max(1);
+    }
+
+    pub fn get_solver_iterations(&self) -> usize {
+        self.solver_iterations
+    }
+
+    /// Sets speed (in units per second) above which body is integrated and collided in
+    /// substeps: its motion over step is split into segments no longer than its size, and
+    /// body is moved segment by segment, so fast body can't pass through thin obstacles.
+    /// Motion into obstacle which body hits is cancelled, motion along it continues, so
+    /// fast body slides at its own speed. Slow bodies take one step as usual. `None`
+    /// disables substeps, it is default.
+    pub fn set_substep_speed_threshold(&mut self, threshold: Option<f32>) {
+        self.substep_speed_threshold = threshold.map(|threshold| threshold.abs());
+    }
+
+    pub fn get_substep_speed_threshold(&self) -> Option<f32> {
+        self.substep_speed_threshold
+    }
+
+    /// Sets maximum amount of substeps per step of single fast body, clamped to be at
+    /// least one. Default is [`DEFAULT_MAX_SUBSTEPS`](Self::DEFAULT_MAX_SUBSTEPS).
+    pub fn set_max_substeps(&mut self, max_substeps: usize) {
+        self.max_substeps = max_substeps.max(1);
+    }
+
+    pub fn get_max_substeps(&self) -> usize {
+        self.max_substeps
+    }
+
+    /// Sets approach speed (in units per second) below which impacts do not bounce
+    /// regardless of restitution of bodies, so bouncy body which comes to rest settles
+    /// instead of micro-bouncing forever. Default is [`DEFAULT_RESTITUTION_THRESHOLD`](Self::DEFAULT_RESTITUTION_THRESHOLD),
+    /// see [`RigidBody::set_restitution`].
+    pub fn set_restitution_threshold(&mut self, threshold: f32) {
+        self.restitution_threshold = threshold.max(0.0);
+    }
+
+    pub fn get_restitution_threshold(&self) -> f32 {
+        self.restitution_threshold
+    }
+
+    /// Sets amount of steps after which contact lists of bodies are shrunk to
+    /// [`RESERVED_CONTACTS_PER_BODY`](Self::RESERVED_CONTACTS_PER_BODY). Contact lists are
+    /// cleared but keep their memory between steps, so fluctuating amount of contacts
+    /// does not cause reallocations, but body which once had many contacts holds memory
+    /// for them. Shrinking reclaims that memory at the cost of reallocations on next
+    /// steps for bodies which still have many contacts. `None` (default) never shrinks.
+    pub fn set_contact_shrink_interval(&mut self, interval: Option<u32>) {
+        self.contact_shrink_interval = interval.map(|interval| interval.max(1));
+        self.steps_since_contact_shrink = 0;
+    }
+
+    pub fn get_contact_shrink_interval(&self) -> Option<u32> {
+        self.contact_shrink_interval
+    }
+
+    /// Enables coarse sampled penetration search which is used when EPA fails to converge
+    /// on near-degenerated pairs of shapes, so such pairs still get a contact instead of
+    /// none. Disabled by default, see [`gjk_epa::sampled_penetration_info`].
+    pub fn set_epa_fallback(&mut self, enabled: bool) {
+        self.epa_fallback = enabled;
+    }
+
+    pub fn is_epa_fallback_enabled(&self) -> bool {
+        self.epa_fallback
+    }
+
+    /// Sets separation which solver keeps between resting bodies and surfaces they rest
+    /// on. Bodies are treated as if they were inflated by clearance, so contact persists
+    /// while body hovers within clearance over surface, and contact point does not sit
+    /// exactly at surface, which reduces sticking and jitter of bodies with high friction.
+    /// Clearance also counts towards contact depth. Negative values are clamped to zero,
+    /// default is zero.
+    pub fn set_resting_clearance(&mut self, clearance: f32) {
+        self.resting_clearance = clearance.max(0.0);
+    }
+
+    pub fn get_resting_clearance(&self) -> f32 {
+        self.resting_clearance
+    }
+
+    /// Sets distance at which shapes generate contacts before they actually touch. Solver
+    /// pushes bodies apart until they exactly touch, so without margin resting body loses
+    /// its contacts on next step, falls a tiny bit and flickers between contact and no
+    /// contact. Contacts within margin have zero depth and do not push bodies, unlike
+    /// [`resting clearance`](Self::set_resting_clearance) which keeps bodies apart. Default
+    /// is zero - contacts are generated only for penetrating shapes.
+    pub fn set_contact_margin(&mut self, margin: f32) {
+        self.contact_margin = margin.max(0.0);
+    }
+
+    pub fn get_contact_margin(&self) -> f32 {
+        self.contact_margin
+    }
+
+    /// Replaces broad phase of world, it is used to find pairs of bodies to test for
+    /// collisions and by queries. Default is [`SweepAndPrune`]. Broad phase is filled with
+    /// bodies right away. Broad phase is not serialized or cloned, world gets default one
+    /// instead.
+    pub fn set_broadphase(&mut self, broadphase: Box<dyn Broadphase>) {
+        self.broadphase = broadphase;
+        self.broadphase_bodies.clear();
+        self.sync_broadphase();
+    }
+
+    pub fn get_broadphase(&self) -> &dyn Broadphase {
+        self.broadphase.as_ref()
+    }
+
+    /// Returns true if body is tracked by broad phase. Query-only bodies are tracked too,
+    /// so queries find them, but they are never in solve order and so never form pairs.
+    fn is_broadphase_body(body: &RigidBody) -> bool {
+        body.enabled
+    }
+
+    /// Removes bodies which were removed or disabled from broad phase.
+    fn untrack_stale_bodies(&mut self) {
+        let bodies = &self.bodies;
+        let broadphase = &mut self.broadphase;
+        self.broadphase_bodies.retain(|handle| {
+            let keep = bodies.try_borrow(*handle).is_some_and(Self::is_broadphase_body);
+            if !keep {
+                broadphase.remove(*handle);
+            }
+            keep
+        });
+    }
+
+    /// Starts tracking of body by broad phase or updates its box.
+    fn track_body(&mut self, handle: Handle<RigidBody>) {
+        let body = self.bodies.borrow(handle);
+        if !Self::is_broadphase_body(body) {
+            return;
+        }
+        if self.broadphase_bodies.insert(handle) {
+            self.broadphase.insert(handle, body.get_aabb());
+        } else {
+            self.broadphase.update(handle, body.get_aabb());
+        }
+    }
+
+    fn untrack_body(&mut self, handle: Handle<RigidBody>) {
+        if self.broadphase_bodies.remove(&handle) {
+            self.broadphase.remove(handle);
+        }
+    }
+
+    /// Syncs broad phase with current bounding boxes of bodies, so queries made between
+    /// steps (see [`apply_radial_impulse`](Self::apply_radial_impulse)) can use it.
+    fn sync_broadphase(&mut self) {
+        self.untrack_stale_bodies();
+        for index in 0..self.bodies.get_capacity() {
+            if self.bodies.at(index).is_some() {
+                self.track_body(self.bodies.handle_from_index(index));
+            }
+        }
+    }
+
+    /// Returns indices of bodies which take part in contact solving, sorted by position so
+    /// results do not depend on order in which bodies were added. Buffer is taken from
+    /// `solve_order` and must be put back after use.
+    fn take_solve_order(&mut self) -> Vec<u32> {
+        let mut solve_order = std::mem::take(&mut self.solve_order);
+        solve_order.clear();
+        solve_order.extend(self.bodies.pair_iter()
+            .filter(|(_, body)| body.enabled && !body.collision_flags.contains(CollisionFlags::QUERY_ONLY))
+            .map(|(handle, _)| handle.index()));
+        let bodies = &self.bodies;
+        solve_order.sort_by(|a, b| {
+            let (pa, pb) = (bodies.at(*a as usize).unwrap().position, bodies.at(*b as usize).unwrap().position);
+            pa.x.total_cmp(&pb.x)
+                .then_with(|| pa.y.total_cmp(&pb.y))
+                .then_with(|| pa.z.total_cmp(&pb.z))
+                .then_with(|| a.cmp(b))
+        });
+        solve_order
+    }
+
+    /// Syncs broad phase with bodies in solve order and collects candidates of each body
+    /// from pairs found by broad phase. Boxes are swept and enlarged by clearance and
+    /// contact margin, so they contain every speculative contact of body.
+    fn find_candidates(&mut self, solve_order: &[u32], enlargement: f32) {
+        self.untrack_stale_bodies();
+
+        self.solve_ranks.clear();
+        self.solve_ranks.resize(self.bodies.get_capacity(), u32::MAX);
+        let enlargement = Vec3::new(enlargement, enlargement, enlargement);
+        for (rank, index) in solve_order.iter().enumerate() {
+            self.solve_ranks[*index as usize] = rank as u32;
+            let handle = self.bodies.handle_from_index(*index as usize);
+            let body = self.bodies.at(*index as usize).unwrap();
+            let mut aabb = body.get_swept_aabb();
+            if !body.shape.is_half_space() {
+                aabb.min -= enlargement;
+                aabb.max += enlargement;
+            }
+            if self.broadphase_bodies.insert(handle) {
+                self.broadphase.insert(handle, aabb);
+            } else {
+                self.broadphase.update(handle, aabb);
+            }
+        }
+
+        self.broadphase_pairs.clear();
+        self.broadphase.query_pairs(&mut self.broadphase_pairs);
+
+        self.candidate_links.clear();
+        for (a, b) in self.broadphase_pairs.iter() {
+            let (rank_a, rank_b) = match (self.solve_ranks.get(a.index() as usize), self.solve_ranks.get(b.index() as usize)) {
+                (Some(rank_a), Some(rank_b)) if *rank_a != u32::MAX && *rank_b != u32::MAX && a != b => (*rank_a, *rank_b),
+                _ => continue,
+            };
+            self.candidate_links.push((rank_a, rank_b));
+            self.candidate_links.push((rank_b, rank_a));
+        }
+        // Candidates are visited in solve order, so results do not depend on order of
+        // pairs reported by broad phase.
+        self.candidate_links.sort_unstable();
+        self.candidate_links.dedup();
+
+        self.candidates.clear();
+        self.candidate_offsets.clear();
+        let mut links = self.candidate_links.iter().peekable();
+        for rank in 0..solve_order.len() as u32 {
+            self.candidate_offsets.push(self.candidates.len());
+            while let Some((_, other)) = links.next_if(|(body, _)| *body == rank) {
+                self.candidates.push(solve_order[*other as usize]);
+            }
+        }
+        self.candidate_offsets.push(self.candidates.len());
+    }
+
+    /// Collects pairs of bodies bound by unbroken welds, such bodies don't collide.
+    fn gather_welded_pairs(&mut self) {
+        self.welded_pairs.clear();
+        for constraint in self.constraints.iter() {
+            if let Constraint::Weld(weld) = constraint {
+                if !weld.broken {
+                    self.welded_pairs.insert((weld.body_a, weld.body_b));
+                    self.welded_pairs.insert((weld.body_b, weld.body_a));
+                }
+            }
+        }
+    }
+
+    fn penetration_settings(&self) -> PenetrationSettings {
+        PenetrationSettings {
+            epa_fallback: self.epa_fallback,
+            clearance: self.resting_clearance,
+            up: self.up_vector,
+        }
+    }
+
+    /// Sets contact hysteresis: body which has lost all its contacts is still considered
+    /// touching (and gets surface friction instead of air friction) for given amount of
+    /// steps, as long as it stays within given distance from position where contact was
+    /// lost. This smooths flickering of friction for bodies at the edge of touching.
+    /// Zero steps (default) disables hysteresis.
+    pub fn set_contact_hysteresis(&mut self, steps: u32, distance: f32) {
+        self.contact_hysteresis_steps = steps;
+        self.contact_hysteresis_distance = distance.max(0.0);
+    }
+
+    /// Returns amount of steps and distance of contact hysteresis.
+    pub fn get_contact_hysteresis(&self) -> (u32, f32) {
+        (self.contact_hysteresis_steps, self.contact_hysteresis_distance)
+    }
+
+    /// Sets contact reduction: contacts of body with same other party (body or static
+    /// geometry) which are closer than given distance and which normals differ by no more
+    /// than given angle (in degrees) are merged into one contact with averaged position
+    /// and normal. This removes clusters of nearly identical contacts which are generated
+    /// by meshes, leaving less contacts for friction and contact consumers. Zero distance
+    /// (default) disables reduction.
+    pub fn set_contact_reduction(&mut self, distance: f32, angle_deg: f32) {
+        self.contact_reduction_distance = distance.max(0.0);
+        self.contact_reduction_angle = angle_deg.clamp(0.0, 180.0);
+    }
+
+    /// Returns distance and angle (in degrees) of contact reduction.
+    pub fn get_contact_reduction(&self) -> (f32, f32) {
+        (self.contact_reduction_distance, self.contact_reduction_angle)
+    }
+
+    /// Sets queue into which contact begin events will be pushed. Event is generated when
+    /// body starts touching other body or static geometry (contacts with different triangles
+    /// of same geometry are treated as one). Queue can be drained by other thread, so
+    /// events can be consumed without synchronizing with physics. Queue is not serialized
+    /// and not cloned together with physics. `None` (default) disables events.
+    pub fn set_contact_event_queue(&mut self, queue: Option<ContactEventQueue>) {
+        self.contact_event_queue = queue;
+    }
+
+    pub fn get_contact_event_queue(&self) -> Option<&ContactEventQueue> {
+        self.contact_event_queue.as_ref()
+    }
+
+    /// Returns pairs of bodies which started touching each other during last step, each
+    /// pair is listed once. List is cleared at the beginning of each step.
+    pub fn new_contacts_this_step(&self) -> &[(Handle<RigidBody>, Handle<RigidBody>)] {
+        &self.new_contacts
+    }
+
+    /// Returns up to `n` strongest contacts of last step sorted by impulse (see [`Contact::impulse`]),
+    /// strongest first, as `(body, other body, contact)`. Other body is `Handle::NONE`
+    /// for contacts with static geometry. Contact of two bodies is listed once. Useful to
+    /// pick loudest impacts when there are too many contacts to play sound for each.
+    pub fn strongest_contacts(&self, n: usize) -> Vec<(Handle<RigidBody>, Handle<RigidBody>, &Contact)> {
+        let mut contacts: Vec<(Handle<RigidBody>, Handle<RigidBody>, &Contact)> = self.bodies.pair_iter()
+            .flat_map(|(handle, body)| body.contacts.iter()
+                // Both bodies have contact with each other, take one of body with smaller index.
+                .filter(move |contact| contact.body.is_none() || contact.body.index() > handle.index())
+                .map(move |contact| (handle, contact.body, contact)))
+            .collect();
+        contacts.sort_by(|a, b| b.2.impulse.partial_cmp(&a.2.impulse).unwrap_or(Ordering::Equal));
+        contacts.truncate(n);
+        contacts
+    }
+
+    /// Sets budget of collision solver. When budget is exhausted solver stops, remaining
+    /// penetrations are left for next step, which will start from body at which solver
+    /// has stopped, so every body gets resolved eventually. This trades accuracy for
+    /// predictable step time on frame spikes. Default is [`Budget::Unlimited`].
+    pub fn set_solver_budget(&mut self, budget: Budget) {
+        self.solver_budget = budget;
+    }
+
+    pub fn get_solver_budget(&self) -> Budget {
+        self.solver_budget
+    }
+
+    /// Sets plane to which motion of every rigid body is constrained, useful for 2.5D games.
+    /// Position and velocity of each body are projected onto the plane after integration
+    /// and after collision response, so bodies can move only along the plane. Soft bodies
+    /// are not affected. `None` (default) removes the constraint.
+    pub fn set_constraint_plane(&mut self, plane: Option<Plane>) {
+        self.constraint_plane = plane;
+    }
+
+    pub fn get_constraint_plane(&self) -> Option<Plane> {
+        self.constraint_plane
+    }
+
+    /// Returns statistics of last step.
+    pub fn get_step_stats(&self) -> StepStats {
+        self.step_stats
+    }
+
+    /// Analyzes contacts of last step: counts resting bodies and bodies which are left
+    /// penetrating, penetrations are measured again with current positions of bodies.
+    /// Useful to find source of jitter in scene, bodies of settled scene should all be
+    /// resting with penetration below [`SOLVER_TOLERANCE`](Self::SOLVER_TOLERANCE).
+    pub fn stability_report(&self) -> StabilityReport {
+        let mut report = StabilityReport::default();
+        let mut scratch = EpaScratch::default();
+        let settings = self.penetration_settings();
+        let resting_speed = Self::RESTING_SPEED * self.step_delta_time;
+        let mut body_count = 0;
+        let mut contact_count = 0;
+        for body in self.bodies.iter().filter(|body| body.enabled && !body.collision_flags.contains(CollisionFlags::QUERY_ONLY)) {
+            body_count += 1;
+            contact_count += body.contacts.len();
+            if body.contacts.is_empty() {
+                continue;
+            }
+
+            if body.get_velocity().len() <= resting_speed {
+                report.resting_bodies += 1;
+            }
+
+            let mut depth = 0.0f32;
+            for contact in body.contacts.iter() {
+                let penetration = if let Some(other) = self.bodies.try_borrow(contact.body) {
+                    body.body_penetration(other, &mut scratch, settings)
+                } else if let Some(static_geom) = self.static_geoms.try_borrow(contact.static_geom) {
+                    static_geom.triangles.get(contact.triangle_index as usize)
+                        .and_then(|triangle| body.triangle_penetration(triangle, static_geom.feature_normals.get(contact.triangle_index as usize), &mut scratch, settings))
+                } else {
+                    None
+                };
+                if let Some((_, _, contact_depth)) = penetration {
+                    depth = depth.max(contact_depth);
+                }
+            }
+            if depth > Self::SOLVER_TOLERANCE {
+                report.penetrating_bodies += 1;
+            }
+            report.max_penetration = report.max_penetration.max(depth);
+        }
+        if body_count > 0 {
+            report.average_contacts = contact_count as f32 / body_count as f32;
+        }
+        report
+    }
+
+    /// Sets maximum speed (in units per second) of any body. This is a safety clamp which
+    /// prevents bodies from exploding after numerical errors, it is not meant to be
+    /// gameplay speed limit (see [`RigidBody::set_speed_limit`]), so it should be way
+    /// above speed of fastest expected body. Non-finite velocities are reset to zero.
+    /// Default is 1000 units per second.
+    pub fn set_max_linear_velocity(&mut self, max_linear_velocity: f32) {
+        self.max_linear_velocity = max_linear_velocity.abs();
+    }
+
+    pub fn get_max_linear_velocity(&self) -> f32 {
+        self.max_linear_velocity
+    }
+
+    /// Sets time step used by [`update`](Self::update). Non-positive values are ignored.
+    pub fn set_fixed_time_step(&mut self, time_step: f32) {
+        if time_step > 0.0 {
+            self.fixed_time_step = time_step;
+        }
+    }
+
+    pub fn get_fixed_time_step(&self) -> f32 {
+        self.fixed_time_step
+    }
+
+    /// Sets multiplier of frame time passed to [`update`](Self::update), can be used for
+    /// slow motion. Fixed time step stays the same, so simulation stays deterministic,
+    /// just fewer steps are done per frame. Zero pauses simulation, negative values are
+    /// ignored.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        if time_scale >= 0.0 {
+            self.time_scale = time_scale;
+        }
+    }
+
+    pub fn get_time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Advances simulation by given frame time (scaled by time scale) using as many
+    /// fixed steps as fit into accumulated time, leftover time is kept for next update.
+    /// Returns amount of steps done. Expired bodies of every step are collected into
+    /// [`get_expired_bodies`](Self::get_expired_bodies).
+    pub fn update(&mut self, frame_time: f32) -> usize {
+        self.time_accumulator += frame_time * self.time_scale;
+
+        let mut expired_bodies = Vec::new();
+        let mut steps = 0;
+        while self.time_accumulator >= self.fixed_time_step && steps < Self::MAX_STEPS_PER_UPDATE {
+            self.time_accumulator -= self.fixed_time_step;
+            expired_bodies.extend_from_slice(self.step(self.fixed_time_step));
+            steps += 1;
+        }
+        if steps == Self::MAX_STEPS_PER_UPDATE {
+            self.time_accumulator = self.time_accumulator.min(self.fixed_time_step);
+        }
+
+        if steps > 0 {
+            self.expired_bodies = expired_bodies;
+        }
+
+        steps
+    }
+
+    /// Advances simulation by given time. Returns bodies that were removed during this
+    /// step, their handles are no longer valid (same list as [`get_expired_bodies`](Self::get_expired_bodies)).
+    ///
+    /// Step consists of three phases: [`integrate`](Self::integrate), [`detect_collisions`](Self::detect_collisions)
+    /// and [`resolve`](Self::resolve), they can be called manually to run custom logic in
+    /// between.
+    pub fn step(&mut self, delta_time: f32) -> &[ExpiredBody] {
+        self.integrate(delta_time);
+        self.detect_collisions();
+        self.resolve(delta_time)
+    }
+
+    /// First phase of step. Moves every body and soft body according to its velocity and
+    /// acceleration, clears contacts of bodies and list of expired bodies. After this phase
+    /// bodies may intersect each other and static geometry, their positions can be
+    /// freely modified (velocity is difference between position and last position).
+    pub fn integrate(&mut self, delta_time: f32) {
+        self.expired_bodies.clear();
+        self.out_of_bounds_bodies.clear();
+        self.new_contacts.clear();
+        self.step_delta_time = delta_time;
+
+        if !self.enabled {
+            return;
+        }
+
+        let dt2 = delta_time * delta_time;
+        let substep_distance = self.substep_speed_threshold.map(|threshold| threshold * delta_time);
+
+        let shrink_contacts = match self.contact_shrink_interval {
+            Some(interval) => {
+                self.steps_since_contact_shrink += 1;
+                if self.steps_since_contact_shrink >= interval {
+                    self.steps_since_contact_shrink = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        };
+
+        self.attractors.clear();
+        for (handle, body) in self.bodies.pair_iter().filter(|(_, body)| body.enabled) {
+            if let Some(attractor) = body.attractor {
+                self.attractors.push((handle, body.position, attractor));
+            }
+        }
+        // Pulls are added in order of attractors, so sum does not depend on order in which
+        // broad phase returns bodies.
+        for (attractor_handle, center, attractor) in self.attractors.iter() {
+            let extents = Vec3::new(attractor.radius, attractor.radius, attractor.radius);
+            self.attracted_bodies.clear();
+            self.broadphase.query_aabb(&AxisAlignedBoundingBox::from_min_max(*center - extents, *center + extents), &mut self.attracted_bodies);
+            for handle in self.attracted_bodies.iter().filter(|handle| *handle != attractor_handle) {
+                if let Some(body) = self.bodies.try_borrow_mut(*handle) {
+                    // Query-only bodies are not integrated, so pull would only pile up.
+                    if Self::is_broadphase_body(body) && !body.collision_flags.contains(CollisionFlags::QUERY_ONLY) {
+                        body.acceleration += attractor.evaluate(*center, body.position);
                     }
                 }
             }
         }
 
-        if options.sort_results {
-            result.sort_by(|a, b| {
-                if a.sqr_distance > b.sqr_distance {
-                    Ordering::Greater
-                } else if a.sqr_distance < b.sqr_distance {
-                    Ordering::Less
+        for body in self.bodies.iter_mut() {
+            if !body.enabled {
+                continue;
+            }
+
+            if let Some(ref mut lifetime) = body.lifetime {
+                *lifetime -= delta_time;
+            }
+
+            if body.collision_flags.contains(CollisionFlags::QUERY_ONLY) {
+                body.contacts.clear();
+                continue;
+            }
+
+            let gravity = match self.gravity_field.as_ref() {
+                Some(field) => field.evaluate(body.position),
+                None => body.gravity,
+            };
+            let gravity = body.scale_gravity(gravity, self.up_vector);
+            body.acceleration += gravity;
+            if let Some(grab) = body.grab {
+                body.acceleration += grab.force(body, gravity, delta_time);
+            }
+            let substeps = Self::speed_substeps(body, substep_distance, self.max_substeps);
+            body.verlet_in_substeps(dt2, Self::AIR_FRICTION, substeps as u32);
+            let surface_velocity = Self::contact_surface_velocity(&self.static_geoms, body);
+            if surface_velocity != Vec3::ZERO {
+                body.apply_surface_velocity(surface_velocity.scale(delta_time));
+            }
+            body.clamp_velocity(self.max_linear_velocity * delta_time);
+            if let Some(plane) = self.constraint_plane.as_ref() {
+                body.project_onto_plane(plane);
+            }
+
+            body.contacts.clear();
+            if shrink_contacts {
+                body.contacts.shrink_to(Self::RESERVED_CONTACTS_PER_BODY);
+            }
+        }
+
+        for soft_body in self.soft_bodies.iter_mut() {
+            soft_body.verlet(dt2, Self::AIR_FRICTION);
+        }
+    }
+
+    /// Makes bodies with restitution bounce off parties of their strongest contacts. Solver
+    /// only pushes bodies out, which stops approach, bounce is added by changing last
+    /// position, so Verlet integration turns it into velocity.
+    fn apply_restitution(&mut self) {
+        let threshold = self.restitution_threshold * self.step_delta_time;
+        self.bounces.clear();
+        for (handle, body) in self.bodies.pair_iter().filter(|(_, body)| body.enabled) {
+            if body.shape.is_half_space() || body.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) {
+                continue;
+            }
+            let strongest = body.contacts.iter()
+                .filter(|contact| contact.impulse > threshold)
+                .max_by(|a, b| a.impulse.total_cmp(&b.impulse));
+            if let Some(contact) = strongest {
+                let (restitution, other_velocity, share) = match self.bodies.try_borrow(contact.body) {
+                    Some(other) => (body.restitution.max(other.restitution), other.get_velocity(),
+                                    if other.shape.is_half_space() { 1.0 } else { 0.5 }),
+                    None => (body.restitution, Vec3::ZERO, 1.0),
+                };
+                let normal_velocity = (body.get_velocity() - other_velocity).dot(&contact.normal);
+                let change = restitution * contact.impulse - normal_velocity;
+                if restitution > 0.0 && change > 0.0 {
+                    self.bounces.push((handle.index(), contact.normal.scale(change * share)));
+                }
+            }
+        }
+        for (index, change) in self.bounces.iter() {
+            let body = self.bodies.at_mut(*index as usize).unwrap();
+            body.last_position -= *change;
+        }
+    }
+
+    /// Finds contacts of every body with other bodies and static geometry at their current
+    /// positions without moving anything, so overlaps can be inspected before deciding how
+    /// to respond to them. Pairs are found by broad phase and tested by same narrow phase as
+    /// in [`step`](Self::step), both bodies of pair get contact. Previous
+    /// contacts of bodies are replaced, contact filter is applied as usual, but no contact
+    /// events are generated.
+    pub fn detect_only(&mut self) {
+        // See detect_collisions.
+        let other_bodies = unsafe { &mut *(&mut self.bodies as *mut Pool<RigidBody>) };
+
+        for body in self.bodies.iter_mut() {
+            body.contacts.clear();
+        }
+        let solve_order = self.take_solve_order();
+        let penetration = self.penetration_settings();
+        self.find_candidates(&solve_order, penetration.clearance + self.contact_margin);
+        self.gather_welded_pairs();
+
+        let mut context = SolverContext {
+            scratch: &mut self.epa_scratch,
+            contact_filter: self.contact_filter.as_deref(),
+            response_filter: None,
+            apply_response: false,
+            contact_margin: self.contact_margin,
+            relaxation: self.solver_relaxation,
+            penetration,
+            iteration: 0,
+            max_depth: 0.0,
+            welded_pairs: &self.welded_pairs,
+        };
+
+        // Nothing moves, so each pair is tested once by body that comes first in solve order,
+        // both parties get contact.
+        let solve_ranks = &self.solve_ranks;
+        let mut later_candidates = Vec::new();
+        for (rank, index) in solve_order.iter().map(|i| *i as usize).enumerate() {
+            later_candidates.clear();
+            later_candidates.extend(self.candidates[self.candidate_offsets[rank]..self.candidate_offsets[rank + 1]].iter()
+                .filter(|other| solve_ranks[**other as usize] as usize > rank));
+            let body_handle = self.bodies.handle_from_index(index);
+            let body = self.bodies.at_mut(index).unwrap();
+            Self::solve_body_collisions(body, body_handle, other_bodies, &later_candidates, &self.static_geoms, &self.query_buffer, &mut context);
+        }
+
+        self.solve_order = solve_order;
+    }
+
+    /// Returns amount of substeps of body which travels more than `substep_distance` per step.
+    fn speed_substeps(body: &RigidBody, substep_distance: Option<f32>, max_substeps: usize) -> usize {
+        match substep_distance {
+            Some(distance) if !body.shape.is_half_space() => {
+                let travel = body.get_velocity().len();
+                if travel > distance {
+                    let segment = (body.shape.circumradius() + body.margin).max(Self::TOI_TOLERANCE);
+                    ((travel / segment).ceil() as usize).clamp(1, max_substeps)
                 } else {
-                    Ordering::Equal
+                    1
                 }
-            })
+            }
+            _ => 1,
         }
+    }
 
-        !result.is_empty()
+    /// Solves collisions of single body with given other bodies (in given order) and with
+    /// static geometry.
+    fn solve_body_collisions(body: &mut RigidBody,
+                             body_handle: Handle<RigidBody>,
+                             other_bodies: &mut Pool<RigidBody>,
+                             solve_order: &[u32],
+                             static_geoms: &Pool<StaticGeometry>,
+                             query_buffer: &RefCell<Vec<u32>>,
+                             context: &mut SolverContext) {
+        for other_index in solve_order.iter().map(|i| *i as usize) {
+            let other_body_handle = other_bodies.handle_from_index(other_index);
+            let other_body = other_bodies.at_mut(other_index).unwrap();
+            // Enforce borrowing rules at runtime.
+            if !std::ptr::eq(body, other_body) &&
+                ((other_body.collision_group & body.collision_mask) != 0) &&
+                ((body.collision_group & other_body.collision_mask) != 0) &&
+                !context.welded_pairs.contains(&(body_handle, other_body_handle)) {
+                body.solve_rigid_body_collision(body_handle, other_body, other_body_handle, context);
+            }
+        }
+
+        // Half-space is infinite, it would intersect any static geometry.
+        if body.shape.is_half_space() {
+            return;
+        }
+
+        for (handle, static_geometry) in static_geoms.pair_iter() {
+            let mut query_buffer = query_buffer.borrow_mut();
+            let radius = body.shape.circumradius() + body.margin + context.penetration.clearance + context.contact_margin;
+            static_geometry.octree.sphere_query(body.position, radius, &mut query_buffer);
+
+            for n in query_buffer.iter().map(|i| *i as usize) {
+                let triangle = static_geometry.triangles.get(n).unwrap();
+                body.solve_triangle_collision(body_handle, triangle, static_geometry.feature_normals.get(n), n, handle, context);
+            }
+        }
+    }
+
+    /// Returns average tangential velocity of static surfaces which body touches.
+    fn contact_surface_velocity(static_geoms: &Pool<StaticGeometry>, body: &RigidBody) -> Vec3 {
+        let mut sum = Vec3::ZERO;
+        let mut count = 0;
+        for contact in body.contacts.iter().filter(|contact| contact.static_geom.is_some()) {
+            if let Some(static_geom) = static_geoms.try_borrow(contact.static_geom) {
+                let velocity = static_geom.surface_velocity;
+                sum += velocity - contact.normal.scale(velocity.dot(&contact.normal));
+                count += 1;
+            }
+        }
+        if count > 0 {
+            sum.scale(1.0 / count as f32)
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    /// Second phase of step, must be called after [`integrate`](Self::integrate). Finds
+    /// intersections of bodies with each other and with static geometry and fills contact
+    /// lists of bodies, only pairs of bodies found by broad phase are tested (see
+    /// [`set_broadphase`](Self::set_broadphase)). Solver is position based, so bodies are
+    /// pushed out of each other right away, contact filter is applied here too. Bodies are
+    /// solved in order which depends only on their state (position, then handle index for
+    /// bodies at same position), not on order of insertion or removal of bodies, so worlds
+    /// which contain same bodies produce same results.
+    pub fn detect_collisions(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        // Take second mutable reference to bodies, this is safe because:
+        // 1. We won't modify collection while iterating over it.
+        // 2. Simultaneous access to a body won't happen because of
+        //    pointer equality check down below.
+        let other_bodies = unsafe { &mut *(&mut self.bodies as *mut Pool<RigidBody>) };
+
+        let started = Instant::now();
+        let mut stats = StepStats::default();
+        let solve_order = self.take_solve_order();
+
+        let penetration = self.penetration_settings();
+        self.find_candidates(&solve_order, penetration.clearance + self.contact_margin);
+        self.gather_welded_pairs();
+
+        let mut context = SolverContext {
+            scratch: &mut self.epa_scratch,
+            contact_filter: self.contact_filter.as_deref(),
+            response_filter: self.response_filter.as_deref(),
+            apply_response: true,
+            contact_margin: self.contact_margin,
+            relaxation: self.solver_relaxation,
+            penetration,
+            iteration: 0,
+            max_depth: 0.0,
+            welded_pairs: &self.welded_pairs,
+        };
+
+        let step_delta_time = self.step_delta_time;
+        let substep_distance = self.substep_speed_threshold.map(|threshold| threshold * step_delta_time);
+        let max_substeps = self.max_substeps;
+
+        let body_count = solve_order.len();
+        // Body may be removed or taken out of solve order since then.
+        let first = match self.solve_ranks.get(self.solver_resume_body.index() as usize) {
+            Some(rank) if *rank != u32::MAX && self.bodies.is_valid_handle(self.solver_resume_body) => *rank as usize,
+            _ => 0,
+        };
+        self.solver_resume_body = Handle::NONE;
+
+        'passes: for iteration in 0..self.solver_iterations {
+            context.iteration = iteration;
+            context.max_depth = 0.0;
+            for k in 0..body_count {
+                let rank = (first + k) % body_count;
+                let index = solve_order[rank] as usize;
+                let candidates = &self.candidates[self.candidate_offsets[rank]..self.candidate_offsets[rank + 1]];
+                let body_handle = self.bodies.handle_from_index(index);
+                let body = self.bodies.at_mut(index).unwrap();
+
+                if self.solver_budget.is_exhausted(stats.solver_iterations, started) {
+                    stats.budget_exhausted = true;
+                    self.solver_resume_body = body_handle;
+                    break 'passes;
+                }
+                stats.solver_iterations += 1;
+
+                // Own substeps of body split its motion too, so contacts are found along
+                // its finer path.
+                let substeps = if iteration == 0 {
+                    Self::speed_substeps(body, substep_distance, max_substeps).max(body.substeps as usize)
+                } else {
+                    1
+                };
+                if substeps > 1 {
+                    stats.substepped_bodies += 1;
+                    let start = body.step_start_position;
+                    let mut motion = (body.position - start).scale(1.0 / substeps as f32);
+                    body.position = start;
+                    for _ in 0..substeps {
+                        body.position += motion;
+                        Self::solve_body_collisions(body, body_handle, other_bodies, candidates, &self.static_geoms, &self.query_buffer, &mut context);
+                        // Motion into obstacles is cancelled, like trajectory of body was stopped
+                        // by them, motion along them continues.
+                        for contact in body.contacts.iter() {
+                            let approach = motion.dot(&contact.normal);
+                            if approach < 0.0 {
+                                motion -= contact.normal.scale(approach);
+                            }
+                        }
+                    }
+                } else {
+                    Self::solve_body_collisions(body, body_handle, other_bodies, candidates, &self.static_geoms, &self.query_buffer, &mut context);
+                }
+            }
+
+            // Further passes are useless when nothing penetrates.
+            if context.max_depth <= Self::SOLVER_TOLERANCE {
+                break;
+            }
+        }
+
+        self.solve_order = solve_order;
+        self.step_stats = stats;
+
+        if let Some(plane) = self.constraint_plane.as_ref() {
+            for body in self.bodies.iter_mut().filter(|body| body.enabled) {
+                body.project_onto_plane(plane);
+            }
+        }
+
+        self.apply_restitution();
+
+        if self.contact_reduction_distance > 0.0 {
+            let min_normal_cos = self.contact_reduction_angle.to_radians().cos();
+            for body in self.bodies.iter_mut().filter(|body| body.enabled) {
+                body.reduce_contacts(self.contact_reduction_distance, min_normal_cos, &mut self.reduction_counts);
+            }
+        }
+
+        std::mem::swap(&mut self.contact_pairs, &mut self.previous_contact_pairs);
+        self.contact_pairs.clear();
+        for (handle, body) in self.bodies.pair_iter().filter(|(_, body)| body.enabled) {
+            for contact in body.contacts.iter() {
+                // Both bodies have contact with each other, pair is reported by the one
+                // with smaller index.
+                if contact.body.is_some() && contact.body.index() < handle.index() {
+                    continue;
+                }
+                let pair = ContactPair {
+                    body: handle,
+                    other_body: contact.body,
+                    static_geom: contact.static_geom,
+                };
+                if self.contact_pairs.insert(pair) && !self.previous_contact_pairs.contains(&pair) {
+                    if contact.body.is_some() {
+                        self.new_contacts.push((handle, contact.body));
+                    }
+                    if let Some(queue) = self.contact_event_queue.as_ref() {
+                        queue.push(ContactEvent {
+                            body: handle,
+                            other_body: contact.body,
+                            static_geom: contact.static_geom,
+                            position: contact.position,
+                            normal: contact.normal,
+                            impulse: contact.impulse,
+                        });
+                    }
+                }
+            }
+        }
+
+        for i in 0..self.new_contacts.len() {
+            let (a, b) = self.new_contacts[i];
+            let (body_a, body_b) = (self.bodies.borrow(a), self.bodies.borrow(b));
+            if (body_a.collision_flags.contains(CollisionFlags::STICKY) || body_b.collision_flags.contains(CollisionFlags::STICKY)) &&
+                !self.is_stuck(a, b) {
+                let mut weld = WeldConstraint::new(a, body_a, b, body_b);
+                weld.sticky = true;
+                let _ = self.constraints.spawn(Constraint::Weld(weld));
+            }
+        }
+
+        for body in self.bodies.iter_mut().filter(|body| body.enabled) {
+            body.update_contact_hysteresis(self.contact_hysteresis_steps, self.contact_hysteresis_distance);
+        }
+    }
+
+    /// Last phase of step, must be called after [`detect_collisions`](Self::detect_collisions)
+    /// with same time as [`integrate`](Self::integrate). Applies rolling friction using
+    /// contacts, solves soft bodies and constraints, removes bodies with expired lifetime
+    /// and bodies which left world bounds. Returns removed bodies.
+    pub fn resolve(&mut self, delta_time: f32) -> &[ExpiredBody] {
+        if !self.enabled {
+            return &self.expired_bodies;
+        }
+
+        let dt2 = delta_time * delta_time;
+
+        for body in self.bodies.iter_mut().filter(|body| body.enabled) {
+            let gravity = match self.gravity_field.as_ref() {
+                Some(field) => field.evaluate(body.position),
+                None => body.gravity,
+            };
+            body.apply_rolling_friction(body.scale_gravity(gravity, self.up_vector), dt2);
+        }
+
+        for soft_body in self.soft_bodies.iter_mut() {
+            soft_body.solve_links();
+
+            for static_geometry in self.static_geoms.iter() {
+                soft_body.solve_static_collision(static_geometry, &mut self.query_buffer.borrow_mut(), &mut self.epa_scratch);
+            }
+        }
+
+        for constraint in self.constraints.iter_mut() {
+            constraint.solve(&mut self.bodies, delta_time);
+        }
+
+        #[cfg(feature = "debug-history")]
+        {
+            for body in self.bodies.iter_mut() {
+                body.record_contact_history(self.contact_history_depth);
+            }
+        }
+
+        for (handle, body) in self.bodies.pair_iter_mut() {
+            if body.lifetime.is_some_and(|lifetime| lifetime <= 0.0) {
+                self.expired_bodies.push(ExpiredBody {
+                    handle,
+                    position: body.position,
+                    rotation: body.rotation,
+                });
+            } else if body.enabled && self.bounds.is_some_and(|bounds| !bounds.contains(body.position)) {
+                self.out_of_bounds_bodies.push(handle);
+                match self.out_of_bounds_action {
+                    OutOfBoundsAction::Remove => self.expired_bodies.push(ExpiredBody {
+                        handle,
+                        position: body.position,
+                        rotation: body.rotation,
+                    }),
+                    OutOfBoundsAction::Disable => body.enabled = false,
+                }
+            }
+        }
+
+        for i in 0..self.expired_bodies.len() {
+            let body = self.bodies.free(self.expired_bodies[i].handle);
+            self.release_body(body);
+        }
+
+        // Bodies are freed right away, so duplicated handles in queue become invalid.
+        for handle in self.removal_queue.take() {
+            if self.bodies.is_valid_handle(handle) {
+                let body = self.bodies.free(handle);
+                self.expired_bodies.push(ExpiredBody {
+                    handle,
+                    position: body.position,
+                    rotation: body.rotation,
+                });
+                self.release_body(body);
+            }
+        }
+
+        let removed: Vec<Handle<RigidBody>> = self.expired_bodies.iter().map(|expired| expired.handle).collect();
+
+        // Neither contacts nor constraints may refer to removed bodies.
+        if !removed.is_empty() {
+            for body in self.bodies.iter_mut() {
+                body.contacts.retain(|contact| !removed.contains(&contact.body));
+            }
+            self.remove_dangling_constraints();
+        }
+
+        // Boxes of bodies used for detection are swept and enlarged, queries between
+        // steps need actual ones.
+        self.sync_broadphase();
+
+        #[cfg(feature = "debug-checks")]
+        self.check_invariants();
+
+        &self.expired_bodies
+    }
+
+    /// Panics with description of first found invariant violation: non-finite state of
+    /// body, invalid shape, contact which refers removed body or static geometry.
+    #[cfg(feature = "debug-checks")]
+    fn check_invariants(&self) {
+        let finite = |v: Vec3| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        for (handle, body) in self.bodies.pair_iter() {
+            assert!(finite(body.position) && finite(body.last_position) && finite(body.acceleration),
+                    "debug-checks: body {:?} has non-finite state: position {:?}, last position {:?}, acceleration {:?}",
+                    handle, body.position, body.last_position, body.acceleration);
+            let rotation = body.rotation;
+            assert!(rotation.x.is_finite() && rotation.y.is_finite() && rotation.z.is_finite() && rotation.w.is_finite(),
+                    "debug-checks: body {:?} has non-finite rotation {:?}", handle, rotation);
+            if let Err(error) = body.shape.validate() {
+                panic!("debug-checks: body {:?} has invalid shape: {}", handle, error);
+            }
+            for (i, contact) in body.contacts.iter().enumerate() {
+                assert!(contact.body.is_none() || self.bodies.is_valid_handle(contact.body),
+                        "debug-checks: contact #{} of body {:?} refers removed body {:?}", i, handle, contact.body);
+                assert!(contact.static_geom.is_none() || self.static_geoms.is_valid_handle(contact.static_geom),
+                        "debug-checks: contact #{} of body {:?} refers removed static geometry {:?}", i, handle, contact.static_geom);
+                assert!(finite(contact.position) && finite(contact.normal),
+                        "debug-checks: contact #{} of body {:?} has non-finite position {:?} or normal {:?}",
+                        i, handle, contact.position, contact.normal);
+            }
+        }
+    }
+
+    /// Replaces given bodies with static geometry made of their triangulated shapes (see
+    /// [`ConvexShape::triangulate`]) at their current transforms, then removes the bodies.
+    /// Useful to bake settled piles of debris. Margins of bodies are not baked, half-space
+    /// bodies are not supported and are left untouched, invalid handles are ignored.
+    /// Returns handle of new static geometry.
+    pub fn freeze_to_static(&mut self, handles: &[Handle<RigidBody>]) -> Handle<StaticGeometry> {
+        let mut triangles = Vec::new();
+        for handle in handles.iter() {
+            if !self.bodies.is_valid_handle(*handle) || self.bodies.borrow(*handle).shape.is_half_space() {
+                continue;
+            }
+
+            self.untrack_body(*handle);
+            let body = self.bodies.free(*handle);
+            triangles.extend(body.shape.triangulate(&body.get_transform(), Self::FREEZE_SEGMENTS)
+                .iter()
+                .filter_map(|[a, b, c]| StaticTriangle::from_points(a, b, c)));
+            self.release_body(body);
+        }
+        self.remove_dangling_constraints();
+        self.add_static_geometry(StaticGeometry::new(triangles))
+    }
+
+    /// Moves every object of other world into this world, shifting them by given offset.
+    /// Objects get new handles, returned map can be used to fix up references to them.
+    /// Handles stored in bodies (contacts) and constraints are remapped automatically.
+    /// Settings of other world (contact filter, up vector, etc.) are discarded.
+    pub fn merge(&mut self, mut other: Physics, offset: Vec3) -> HandleRemap {
+        let mut remap = HandleRemap::default();
+
+        for (old_handle, mut static_geom) in drain_pool(&mut other.static_geoms) {
+            static_geom.translate(offset);
+            remap.static_geoms.insert(old_handle, self.static_geoms.spawn(static_geom));
+        }
+
+        let mut new_bodies = Vec::new();
+        for (old_handle, mut body) in drain_pool(&mut other.bodies) {
+            body.position += offset;
+            body.last_position += offset;
+            let new_handle = self.bodies.spawn(body);
+            remap.bodies.insert(old_handle, new_handle);
+            new_bodies.push(new_handle);
+        }
+        for handle in new_bodies {
+            for contact in self.bodies.borrow_mut(handle).contacts.iter_mut() {
+                contact.body = remap.bodies.get(&contact.body).copied().unwrap_or(Handle::NONE);
+                contact.static_geom = remap.static_geoms.get(&contact.static_geom).copied().unwrap_or(Handle::NONE);
+            }
+        }
+
+        for (old_handle, mut soft_body) in drain_pool(&mut other.soft_bodies) {
+            soft_body.translate(offset);
+            remap.soft_bodies.insert(old_handle, self.soft_bodies.spawn(soft_body));
+        }
+
+        for (old_handle, mut constraint) in drain_pool(&mut other.constraints) {
+            constraint.remap_bodies(&remap.bodies);
+            remap.constraints.insert(old_handle, self.constraints.spawn(constraint));
+        }
+
+        self.sync_broadphase();
+
+        remap
+    }
+
+    /// Pushes body out of every other body and static geometry it penetrates, once. Velocity
+    /// of body is preserved and no other body is moved, so this can be used to fix bodies
+    /// spawned inside of walls, including ones with disabled collision response. Collision
+    /// groups are respected, contact filter is not used. Returns true if body was moved.
+    pub fn depenetrate_body(&mut self, handle: Handle<RigidBody>) -> bool {
+        let (ticket, mut body) = self.bodies.take_reserve(handle);
+        let settings = self.penetration_settings();
+
+        let mut offset = Vec3::ZERO;
+        if !body.shape.is_half_space() {
+            for other in self.bodies.iter() {
+                if !other.enabled || other.collision_flags.contains(CollisionFlags::QUERY_ONLY) ||
+                    (other.collision_group & body.collision_mask) == 0 ||
+                    (body.collision_group & other.collision_mask) == 0 {
+                    continue;
+                }
+
+                if let Some((_, normal, depth)) = body.body_penetration(other, &mut self.epa_scratch, settings) {
+                    let push = normal.scale(depth);
+                    body.position += push;
+                    offset += push;
+                }
+            }
+
+            for static_geometry in self.static_geoms.iter() {
+                let mut query_buffer = self.query_buffer.borrow_mut();
+                static_geometry.octree.sphere_query(body.position, body.shape.circumradius() + body.margin + settings.clearance, &mut query_buffer);
+
+                for n in query_buffer.iter().map(|i| *i as usize) {
+                    if let Some((_, normal, depth)) = body.triangle_penetration(&static_geometry.triangles[n], static_geometry.feature_normals.get(n), &mut self.epa_scratch, settings) {
+                        let push = normal.scale(depth);
+                        body.position += push;
+                        offset += push;
+                    }
+                }
+            }
+        }
+        body.last_position += offset;
+
+        self.bodies.put_back(ticket, body);
+
+        offset != Vec3::ZERO
+    }
+
+    /// Returns true if kinetic energy of every body is below given threshold, so
+    /// simulation can be considered settled and stepping can be stopped. Bodies have
+    /// unit mass and velocity is measured in units per step (see [`RigidBody::get_velocity`]).
+    /// Query-only bodies are ignored since they are never integrated.
+    pub fn is_settled(&self, energy_threshold: f32) -> bool {
+        self.bodies.iter()
+            .filter(|body| !body.collision_flags.contains(CollisionFlags::QUERY_ONLY))
+            .all(|body| 0.5 * body.get_velocity().sqr_len() < energy_threshold)
+    }
+
+    /// Applies impulse directed away from center to every body which center is within given
+    /// radius, scaled by strength and falloff. Body located exactly at center is pushed up.
+    /// Bodies are found by broad phase, which is updated at the end of each step, so bodies
+    /// moved manually since then are found by their previous boxes. Disabled, query-only and
+    /// half-space bodies are not affected.
+    pub fn apply_radial_impulse(&mut self, center: Vec3, radius: f32, strength: f32, falloff: Falloff) {
+        let extents = Vec3::new(radius, radius, radius);
+        let mut handles = Vec::new();
+        self.broadphase.query_aabb(&AxisAlignedBoundingBox::from_min_max(center - extents, center + extents), &mut handles);
+
+        for handle in handles {
+            let body = match self.bodies.try_borrow_mut(handle) {
+                Some(body) if Self::is_broadphase_body(body) && !body.collision_flags.contains(CollisionFlags::QUERY_ONLY) &&
+                    !body.shape.is_half_space() => body,
+                _ => continue,
+            };
+
+            let offset = body.position - center;
+            let distance = offset.len();
+            if distance > radius {
+                continue;
+            }
+
+            let direction = Backend::normalize(offset).unwrap_or(self.up_vector);
+            body.apply_impulse(direction.scale(strength * falloff.factor(distance, radius)));
+        }
+    }
+
+    /// Checks if two bodies overlap. This is cheaper than contact generation since no
+    /// penetration depth is computed, useful for trigger logic. Bodies which collision
+    /// groups do not match each other masks never overlap.
+    pub fn test_overlap(&self, handle_a: Handle<RigidBody>, handle_b: Handle<RigidBody>) -> bool {
+        let a = self.bodies.borrow(handle_a);
+        let b = self.bodies.borrow(handle_b);
+        handle_a != handle_b &&
+            (a.collision_group & b.collision_mask) != 0 &&
+            (b.collision_group & a.collision_mask) != 0 &&
+            a.intersects(b)
+    }
+
+    /// Returns penetration depth of two bodies (same as depth of contact between them), or
+    /// `None` if bodies do not overlap. Depth grows continuously as bodies are pressed
+    /// into each other, so it can be used for crushing and such. Collision groups are
+    /// checked same way as in [`test_overlap`](Self::test_overlap).
+    pub fn overlap_depth(&self, handle_a: Handle<RigidBody>, handle_b: Handle<RigidBody>) -> Option<f32> {
+        let a = self.bodies.borrow(handle_a);
+        let b = self.bodies.borrow(handle_b);
+        if handle_a == handle_b ||
+            (a.collision_group & b.collision_mask) == 0 ||
+            (b.collision_group & a.collision_mask) == 0 {
+            return None;
+        }
+        let settings = PenetrationSettings {
+            epa_fallback: self.epa_fallback,
+            clearance: 0.0,
+            up: self.up_vector,
+        };
+        a.body_penetration(b, &mut EpaScratch::default(), settings)
+            .map(|(_, _, depth)| depth)
+    }
+
+    /// Predicts when two bodies will touch if they keep moving with their current velocities.
+    /// Time is measured in steps (same units as velocity, see [`RigidBody::get_velocity`]),
+    /// multiply it by step duration to get seconds. Uses conservative advancement: bodies
+    /// are moved along their velocities by distance which can't be skipped over, until
+    /// it becomes small enough. Returns zero if bodies already touch, `None` if they
+    /// won't touch within given horizon. Gravity, collisions and forces are ignored, and
+    /// half-spaces are not supported (`None` is returned).
+    pub fn predict_toi(&self, handle_a: Handle<RigidBody>, handle_b: Handle<RigidBody>, horizon: f32) -> Option<f32> {
+        let a = self.bodies.borrow(handle_a);
+        let b = self.bodies.borrow(handle_b);
+        if handle_a == handle_b || a.shape.is_half_space() || b.shape.is_half_space() {
+            return None;
+        }
+
+        let (velocity_a, velocity_b) = (a.get_velocity(), b.get_velocity());
+        let speed = Backend::sqrt((velocity_a - velocity_b).sqr_len());
+        let mut time = 0.0;
+        for _ in 0..Self::MAX_TOI_ITERATIONS {
+            let transform_a = ShapeTransform::new(a.position + velocity_a.scale(time), a.rotation);
+            let transform_b = ShapeTransform::new(b.position + velocity_b.scale(time), b.rotation);
+            let distance = gjk_epa::separation_distance(&a.shape, &transform_a, &b.shape, &transform_b) - a.margin - b.margin;
+            if distance <= Self::TOI_TOLERANCE {
+                return Some(time);
+            }
+            if speed <= f32::EPSILON {
+                return None;
+            }
+            // Bodies can't get closer than distance during this time.
+            time += distance / speed;
+            if time > horizon {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Moves given shape from start position along given motion and returns every body
+    /// (which collision group matches mask) touched on the way, sorted by time of impact.
+    /// Bodies which overlap shape at start are reported with zero time of impact, hits with
+    /// equal time are sorted by index. Only bodies found by broad phase in swept bounding
+    /// box of shape are tested, each of them using conservative advancement like in
+    /// [`predict_toi`](Self::predict_toi). Broad phase is updated at the end of each step,
+    /// so bodies moved manually since then are found by their previous boxes. Disabled bodies
+    /// are skipped, half-spaces are supported neither as shape nor as bodies.
+    pub fn sweep_shape_all(&self, shape: &ConvexShape, start: Vec3, motion: Vec3, mask: u64) -> Vec<SweepHit> {
+        let mut hits = Vec::new();
+        if shape.is_half_space() {
+            return hits;
+        }
+
+        let mut swept_aabb = shape.compute_aabb(&ShapeTransform::from_position(start));
+        let end_aabb = shape.compute_aabb(&ShapeTransform::from_position(start + motion));
+        swept_aabb.add_point(end_aabb.min);
+        swept_aabb.add_point(end_aabb.max);
+
+        let mut candidates = Vec::new();
+        self.broadphase.query_aabb(&swept_aabb, &mut candidates);
+
+        let length = Backend::sqrt(motion.sqr_len());
+        for handle in candidates {
+            let body = match self.bodies.try_borrow(handle) {
+                Some(body) if Self::is_broadphase_body(body) => body,
+                _ => continue,
+            };
+            if body.collision_group & mask == 0 || body.shape.is_half_space() {
+                continue;
+            }
+
+            let body_transform = body.get_transform();
+            let mut toi = 0.0;
+            for _ in 0..Self::MAX_TOI_ITERATIONS {
+                let position = start + motion.scale(toi);
+                let distance = gjk_epa::separation_distance(shape, &ShapeTransform::from_position(position),
+                                                            &body.shape, &body_transform) - body.margin;
+                if distance <= Self::TOI_TOLERANCE {
+                    hits.push(SweepHit { body: handle, toi, position });
+                    break;
+                }
+                if length <= f32::EPSILON {
+                    break;
+                }
+                toi += distance / length;
+                if toi > 1.0 {
+                    break;
+                }
+            }
+        }
+
+        hits.sort_by(|a, b| a.toi.partial_cmp(&b.toi).unwrap_or(Ordering::Equal)
+            .then_with(|| a.body.index().cmp(&b.body.index())));
+        hits
+    }
+
+    /// Returns intersection of bounding boxes of two bodies (see [`RigidBody::get_aabb`]),
+    /// or `None` if boxes do not intersect. This is much cheaper than exact intersection
+    /// of shapes and gives region to place effects of collision at. Intersection with
+    /// half-space is bounding box of other body, intersection of two half-spaces is
+    /// infinite.
+    pub fn overlap_aabb(&self, handle_a: Handle<RigidBody>, handle_b: Handle<RigidBody>) -> Option<AxisAlignedBoundingBox> {
+        let aabb_a = self.bodies.borrow(handle_a).get_aabb();
+        let aabb_b = self.bodies.borrow(handle_b).get_aabb();
+        if !is_aabbs_overlap(&aabb_a, &aabb_b) {
+            return None;
+        }
+        Some(AxisAlignedBoundingBox::from_min_max(
+            Vec3::new(aabb_a.min.x.max(aabb_b.min.x), aabb_a.min.y.max(aabb_b.min.y), aabb_a.min.z.max(aabb_b.min.z)),
+            Vec3::new(aabb_a.max.x.min(aabb_b.max.x), aabb_a.max.y.min(aabb_b.max.y), aabb_a.max.z.min(aabb_b.max.z))))
+    }
+
+    /// Approximates volume of intersection of two bodies by testing points of regular grid
+    /// with given resolution (per axis) inside intersection of their bounding boxes. Cost
+    /// is `resolution^3` point tests, margins of bodies are ignored. Returns zero if bodies
+    /// do not overlap.
+    pub fn overlap_volume(&self, handle_a: Handle<RigidBody>, handle_b: Handle<RigidBody>, resolution: usize) -> f32 {
+        let a = self.bodies.borrow(handle_a);
+        let b = self.bodies.borrow(handle_b);
+        // Intersection of two half-spaces is unbounded.
+        if handle_a == handle_b || resolution == 0 || (a.shape.is_half_space() && b.shape.is_half_space()) {
+            return 0.0;
+        }
+        let (min, max) = match self.overlap_aabb(handle_a, handle_b) {
+            Some(overlap) => (overlap.min, overlap.max),
+            None => return 0.0,
+        };
+
+        let size = max - min;
+        let cell = Vec3::new(size.x / resolution as f32, size.y / resolution as f32, size.z / resolution as f32);
+
+        let mut inside = 0;
+        for i in 0..resolution {
+            for j in 0..resolution {
+                for k in 0..resolution {
+                    // Sample centers of grid cells.
+                    let point = min + Vec3::new(cell.x * (i as f32 + 0.5), cell.y * (j as f32 + 0.5), cell.z * (k as f32 + 0.5));
+                    let point_transform = ShapeTransform::from_position(point);
+                    if Self::body_contains_point(a, &point_transform) && Self::body_contains_point(b, &point_transform) {
+                        inside += 1;
+                    }
+                }
+            }
+        }
+
+        cell.x * cell.y * cell.z * inside as f32
+    }
+
+    /// Returns body with smallest index which shape contains given point and which collision
+    /// group matches given mask. Point exactly on surface of a shape is not inside of it.
+    /// Bodies are found by broad phase, which is updated at the end of each step, so bodies
+    /// moved manually since then are found by their previous boxes. Disabled bodies are skipped.
+    pub fn point_inside(&self, point: Vec3, mask: u64) -> Option<Handle<RigidBody>> {
+        let mut candidates = Vec::new();
+        self.broadphase.query_aabb(&AxisAlignedBoundingBox::from_min_max(point, point), &mut candidates);
+        candidates.sort_unstable_by_key(|handle| handle.index());
+
+        let point_transform = ShapeTransform::from_position(point);
+        candidates.into_iter().find(|handle| self.bodies.try_borrow(*handle).is_some_and(|body| Self::is_broadphase_body(body) &&
+            body.collision_group & mask != 0 && body.get_aabb().is_contains_point(point) && Self::body_contains_point(body, &point_transform)))
+    }
+
+    fn body_contains_point(body: &RigidBody, point_transform: &ShapeTransform) -> bool {
+        let transform = body.get_transform();
+        if let ConvexShape::HalfSpace(half_space) = &body.shape {
+            transform.basis.transform_vector(half_space.get_normal()).dot(&(point_transform.position - body.position)) < 0.0
+        } else {
+            gjk_epa::gjk_is_intersects(&body.shape, &transform, &ConvexShape::Dummy, point_transform).is_some()
+        }
+    }
+
+    /// Returns handles of bodies which centers are within given radius from center of given
+    /// body, body itself is not included, handles are sorted by index. Bodies are found by
+    /// broad phase, which is updated at the end of each step, so bodies moved manually since
+    /// then are found by their previous boxes. Disabled bodies are skipped.
+    pub fn neighbors(&self, handle: Handle<RigidBody>, radius: f32) -> Vec<Handle<RigidBody>> {
+        let center = self.bodies.borrow(handle).position;
+        let extents = Vec3::new(radius, radius, radius);
+        let mut neighbors = Vec::new();
+        self.broadphase.query_aabb(&AxisAlignedBoundingBox::from_min_max(center - extents, center + extents), &mut neighbors);
+
+        let sqr_radius = radius * radius;
+        neighbors.retain(|other_handle| *other_handle != handle && self.bodies.try_borrow(*other_handle)
+            .is_some_and(|other| Self::is_broadphase_body(other) && other.position.sqr_distance(&center) <= sqr_radius));
+        neighbors.sort_unstable_by_key(|other_handle| other_handle.index());
+        neighbors
+    }
+
+    /// Returns most ground-like contact of given body: contact which normal is closest to
+    /// given up vector and passes [`Contact::is_ground`](contact::Contact::is_ground) check
+    /// with given slope limit (in degrees). Contacts do not store depth, so among equally
+    /// aligned contacts lowest one is preferred. Returns `None` if body is airborne.
+    pub fn ground_contact(&self, handle: Handle<RigidBody>, up: Vec3, max_slope_deg: f32) -> Option<Contact> {
+        self.bodies.borrow(handle).contacts.iter()
+            .filter(|contact| contact.is_ground(up, max_slope_deg))
+            .max_by(|a, b| {
+                let alignment = a.normal.dot(&up).partial_cmp(&b.normal.dot(&up)).unwrap_or(Ordering::Equal);
+                // Lower contact point is "deeper" under body.
+                alignment.then_with(|| b.position.dot(&up).partial_cmp(&a.position.dot(&up)).unwrap_or(Ordering::Equal))
+            })
+            .cloned()
+    }
+
+    /// Batch version of [`point_inside`](Self::point_inside), returns result for each point.
+    pub fn points_inside(&self, points: &[Vec3], mask: u64) -> Vec<Option<Handle<RigidBody>>> {
+        points.iter().map(|point| self.point_inside(*point, mask)).collect()
+    }
+
+    /// Computes bounding box which encloses every body and static geometry. Returns
+    /// invalid bounding box (min > max) if world is empty. Half-space bodies make box
+    /// cover whole space.
+    pub fn world_aabb(&self) -> AxisAlignedBoundingBox {
+        let mut aabb = AxisAlignedBoundingBox::default();
+        let bodies = self.bodies.iter().map(|body| body.get_aabb());
+        let static_geoms = self.static_geoms.iter().map(|geom| geom.get_aabb());
+        for other in bodies.chain(static_geoms) {
+            if other.min.x <= other.max.x {
+                aabb.add_point(other.min);
+                aabb.add_point(other.max);
+            }
+        }
+        aabb
+    }
+
+    /// Transforms ray from world space to local space of shape with given transform.
+    fn local_ray(ray: &Ray, transform: &ShapeTransform) -> Ray {
+        Ray {
+            origin: transform.inverse_transform_point(ray.origin),
+            dir: transform.inverse_transform_direction(ray.dir),
+        }
+    }
+
+    pub fn ray_cast(&self, ray: &Ray, options: RayCastOptions, result: &mut Vec<RayCastResult>) -> bool {
+        result.clear();
+
+        // Check bodies
+        if !options.ignore_bodies {
+            for body_index in 0..self.bodies.get_capacity() {
+                let body = match self.bodies.at(body_index) {
+                    Some(body) if body.enabled || !options.ignore_disabled_bodies => body,
+                    _ => continue,
+                };
+
+                let body_handle = self.bodies.handle_from_index(body_index);
+
+                match &body.shape {
+                    ConvexShape::Dummy => {}
+                    ConvexShape::Box(box_shape) => {
+                        // Box is axis aligned in local space of body.
+                        let transform = body.get_transform();
+                        if let Some(points) = Self::local_ray(ray, &transform).box_intersection_points(&box_shape.get_min(), &box_shape.get_max()) {
+                            for point in points.iter().map(|point| transform.transform_point(*point)) {
+                                result.push(RayCastResult {
+                                    kind: HitKind::Body(body_handle),
+                                    user_flags: body.user_flags,
+                                    collision_group: body.collision_group,
+                                    position: point,
+                                    normal: point - body.position, // TODO: Fix normal
+                                    sqr_distance: point.sqr_distance(&ray.origin),
+                                })
+                            }
+                        }
+                    }
+                    ConvexShape::Sphere(sphere_shape) => {
+                        if let Some(points) = ray.sphere_intersection_points(&body.position, sphere_shape.radius) {
+                            for point in points.iter() {
+                                result.push(RayCastResult {
+                                    kind: HitKind::Body(body_handle),
+                                    user_flags: body.user_flags,
+                                    collision_group: body.collision_group,
+                                    position: *point,
+                                    normal: *point - body.position,
+                                    sqr_distance: point.sqr_distance(&ray.origin),
+                                })
+                            }
+                        }
+                    }
+                    ConvexShape::Capsule(capsule_shape) => {
+                        let (pa, pb) = capsule_shape.get_cap_centers();
+                        let transform = body.get_transform();
+                        let pa = transform.transform_point(pa);
+                        let pb = transform.transform_point(pb);
+
+                        if let Some(points) = ray.capsule_intersection(&pa, &pb, capsule_shape.get_radius()) {
+                            for point in points.iter() {
+                                result.push(RayCastResult {
+                                    kind: HitKind::Body(body_handle),
+                                    user_flags: body.user_flags,
+                                    collision_group: body.collision_group,
+                                    position: *point,
+                                    normal: *point - body.position,
+                                    sqr_distance: point.sqr_distance(&ray.origin),
+                                })
+                            }
+                        }
+                    }
+                    ConvexShape::Triangle(triangle_shape) => {
+                        let transform = body.get_transform();
+                        if let Some(point) = Self::local_ray(ray, &transform).triangle_intersection(&triangle_shape.vertices) {
+                            let point = transform.transform_point(point);
+                            result.push(RayCastResult {
+                                kind: HitKind::Body(body_handle),
+                                user_flags: body.user_flags,
+                                collision_group: body.collision_group,
+                                position: point,
+                                normal: transform.basis.transform_vector(triangle_shape.get_normal().unwrap()),
+                                sqr_distance: point.sqr_distance(&ray.origin),
+                            })
+                        }
+                    }
+                    ConvexShape::HalfSpace(half_space) => {
+                        let normal = body.get_transform().basis.transform_vector(half_space.get_normal());
+                        if let Ok(plane) = Plane::from_normal_and_point(&normal, &body.position) {
+                            if let Some(point) = ray.plane_intersection_point(&plane) {
+                                result.push(RayCastResult {
+                                    kind: HitKind::Body(body_handle),
+                                    user_flags: body.user_flags,
+                                    collision_group: body.collision_group,
+                                    position: point,
+                                    normal,
+                                    sqr_distance: point.sqr_distance(&ray.origin),
+                                })
+                            }
+                        }
+                    }
+                    ConvexShape::Disk(disk) => {
+                        let normal = body.get_transform().basis.transform_vector(disk.get_normal());
+                        if let Ok(plane) = Plane::from_normal_and_point(&normal, &body.position) {
+                            if let Some(point) = ray.plane_intersection_point(&plane) {
+                                if point.sqr_distance(&body.position) <= disk.get_radius() * disk.get_radius() {
+                                    result.push(RayCastResult {
+                                        kind: HitKind::Body(body_handle),
+                                        user_flags: body.user_flags,
+                                        collision_group: body.collision_group,
+                                        position: point,
+                                        normal,
+                                        sqr_distance: point.sqr_distance(&ray.origin),
+                                    })
+                                }
+                            }
+                        }
+                    }
+                    ConvexShape::PointCloud(_point_cloud) => {
+                        // TODO: Implement this. This requires to build convex hull from point cloud first
+                        // i.e. by gift wrapping algorithm or some other more efficient algorithms -
+                        // https://dccg.upc.edu/people/vera/wp-content/uploads/2014/11/GA2014-ConvexHulls3D-Roger-Hernando.pdf
+                    }
+                }
+            }
+        }
+
+        // Check static geometries
+        if !options.ignore_static_geometries {
+            for (handle, geom) in self.static_geoms.pair_iter() {
+                let mut query_buffer = self.query_buffer.borrow_mut();
+                geom.octree.ray_query(ray, &mut query_buffer);
+
+                for triangle_index in query_buffer.iter().map(|i| *i as usize) {
+                    let triangle = geom.triangles.get(triangle_index).unwrap();
+                    if let Some(point) = ray.triangle_intersection(&triangle.points) {
+                        result.push(RayCastResult {
+                            kind: HitKind::StaticTriangle {
+                                static_geometry: handle,
+                                triangle_index,
+                                barycentric: triangle.barycentric(point),
+                            },
+                            user_flags: geom.user_flags,
+                            collision_group: 0,
+                            position: point,
+                            normal: triangle.plane.normal,
+                            sqr_distance: point.sqr_distance(&ray.origin),
+                        })
+                    }
+                }
+            }
+        }
+
+        if options.sort_results {
+            result.sort_by(|a, b| {
+                if a.sqr_distance > b.sqr_distance {
+                    Ordering::Greater
+                } else if a.sqr_distance < b.sqr_distance {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            })
+        }
+
+        !result.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub(in crate) mod test {
+    use rg3d_core::{
+        math::{vec3::Vec3, quat::Quat, ray::Ray, aabb::AxisAlignedBoundingBox, plane::Plane},
+        pool::Handle,
+    };
+    use crate::{
+        Physics,
+        HitKind,
+        Falloff,
+        RayCastOptions,
+        Budget,
+        Bounds,
+        OutOfBoundsAction,
+        contact::ContactEventQueue,
+        gravity::{Attractor, GravityFalloff, GravityField},
+        layers::{self, CollisionMatrix, Layer},
+        rigid_body::{RigidBody, CollisionFlags},
+        static_geometry::{StaticGeometry, StaticTriangle},
+        convex_shape::{ConvexShape, SphereShape, HalfSpaceShape, BoxShape, ShapeTransform},
+        constraint::{Constraint, MotorConstraint, KinematicTarget},
+    };
+    // Allocation tests are compiled out with history recording, it allocates every step.
+    #[cfg(not(feature = "debug-history"))]
+    use crate::gjk_epa::test::count_allocations;
+    use crate::gjk_epa::test::count_gjk_calls;
+
+    pub(in crate) const DT: f32 = 1.0 / 60.0;
+
+    pub(in crate) fn sphere(position: Vec3, radius: f32) -> RigidBody {
+        let mut body = RigidBody::new(ConvexShape::Sphere(SphereShape::new(radius)));
+        body.set_position(position);
+        body
+    }
+
+    pub(in crate) fn floor() -> RigidBody {
+        let mut floor = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(Vec3::UP)));
+        floor.set_gravity(Vec3::ZERO);
+        floor
+    }
+
+    /// Horizontal square made of two triangles facing up.
+    fn platform(height: f32, half_size: f32) -> StaticGeometry {
+        let a = Vec3::new(-half_size, height, -half_size);
+        let b = Vec3::new(-half_size, height, half_size);
+        let c = Vec3::new(half_size, height, half_size);
+        let d = Vec3::new(half_size, height, -half_size);
+        StaticGeometry::new(vec![
+            StaticTriangle::from_points(&a, &b, &c).unwrap(),
+            StaticTriangle::from_points(&a, &c, &d).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn expired_body_is_reported_once_with_final_transform() {
+        let mut physics = Physics::new();
+        let rotation = Quat::from_axis_angle(Vec3::UP, 0.5);
+        let mut body = sphere(Vec3::new(1.0, 5.0, 2.0), 0.5);
+        body.set_gravity(Vec3::ZERO).set_rotation(rotation).set_lifetime(0.1);
+        let handle = physics.add_body(body);
+        let survivor = physics.add_body(sphere(Vec3::new(10.0, 0.0, 0.0), 0.5));
+        let motor = physics.add_constraint(Constraint::Motor(MotorConstraint::new(handle, Vec3::RIGHT, 0.0, 0.0)));
+
+        let mut expired = Vec::new();
+        for _ in 0..20 {
+            expired.extend_from_slice(physics.step(DT));
+        }
+
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].handle, handle);
+        assert_eq!(expired[0].position, Vec3::new(1.0, 5.0, 2.0));
+        assert_eq!(expired[0].rotation, rotation);
+        assert!(!physics.is_valid_body_handle(handle));
+        assert!(physics.is_valid_body_handle(survivor));
+        // Constraint of expired body is removed together with it.
+        assert!(!physics.constraints.is_valid_handle(motor));
+    }
+
+    // Recording of contact history clones contacts on every step.
+    #[cfg(not(feature = "debug-history"))]
+    #[test]
+    fn settled_step_does_not_allocate() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        for i in 0..50 {
+            physics.add_body(sphere(Vec3::new((i % 10) as f32 * 1.5, 0.5 + (i / 10) as f32 * 1.5, 0.0), 0.5));
+        }
+        physics.finalize();
+        // Buffers of solver grow while pile forms, then stay.
+        for _ in 0..600 {
+            physics.step(DT);
+        }
+
+        let allocations = count_allocations(|| {
+            for _ in 0..10 {
+                physics.step(DT);
+            }
+        });
+        assert_eq!(allocations, 0);
+    }
+
+    #[test]
+    fn one_way_platform() {
+        let jump = |one_way: bool| {
+            let mut physics = Physics::new();
+            physics.add_static_geometry(platform(2.0, 2.0));
+            if one_way {
+                // Body moving upwards passes through.
+                physics.set_contact_modification(Box::new(|info| info.relative_velocity.y <= 0.0));
+            }
+            let mut body = sphere(Vec3::ZERO, 0.5);
+            body.launch(Vec3::ZERO, Vec3::new(0.0, 10.0 * DT, 0.0));
+            let handle = physics.add_body(body);
+
+            let mut max_height = f32::MIN;
+            for _ in 0..300 {
+                physics.step(DT);
+                max_height = max_height.max(physics.borrow_body(handle).get_position().y);
+            }
+            (max_height, physics.borrow_body(handle).get_position().y)
+        };
+
+        let (max_height, height) = jump(true);
+        assert!(max_height > 4.0);
+        // Body has landed on top of platform.
+        assert!((height - 2.5).abs() < 0.05, "{}", height);
+
+        // Platform stops jump without filter.
+        let (max_height, _) = jump(false);
+        assert!(max_height < 1.6, "{}", max_height);
+    }
+
+    #[cfg(feature = "debug-history")]
+    #[test]
+    fn contact_history_of_settling_body() {
+        let mut physics = Physics::new();
+        physics.set_contact_history_depth(4);
+        // Margin keeps contact of resting body on every step.
+        physics.set_contact_margin(0.01);
+        physics.add_body(floor());
+        let handle = physics.add_body(sphere(Vec3::new(0.0, 0.49, 0.0), 0.5));
+
+        for _ in 0..10 {
+            physics.step(DT);
+        }
+
+        let body = physics.borrow_body(handle);
+        let history = body.contact_history();
+        assert_eq!(history.len(), 4);
+        let latest = history.last().unwrap();
+        assert!(!latest.is_empty());
+        assert_eq!(latest.len(), body.get_contacts().len());
+        for (recorded, current) in latest.iter().zip(body.get_contacts()) {
+            assert_eq!(recorded.body, current.body);
+            assert_eq!(recorded.position, current.position);
+            assert_eq!(recorded.normal, current.normal);
+        }
+    }
+
+    /// Returns bit patterns of positions and rotations of mixed pile after 1000 steps.
+    fn simulate_pile_bits() -> Vec<[u32; 7]> {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut handles = Vec::new();
+        for i in 0..10 {
+            let position = Vec3::new((i % 3) as f32 * 0.7, 1.0 + i as f32 * 1.1, (i % 2) as f32 * 0.3);
+            let body = if i % 2 == 0 {
+                sphere(position, 0.5)
+            } else {
+                let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.4, 0.4, 0.4))));
+                body.set_position(position);
+                body
+            };
+            handles.push(physics.add_body(body));
+        }
+        for _ in 0..1000 {
+            physics.step(DT);
+        }
+        handles.iter()
+            .map(|&handle| {
+                let body = physics.borrow_body(handle);
+                let (p, r) = (body.get_position(), body.get_rotation());
+                [p.x.to_bits(), p.y.to_bits(), p.z.to_bits(), r.x.to_bits(), r.y.to_bits(), r.z.to_bits(), r.w.to_bits()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeated_simulation_gives_identical_positions() {
+        assert_eq!(simulate_pile_bits(), simulate_pile_bits());
+    }
+
+    /// Run in same process proves nothing about other platforms, so strict backend is
+    /// compared with bit patterns stored from earlier run. Any change of evaluation order
+    /// in backend or solver breaks lockstep and must update them.
+    #[test]
+    #[cfg(feature = "strict-math")]
+    fn strict_simulation_matches_stored_bit_patterns() {
+        let expected: [[u32; 7]; 10] = [
+            [3197203302, 1056964608, 931566224, 0, 0, 0, 1065353216],
+            [1061423227, 1053530787, 1050248558, 0, 0, 0, 1065353216],
+            [1073406109, 1056903658, 946913923, 0, 0, 0, 1065353216],
+            [3198508177, 1068608100, 1050254724, 0, 0, 0, 1065353216],
+            [1058635449, 1067850206, 3065158395, 0, 0, 0, 1065353216],
+            [1073200395, 1068693450, 1050250534, 0, 0, 0, 1065353216],
+            [3197040545, 1074938412, 3081128738, 0, 0, 0, 1065353216],
+            [1058990744, 1074561107, 1050253582, 0, 0, 0, 1065353216],
+            [1073000136, 1074969668, 923866448, 0, 0, 0, 1065353216],
+            [3081174009, 1078713288, 1050251500, 0, 0, 0, 1065353216],
+        ];
+        assert_eq!(simulate_pile_bits(), expected.to_vec());
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-history"))]
+    fn reserved_world_does_not_reallocate_on_first_step() {
+        let mut physics = Physics::new();
+        physics.reserve(1000);
+        for i in 0..1000 {
+            // Far from each other, so there are no contacts.
+            let mut body = sphere(Vec3::new((i % 10) as f32 * 3.0, (i / 100) as f32 * 3.0, ((i / 10) % 10) as f32 * 3.0), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            physics.add_body(body);
+        }
+
+        assert_eq!(count_allocations(|| { physics.step(DT); }), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "debug-history"))]
+    fn finalized_world_does_not_allocate_on_first_step() {
+        let make_world = |finalize: bool| {
+            let mut physics = Physics::new();
+            physics.add_static_geometry(platform(0.0, 50.0));
+            for i in 0..100 {
+                // Resting on platform, so every body has contacts on first step.
+                physics.add_body(sphere(Vec3::new((i % 10) as f32 * 3.0, 0.5, (i / 10) as f32 * 3.0), 0.5));
+            }
+            if finalize {
+                physics.finalize();
+            }
+            physics
+        };
+
+        let mut physics = make_world(false);
+        assert!(count_allocations(|| { physics.step(DT); }) > 0);
+
+        let mut physics = make_world(true);
+        assert_eq!(count_allocations(|| { physics.step(DT); }), 0);
+    }
+
+    #[test]
+    fn dropped_stack_settles() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut top = Default::default();
+        for i in 0..3 {
+            let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+            body.set_position(Vec3::new(0.0, 1.0 + i as f32 * 1.2, 0.0));
+            top = physics.add_body(body);
+        }
+
+        let threshold = 1.0e-8;
+        physics.step(DT);
+        assert!(!physics.is_settled(threshold));
+
+        let steps = (0..600).position(|_| {
+            physics.step(DT);
+            physics.is_settled(threshold)
+        });
+        assert!(steps.is_some());
+        // Stack is still standing.
+        assert!((physics.borrow_body(top).get_position().y - 2.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn radial_impulse_is_larger_for_nearer_bodies() {
+        let mut physics = Physics::new();
+        let floor = physics.add_body(floor());
+        let handles = (1..=5)
+            .map(|i| {
+                let mut body = sphere(Vec3::new(i as f32, 0.0, 0.0), 0.25);
+                body.set_gravity(Vec3::ZERO);
+                physics.add_body(body)
+            })
+            .collect::<Vec<_>>();
+        let mut disabled = sphere(Vec3::new(-1.0, 0.0, 0.0), 0.25);
+        disabled.set_enabled(false);
+        let disabled = physics.add_body(disabled);
+
+        physics.apply_radial_impulse(Vec3::ZERO, 4.0, 1.0, Falloff::Linear);
+
+        let speeds = handles.iter()
+            .map(|handle| physics.borrow_body(*handle).get_velocity().len())
+            .collect::<Vec<_>>();
+        assert!(speeds[0] > speeds[1] && speeds[1] > speeds[2] && speeds[2] > 0.0);
+        // Out of radius.
+        assert_eq!(speeds[3], 0.0);
+        assert_eq!(speeds[4], 0.0);
+        assert_eq!(physics.borrow_body(disabled).get_velocity(), Vec3::ZERO);
+        assert_eq!(physics.borrow_body(floor).get_velocity(), Vec3::ZERO);
+        // Pushed away from center.
+        assert!(physics.borrow_body(handles[0]).get_velocity().x > 0.0);
+    }
+
+    #[test]
+    fn ray_hits_moved_and_rotated_box() {
+        let mut physics = Physics::new();
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(2.0, 0.5, 0.5))));
+        body.set_position(Vec3::new(10.0, 0.0, 0.0))
+            .set_rotation(Quat::from_axis_angle(Vec3::UP, std::f32::consts::FRAC_PI_2));
+        physics.add_body(body);
+
+        let mut result = Vec::new();
+        // Rotated box spans from -2 to 2 along Z and from 9.5 to 10.5 along X.
+        let ray = Ray::from_two_points(&Vec3::new(10.0, 0.0, -5.0), &Vec3::new(10.0, 0.0, 5.0)).unwrap();
+        assert!(physics.ray_cast(&ray, RayCastOptions::default(), &mut result));
+        assert!((result[0].position.z + 2.0).abs() < 1.0e-4);
+        assert!((result[0].position.x - 10.0).abs() < 1.0e-4);
+
+        let ray = Ray::from_two_points(&Vec3::new(11.0, 0.0, -5.0), &Vec3::new(11.0, 0.0, 5.0)).unwrap();
+        assert!(!physics.ray_cast(&ray, RayCastOptions::default(), &mut result));
+    }
+
+    #[test]
+    fn projectiles_do_not_collide_with_each_other() {
+        assert!(Layer::new(63).is_ok());
+        assert!(Layer::new(64).is_err());
+
+        let mut matrix = CollisionMatrix::new();
+        matrix.set_collides(layers::PROJECTILE, layers::PROJECTILE, false);
+
+        let mut physics = Physics::new();
+        let mut spawn = |position: Vec3, layer: Layer| {
+            let mut body = sphere(position, 0.5);
+            body.set_gravity(Vec3::ZERO);
+            matrix.configure(&mut body, layer);
+            physics.add_body(body)
+        };
+        // Both pairs overlap.
+        let projectile_a = spawn(Vec3::new(0.0, 0.0, 0.0), layers::PROJECTILE);
+        let projectile_b = spawn(Vec3::new(0.5, 0.0, 0.0), layers::PROJECTILE);
+        let projectile_c = spawn(Vec3::new(10.0, 0.0, 0.0), layers::PROJECTILE);
+        let enemy = spawn(Vec3::new(10.5, 0.0, 0.0), layers::ENEMY);
+
+        physics.step(DT);
+
+        let touches = |a, b| physics.borrow_body(a).get_contacts().iter().any(|contact| contact.body == b);
+        assert!(!touches(projectile_a, projectile_b));
+        assert!(!touches(projectile_b, projectile_a));
+        assert!(touches(projectile_c, enemy) || touches(enemy, projectile_c));
+    }
+
+    #[test]
+    fn merged_worlds_step_together() {
+        let mut world = Physics::new();
+        world.add_static_geometry(platform(0.0, 5.0));
+        let ball = world.add_body(sphere(Vec3::new(0.0, 1.0, 0.0), 0.5));
+
+        let mut chunk = Physics::new();
+        chunk.add_static_geometry(platform(0.0, 5.0));
+        let chunk_a = chunk.add_body(sphere(Vec3::new(0.0, 1.0, 0.0), 0.5));
+        let chunk_b = chunk.add_body(sphere(Vec3::new(2.0, 1.0, 0.0), 0.5));
+        let weld = chunk.weld(chunk_a, chunk_b);
+
+        let remap = world.merge(chunk, Vec3::new(20.0, 0.0, 0.0));
+
+        let (a, b) = (remap.bodies[&chunk_a], remap.bodies[&chunk_b]);
+        assert!(a != ball && b != ball && a != b);
+        assert_eq!(world.bodies.iter().count(), 3);
+        assert_eq!(world.static_geoms.iter().count(), 2);
+        assert_eq!(world.borrow_body(a).get_position(), Vec3::new(20.0, 1.0, 0.0));
+        match world.constraints.borrow(remap.constraints[&weld]) {
+            Constraint::Weld(weld) => assert_eq!(weld.get_bodies(), (a, b)),
+            _ => panic!("weld expected"),
+        }
+
+        for _ in 0..120 {
+            world.step(DT);
+        }
+
+        // Every body rests on platform of its own chunk.
+        for handle in [ball, a, b] {
+            let position = world.borrow_body(handle).get_position();
+            assert!((position.y - 0.5).abs() < 0.05, "{:?} {:?}", handle, position);
+        }
+        assert!((world.borrow_body(a).get_position().x - 20.0).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn spawned_copies_keep_template_settings() {
+        let mut physics = Physics::new();
+        let mut template = sphere(Vec3::ZERO, 0.1);
+        template.set_gravity(Vec3::ZERO);
+        template.collision_flags = CollisionFlags::DISABLE_COLLISION_RESPONSE;
+        template.grab = Some(KinematicTarget::new(Vec3::ZERO, 1.0));
+
+        let velocity = Vec3::new(0.0, 0.0, 0.5);
+        for i in 0..3 {
+            let position = Vec3::new(i as f32, 1.0, 0.0);
+            let handle = physics.spawn_copy(&template, position, velocity);
+            let copy = physics.borrow_body(handle);
+            assert_eq!(copy.get_position(), position);
+            assert!(copy.get_velocity().distance(&velocity) < 1.0e-6);
+            assert!(matches!(copy.get_shape(), ConvexShape::Sphere(shape) if shape.radius == 0.1));
+            assert_eq!(copy.collision_flags, CollisionFlags::DISABLE_COLLISION_RESPONSE);
+            assert!(copy.get_grab().is_none());
+        }
+    }
+
+    #[test]
+    fn expired_handle_stays_invalid_after_slot_reuse() {
+        let mut physics = Physics::new();
+        let survivors = (0..3)
+            .map(|i| physics.add_body(sphere(Vec3::new(i as f32 * 2.0, 0.0, 0.0), 0.5)))
+            .collect::<Vec<_>>();
+        let mut short_living = sphere(Vec3::new(0.0, 0.0, 5.0), 0.5);
+        short_living.set_lifetime(DT * 0.5);
+        let expiring = physics.add_body(short_living);
+
+        let invalidated = physics.step(DT).iter().map(|expired| expired.handle).collect::<Vec<_>>();
+        assert_eq!(invalidated, vec![expiring]);
+        assert!(!physics.is_valid_body_handle(expiring));
+        assert!(survivors.iter().all(|handle| physics.is_valid_body_handle(*handle)));
+
+        let newcomer = physics.add_body(sphere(Vec3::new(0.0, 0.0, 5.0), 0.5));
+        assert_eq!(newcomer.index(), expiring.index());
+        assert!(!physics.is_valid_body_handle(expiring));
+        assert!(physics.is_valid_body_handle(newcomer));
+        assert!(physics.step(DT).is_empty());
+    }
+
+    #[test]
+    fn pool_capacity_stabilizes_for_expiring_projectiles() {
+        let mut physics = Physics::new();
+        physics.set_body_free_list_capacity(64);
+        let mut template = sphere(Vec3::ZERO, 0.1);
+        template.set_gravity(Vec3::ZERO).set_lifetime(0.5);
+
+        let mut capacity_after_warm_up = 0;
+        for i in 0..10000 {
+            // Every projectile flies along its own lane.
+            let lane = (i % 1000) as f32;
+            physics.spawn_copy(&template, Vec3::new(lane % 40.0, (lane / 40.0).floor(), 0.0), Vec3::new(0.0, 0.0, 0.01));
+            if i % 20 == 19 {
+                physics.step(DT);
+            }
+            if i == 5000 {
+                capacity_after_warm_up = physics.bodies.get_capacity();
+            }
+        }
+
+        // Lifetime is 30 steps, so there are 600 projectiles alive at most.
+        assert!(capacity_after_warm_up <= 620);
+        assert_eq!(physics.bodies.get_capacity(), capacity_after_warm_up);
+    }
+
+    #[test]
+    fn recycled_body_invalidates_old_handle_and_constraints() {
+        let mut physics = Physics::new();
+        let a = physics.add_body(sphere(Vec3::ZERO, 0.5));
+        let b = physics.add_body(sphere(Vec3::new(2.0, 0.0, 0.0), 0.5));
+        let weld = physics.weld(a, b);
+
+        let recycled = physics.recycle_body(a, sphere(Vec3::new(0.0, 5.0, 0.0), 0.25)).unwrap();
+        assert!(!physics.is_valid_body_handle(a));
+        assert_eq!(physics.borrow_body(recycled).get_position(), Vec3::new(0.0, 5.0, 0.0));
+        assert!(!physics.constraints.is_valid_handle(weld));
+
+        assert!(physics.recycle_body(a, sphere(Vec3::ZERO, 0.5)).is_none());
+        assert_eq!(physics.bodies.iter().count(), 2);
+    }
+
+    #[test]
+    fn neighbors_are_exactly_bodies_within_radius() {
+        let mut physics = Physics::new();
+        let mut spawn = |position: Vec3| {
+            let mut body = sphere(position, 0.1);
+            body.set_gravity(Vec3::ZERO);
+            physics.add_body(body)
+        };
+        let center = spawn(Vec3::ZERO);
+        let near = [spawn(Vec3::new(0.5, 0.0, 0.0)), spawn(Vec3::new(0.0, -0.9, 0.0)), spawn(Vec3::new(0.5, 0.5, 0.5))];
+        // Inside of query box, but farther than radius.
+        spawn(Vec3::new(0.8, 0.8, 0.0));
+        spawn(Vec3::new(5.0, 0.0, 0.0));
+        let disabled = spawn(Vec3::new(0.0, 0.0, 0.3));
+        physics.borrow_body_mut(disabled).set_enabled(false);
+
+        physics.step(DT);
+
+        assert_eq!(physics.neighbors(center, 1.0), near.to_vec());
+    }
+
+    #[test]
+    fn contact_filter_removes_colliding_body() {
+        let mut physics = Physics::new();
+        let target = physics.add_body(sphere(Vec3::new(0.0, 0.0, 0.0), 0.5));
+        let mut bullet = sphere(Vec3::new(-2.0, 0.0, 0.0), 0.1);
+        bullet.set_gravity(Vec3::ZERO);
+        bullet.launch(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(0.5, 0.0, 0.0));
+        let bullet = physics.add_body(bullet);
+        physics.borrow_body_mut(target).set_gravity(Vec3::ZERO);
+        let witness = physics.add_body(sphere(Vec3::new(0.0, 5.0, 0.0), 0.5));
+        physics.weld(target, witness);
+
+        let queue = physics.removal_queue();
+        physics.set_contact_modification(Box::new(move |info| {
+            // Both parties of contact report it, so removal is requested twice.
+            if info.other_body.is_some() {
+                queue.push(if info.body == bullet { info.other_body } else { info.body });
+            }
+            true
+        }));
+
+        let mut removed = Vec::new();
+        for _ in 0..10 {
+            removed.extend(physics.step(DT).iter().map(|expired| expired.handle));
+        }
+
+        assert_eq!(removed, vec![target]);
+        assert!(!physics.is_valid_body_handle(target));
+        assert!(physics.is_valid_body_handle(bullet));
+        assert!(physics.is_valid_body_handle(witness));
+        // Weld to removed body is removed too.
+        assert_eq!(physics.constraints.iter().count(), 0);
+    }
+
+    #[test]
+    fn over_constrained_pile_respects_solver_budget() {
+        let mut physics = Physics::new();
+        physics.set_solver_iterations(4);
+        physics.set_solver_budget(Budget::Iterations(10));
+        let mut handles = Vec::new();
+        for i in 0..27 {
+            let position = Vec3::new((i % 3) as f32, (i / 3 % 3) as f32, (i / 9) as f32).scale(0.5);
+            let mut body = sphere(position, 1.0);
+            body.set_gravity(Vec3::ZERO);
+            handles.push(physics.add_body(body));
+        }
+
+        let mut separated = false;
+        for step in 0..500 {
+            physics.step(DT);
+            let stats = physics.get_step_stats();
+            assert!(stats.solver_iterations <= 10);
+            if step == 0 {
+                assert!(stats.budget_exhausted);
+            }
+            separated = handles.iter().enumerate().all(|(i, a)| handles[i + 1..].iter().all(|b| {
+                let distance = physics.borrow_body(*a).get_position().distance(&physics.borrow_body(*b).get_position());
+                distance > 2.0 - 0.05
+            }));
+            if separated {
+                break;
+            }
+        }
+        // Solver resumes from the body it stopped at, so every body gets its turn.
+        assert!(separated);
+    }
+
+    #[test]
+    fn contact_events_are_drained_from_other_thread() {
+        use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, thread};
+
+        fn run(queue: ContactEventQueue) {
+            let mut physics = Physics::new();
+            physics.add_body(floor());
+            for i in 0..20 {
+                physics.add_body(sphere(Vec3::new(i as f32 * 3.0, 1.0 + i as f32 * 0.2, 0.0), 0.5));
+            }
+            physics.set_contact_event_queue(Some(queue));
+            for _ in 0..200 {
+                physics.step(DT);
+            }
+        }
+
+        let reference = ContactEventQueue::new(1000);
+        run(reference.clone());
+        let expected = reference.drain();
+        assert!(expected.len() >= 20);
+        assert_eq!(reference.get_dropped_count(), 0);
+
+        let queue = ContactEventQueue::new(4);
+        let stop = Arc::new(AtomicBool::new(false));
+        let consumer = {
+            let queue = queue.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut received = Vec::new();
+                while !stop.load(Ordering::Acquire) {
+                    received.extend(queue.drain());
+                }
+                received.extend(queue.drain());
+                received
+            })
+        };
+        run(queue.clone());
+        stop.store(true, Ordering::Release);
+        let received = consumer.join().unwrap();
+
+        // Nothing is lost silently and order of generation is kept.
+        assert_eq!(received.len() + queue.get_dropped_count(), expected.len());
+        let mut remaining = expected.iter();
+        for event in received.iter() {
+            assert!(remaining.any(|e| e.body == event.body && e.other_body == event.other_body && e.static_geom == event.static_geom));
+        }
+
+        // Single-slot queue keeps only newest event of every step.
+        let world = || {
+            let mut physics = Physics::new();
+            physics.add_body(floor());
+            // Same height, so several events are generated in one step.
+            for i in 0..20 {
+                physics.add_body(sphere(Vec3::new(i as f32 * 3.0, 1.0 + (i / 5) as f32 * 0.2, 0.0), 0.5));
+            }
+            physics
+        };
+        let mut newest = Vec::new();
+        let mut dropped = 0;
+        {
+            let mut physics = world();
+            let queue = ContactEventQueue::new(1000);
+            physics.set_contact_event_queue(Some(queue.clone()));
+            for _ in 0..200 {
+                physics.step(DT);
+                let events = queue.drain();
+                dropped += events.len().saturating_sub(1);
+                newest.push(events.last().cloned());
+            }
+        }
+        let mut physics = world();
+        let queue = ContactEventQueue::new(1);
+        assert_eq!(queue.get_capacity(), 1);
+        physics.set_contact_event_queue(Some(queue.clone()));
+        for expected in newest.iter() {
+            physics.step(DT);
+            assert!(queue.len() <= 1);
+            let event = queue.pop();
+            assert_eq!(event.is_some(), expected.is_some());
+            if let (Some(event), Some(expected)) = (event, expected) {
+                assert!(event.body == expected.body && event.other_body == expected.other_body && event.static_geom == expected.static_geom);
+            }
+        }
+        assert_eq!(queue.get_dropped_count(), dropped);
+        assert!(dropped > 0);
+    }
+
+    #[test]
+    fn sweep_hits_are_ordered_by_time_of_impact() {
+        let mut physics = Physics::new();
+        let far = physics.add_body(sphere(Vec3::new(9.0, 0.0, 0.0), 0.5));
+        let near = physics.add_body(sphere(Vec3::new(3.0, 0.0, 0.0), 0.5));
+        let middle = physics.add_body(sphere(Vec3::new(6.0, 0.0, 0.0), 0.5));
+        let mut disabled = sphere(Vec3::new(4.5, 0.0, 0.0), 0.5);
+        disabled.set_enabled(false);
+        physics.add_body(disabled);
+        let mut query_only = sphere(Vec3::new(7.5, 0.0, 0.0), 0.5);
+        query_only.collision_flags = CollisionFlags::QUERY_ONLY;
+        let query_only = physics.add_body(query_only);
+        // Off the path, must not be reported.
+        physics.add_body(sphere(Vec3::new(6.0, 5.0, 0.0), 0.5));
+
+        let shape = ConvexShape::Sphere(SphereShape::new(0.5));
+        let hits = physics.sweep_shape_all(&shape, Vec3::ZERO, Vec3::new(12.0, 0.0, 0.0), u64::MAX);
+        assert_eq!(hits.iter().map(|hit| hit.body).collect::<Vec<_>>(), vec![near, middle, query_only, far]);
+        for (hit, x) in hits.iter().zip([3.0f32, 6.0, 7.5, 9.0].iter()) {
+            // Shapes touch when centers are one unit apart.
+            assert!((hit.toi - (x - 1.0) / 12.0).abs() < 0.01);
+            assert!((hit.position.x - (x - 1.0)).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn query_only_body_is_found_by_queries_without_response() {
+        let mut physics = Physics::new();
+        let mut query_only = sphere(Vec3::ZERO, 1.0);
+        query_only.collision_flags = CollisionFlags::QUERY_ONLY;
+        let query_only = physics.add_body(query_only);
+        let falling = physics.add_body(sphere(Vec3::new(0.0, 3.0, 0.0), 0.5));
+
+        // Falling body passes through without response, neither body gets contacts.
+        for _ in 0..120 {
+            physics.step(DT);
+            assert!(physics.borrow_body(query_only).get_contacts().is_empty());
+            assert!(physics.borrow_body(falling).get_contacts().is_empty());
+        }
+        assert_eq!(physics.borrow_body(query_only).get_position(), Vec3::ZERO);
+        assert!(physics.borrow_body(falling).get_position().y < -2.0);
+
+        let mut result = Vec::new();
+        let ray = Ray::from_two_points(&Vec3::new(-5.0, 0.0, 0.0), &Vec3::new(5.0, 0.0, 0.0)).unwrap();
+        assert!(physics.ray_cast(&ray, RayCastOptions::default(), &mut result));
+        assert!(result.iter().any(|hit| matches!(hit.kind, HitKind::Body(handle) if handle == query_only)));
+
+        let shape = ConvexShape::Sphere(SphereShape::new(0.5));
+        let hits = physics.sweep_shape_all(&shape, Vec3::new(-5.0, 0.0, 0.0), Vec3::new(10.0, 0.0, 0.0), u64::MAX);
+        assert_eq!(hits.iter().map(|hit| hit.body).collect::<Vec<_>>(), vec![query_only]);
+
+        physics.borrow_body_mut(falling).set_position(Vec3::new(0.0, 1.5, 0.0));
+        physics.step(DT);
+        assert_eq!(physics.neighbors(falling, 2.0), vec![query_only]);
+    }
+
+    #[test]
+    fn insertion_order_does_not_change_simulation() {
+        fn bodies() -> Vec<RigidBody> {
+            let mut bodies = vec![floor()];
+            for i in 0..12 {
+                let position = Vec3::new((i % 3) as f32 * 0.7, 0.5 + i as f32 * 0.8, (i % 4) as f32 * 0.3);
+                if i % 2 == 0 {
+                    bodies.push(sphere(position, 0.5));
+                } else {
+                    let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.4, 0.4, 0.4))));
+                    body.set_position(position);
+                    bodies.push(body);
+                }
+            }
+            bodies
+        }
+
+        let mut forward = Physics::new();
+        let forward_handles = bodies().into_iter().map(|body| forward.add_body(body)).collect::<Vec<_>>();
+        let mut backward = Physics::new();
+        let mut backward_handles = bodies().into_iter().rev().map(|body| backward.add_body(body)).collect::<Vec<_>>();
+        backward_handles.reverse();
+
+        for _ in 0..120 {
+            forward.step(DT);
+            backward.step(DT);
+        }
+
+        for (a, b) in forward_handles.iter().zip(backward_handles.iter()) {
+            let (a, b) = (forward.borrow_body(*a), backward.borrow_body(*b));
+            assert_eq!(a.get_position(), b.get_position());
+            assert_eq!(a.get_rotation(), b.get_rotation());
+        }
+    }
+
+    #[test]
+    fn attractor_pulls_ring_evenly_and_ignores_bodies_outside() {
+        let mut physics = Physics::new();
+        let mut center = sphere(Vec3::ZERO, 0.5);
+        center.set_gravity(Vec3::ZERO);
+        center.set_attractor(Some(Attractor::new(10.0, 6.0, GravityFalloff::InverseSquare)));
+        physics.add_body(center);
+
+        let mut ring = Vec::new();
+        let mut outside = Vec::new();
+        for i in 0..8 {
+            let angle = i as f32 * std::f32::consts::PI / 4.0;
+            let direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+            let mut body = sphere(direction.scale(5.0), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            ring.push((physics.add_body(body), direction));
+            let mut body = sphere(direction.scale(10.0), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            outside.push(physics.add_body(body));
+        }
+
+        physics.step(DT);
+
+        // Velocity is tiny compared to positions, so precision of its length is low.
+        let pull = physics.borrow_body(ring[0].0).get_velocity().len();
+        assert!(pull > 0.0);
+        for (handle, direction) in ring.iter() {
+            let velocity = physics.borrow_body(*handle).get_velocity();
+            assert!((velocity.len() - pull).abs() < 0.02 * pull);
+            assert!(velocity.normalized().unwrap().dot(direction) < -0.99);
+        }
+        for handle in outside.iter() {
+            assert!(physics.borrow_body(*handle).get_velocity().len() < 1.0e-6 * pull);
+        }
+    }
+
+    #[test]
+    fn compressed_chain_resolves_within_one_step() {
+        fn run(iterations: usize) -> f32 {
+            let mut physics = Physics::new();
+            physics.set_solver_iterations(iterations);
+            let mut wall = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(Vec3::new(1.0, 0.0, 0.0))));
+            wall.set_gravity(Vec3::ZERO);
+            physics.add_body(wall);
+            // Every sphere overlaps its neighbours and first one overlaps wall.
+            let chain = (0..4).map(|i| {
+                let mut body = sphere(Vec3::new(0.3 + i as f32 * 0.8, 0.0, 0.0), 0.5);
+                body.set_gravity(Vec3::ZERO);
+                physics.add_body(body)
+            }).collect::<Vec<_>>();
+
+            physics.step(DT);
+
+            let positions = chain.iter().map(|handle| physics.borrow_body(*handle).get_position()).collect::<Vec<_>>();
+            let mut depth = 0.5 - positions[0].x;
+            for (i, a) in positions.iter().enumerate() {
+                for b in positions[i + 1..].iter() {
+                    depth = depth.max(1.0 - a.distance(b));
+                }
+            }
+            depth
+        }
+
+        // Single pass propagates penetration along chain instead of resolving it.
+        assert!(run(1) > 0.05);
+        assert!(run(32) < 0.01);
+    }
+
+    #[test]
+    fn only_fast_body_is_substepped() {
+        let mut physics = Physics::new();
+        physics.set_substep_speed_threshold(Some(20.0));
+        physics.add_body(floor());
+        for i in 0..10 {
+            let mut body = sphere(Vec3::new(i as f32 * 2.0, 0.5, 5.0), 0.5);
+            body.set_velocity(Vec3::new(0.0, 0.0, 0.05));
+            physics.add_body(body);
+        }
+        // One unit per step is ten radiuses.
+        let mut fast = sphere(Vec3::new(0.0, 5.0, -5.0), 0.1);
+        fast.set_velocity(Vec3::new(1.0, 0.0, 0.0));
+        fast.set_gravity(Vec3::ZERO);
+        let fast = physics.add_body(fast);
+
+        for _ in 0..10 {
+            let start = physics.borrow_body(fast).get_position();
+            physics.step(DT);
+            assert_eq!(physics.get_step_stats().substepped_bodies, 1);
+            let travel = physics.borrow_body(fast).get_position() - start;
+            // Air friction slows body down a bit.
+            assert!(travel.x > 0.9);
+        }
+    }
+
+    #[test]
+    fn body_leaving_bounds_is_removed_and_returned() {
+        let mut physics = Physics::new();
+        physics.set_bounds(Some(Bounds::Box(AxisAlignedBoundingBox::from_min_max(
+            Vec3::new(-10.0, -5.0, -10.0), Vec3::new(10.0, 20.0, 10.0)))));
+        let falling = physics.add_body(sphere(Vec3::new(0.0, 1.0, 0.0), 0.5));
+
+        let mut removed = None;
+        for _ in 0..300 {
+            let expired = physics.step(DT).to_vec();
+            if expired.is_empty() {
+                // Body is kept while it is inside.
+                assert!(physics.borrow_body(falling).get_position().y >= -5.0);
+            } else {
+                removed = Some(expired);
+                break;
+            }
+        }
+
+        let removed = removed.expect("body must leave bounds");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].handle, falling);
+        assert!(removed[0].position.y < -5.0);
+        assert_eq!(physics.get_out_of_bounds_bodies(), &[falling]);
+        assert!(!physics.is_valid_body_handle(falling));
+    }
+
+    #[test]
+    fn body_leaving_bounds_can_be_disabled() {
+        let mut physics = Physics::new();
+        physics.set_bounds(Some(Bounds::Sphere { center: Vec3::ZERO, radius: 3.0 }));
+        physics.set_out_of_bounds_action(OutOfBoundsAction::Disable);
+        let falling = physics.add_body(sphere(Vec3::ZERO, 0.5));
+
+        for _ in 0..300 {
+            assert!(physics.step(DT).is_empty());
+            if !physics.get_out_of_bounds_bodies().is_empty() {
+                break;
+            }
+        }
+
+        assert_eq!(physics.get_out_of_bounds_bodies(), &[falling]);
+        let body = physics.borrow_body(falling);
+        assert!(!body.is_enabled());
+        assert!(body.get_position().len() > 3.0);
+    }
+
+    #[test]
+    fn grabbed_body_stops_at_wall() {
+        let mut physics = Physics::new();
+        // Vertical wall at x = 3 facing towards body.
+        let a = Vec3::new(3.0, -2.0, -2.0);
+        let b = Vec3::new(3.0, 2.0, -2.0);
+        let c = Vec3::new(3.0, 2.0, 2.0);
+        let d = Vec3::new(3.0, -2.0, 2.0);
+        physics.add_static_geometry(StaticGeometry::new(vec![
+            StaticTriangle::from_points(&a, &b, &c).unwrap(),
+            StaticTriangle::from_points(&a, &c, &d).unwrap(),
+        ]));
+        let body = physics.add_body(sphere(Vec3::ZERO, 0.5));
+        physics.grab(body, Vec3::ZERO, 50.0);
+
+        for i in 0..240 {
+            // Target goes deep behind wall.
+            physics.update_grab_target(body, Vec3::new((i as f32 * 0.05).min(8.0), 0.0, 0.0));
+            physics.step(DT);
+            assert!(physics.borrow_body(body).get_position().x < 2.5 + 0.05);
+        }
+
+        // Body is pressed against wall.
+        assert!(physics.borrow_body(body).get_position().x > 2.4);
+    }
+
+    #[test]
+    fn detect_only_finds_same_contacts_as_step_without_moving() {
+        fn world() -> (Physics, Vec<Handle<RigidBody>>) {
+            let mut physics = Physics::new();
+            let mut handles = vec![physics.add_body(floor())];
+            for position in [Vec3::new(0.0, 0.4, 0.0), Vec3::new(0.8, 0.4, 0.0), Vec3::new(0.4, 1.1, 0.1), Vec3::new(5.0, 3.0, 0.0)].iter() {
+                let mut body = sphere(*position, 0.5);
+                body.set_gravity(Vec3::ZERO);
+                handles.push(physics.add_body(body));
+            }
+            (physics, handles)
+        }
+        fn pairs(physics: &Physics, handles: &[Handle<RigidBody>]) -> Vec<(u32, u32)> {
+            let mut pairs = handles.iter()
+                .flat_map(|handle| physics.borrow_body(*handle).get_contacts().iter().map(move |contact| (handle.index(), contact.body.index())))
+                .collect::<Vec<_>>();
+            pairs.sort_unstable();
+            pairs
+        }
+
+        let (mut probed, handles) = world();
+        let positions = handles.iter().map(|handle| probed.borrow_body(*handle).get_position()).collect::<Vec<_>>();
+        probed.detect_only();
+        let (mut stepped, _) = world();
+        stepped.step(DT);
+
+        // Solver passes of step may find same pair again, so only pairs are compared.
+        let detected = pairs(&probed, &handles);
+        let mut stepped_pairs = pairs(&stepped, &handles);
+        stepped_pairs.dedup();
+        assert_eq!(detected, stepped_pairs);
+        // Both bodies of every pair get contact, far body has none.
+        for (a, b) in detected.iter() {
+            assert!(detected.contains(&(*b, *a)));
+        }
+        assert!(probed.borrow_body(handles[4]).get_contacts().is_empty());
+        assert!(detected.len() >= 6);
+        for (handle, position) in handles.iter().zip(positions.iter()) {
+            assert_eq!(probed.borrow_body(*handle).get_position(), *position);
+        }
+    }
+
+    #[test]
+    fn point_inside_finds_body_by_broad_phase() {
+        let mut physics = Physics::new();
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 1.0, 1.0))));
+        body.set_gravity(Vec3::ZERO);
+        let handle = physics.add_body(body);
+        let mut far = sphere(Vec3::new(10.0, 0.0, 0.0), 0.5);
+        far.set_gravity(Vec3::ZERO);
+        physics.add_body(far);
+
+        assert_eq!(physics.point_inside(Vec3::ZERO, u64::MAX), Some(handle));
+        assert_eq!(physics.point_inside(Vec3::new(0.5, -0.5, 0.9), u64::MAX), Some(handle));
+        assert_eq!(physics.point_inside(Vec3::new(3.0, 0.0, 0.0), u64::MAX), None);
+        assert_eq!(physics.point_inside(Vec3::ZERO, 0), None);
+
+        // Disabled bodies are not in broad phase.
+        physics.borrow_body_mut(handle).set_enabled(false);
+        physics.step(DT);
+        assert_eq!(physics.point_inside(Vec3::ZERO, u64::MAX), None);
+    }
+
+    #[test]
+    fn ray_at_centroid_gives_equal_barycentric_weights() {
+        let mut physics = Physics::new();
+        let (a, b, c) = (Vec3::new(-1.0, 0.0, -2.0), Vec3::new(3.0, 0.0, 0.5), Vec3::new(0.0, 0.0, 4.0));
+        let triangle = StaticTriangle::from_points(&a, &b, &c).unwrap();
+        let geometry = physics.add_static_geometry(StaticGeometry::new(vec![triangle]));
+
+        let centroid = (a + b + c).scale(1.0 / 3.0);
+        let ray = Ray::from_two_points(&(centroid + Vec3::new(0.0, 5.0, 0.0)), &(centroid - Vec3::new(0.0, 5.0, 0.0))).unwrap();
+        let mut result = Vec::new();
+        assert!(physics.ray_cast(&ray, RayCastOptions::default(), &mut result));
+        match result[0].kind {
+            HitKind::StaticTriangle { static_geometry, triangle_index, barycentric } => {
+                assert_eq!(static_geometry, geometry);
+                assert_eq!(triangle_index, 0);
+                for weight in [barycentric.x, barycentric.y, barycentric.z].iter() {
+                    assert!((weight - 1.0 / 3.0).abs() < 1.0e-5);
+                }
+            }
+            HitKind::Body(_) => panic!("static triangle must be hit"),
+        }
+    }
+
+    #[test]
+    fn box_dragged_across_internal_edge_is_not_pushed_sideways() {
+        let mut physics = Physics::new();
+        // Diagonal from (-3, -3) to (3, 3) is shared by both triangles.
+        physics.add_static_geometry(platform(0.0, 3.0));
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.25, 0.25, 0.25))));
+        body.set_position(Vec3::new(-2.0, 0.3, 0.5)).set_friction(Vec3::ZERO);
+        let handle = physics.add_body(body);
+        for _ in 0..60 {
+            physics.step(DT);
+        }
+
+        let mut max_side = 0.0f32;
+        for _ in 0..80 {
+            let body = physics.borrow_body_mut(handle);
+            let velocity = body.get_velocity();
+            // Box is pressed into floor, so it penetrates both triangles near the edge.
+            body.set_velocity(Vec3::new(0.05, -0.05, velocity.z));
+            physics.step(DT);
+            let velocity = physics.borrow_body(handle).get_velocity();
+            max_side = max_side.max(velocity.z.abs());
+        }
+        let position = physics.borrow_body(handle).get_position();
+        // Box has crossed the edge at x = 0.5.
+        assert!(position.x > 1.5);
+        assert!(max_side < 1.0e-3);
+    }
+
+    #[test]
+    fn body_on_belt_drifts_with_surface_velocity() {
+        let mut physics = Physics::new();
+        let mut belt = platform(0.0, 10.0);
+        belt.set_surface_velocity(Vec3::new(2.0, 0.0, -1.0));
+        physics.add_static_geometry(belt);
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        body.set_position(Vec3::new(0.0, 0.5, 0.0));
+        let handle = physics.add_body(body);
+
+        let mut last_position = physics.borrow_body(handle).get_position();
+        for _ in 0..120 {
+            physics.step(DT);
+            let position = physics.borrow_body(handle).get_position();
+            // Never moves against belt.
+            assert!(position.x >= last_position.x && position.z <= last_position.z);
+            last_position = position;
+        }
+        let drift = Vec3::new(last_position.x, 0.0, last_position.z);
+        let belt_direction = Vec3::new(2.0, 0.0, -1.0).normalized().unwrap();
+        assert!(drift.len() > 1.0);
+        assert!(drift.normalized().unwrap().dot(&belt_direction) > 0.99);
+        assert!(last_position.y < 0.6);
+    }
+
+    #[test]
+    fn body_across_two_triangles_reports_both() {
+        let mut physics = Physics::new();
+        // Without margin box pushed out of one triangle only touches the other one.
+        physics.set_contact_margin(0.01);
+        physics.add_static_geometry(platform(0.0, 2.0));
+        // Box covers diagonal shared by both triangles.
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        body.set_position(Vec3::new(0.0, 0.6, 0.0));
+        let handle = physics.add_body(body);
+        for _ in 0..60 {
+            physics.step(DT);
+        }
+
+        let mut triangles = physics.borrow_body(handle).supporting_triangles(Vec3::UP, 45.0);
+        triangles.sort_unstable();
+        assert_eq!(triangles, vec![0, 1]);
+
+        // Only one triangle is under box moved off diagonal.
+        physics.borrow_body_mut(handle).set_position(Vec3::new(1.2, 0.5, -1.2));
+        for _ in 0..10 {
+            physics.step(DT);
+        }
+        assert_eq!(physics.borrow_body(handle).supporting_triangles(Vec3::UP, 45.0), vec![1]);
+    }
+
+    #[test]
+    fn body_rests_on_frozen_box() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 0.5, 1.0))));
+        body.set_position(Vec3::new(0.0, 0.6, 0.0));
+        let frozen = physics.add_body(body);
+        for _ in 0..120 {
+            physics.step(DT);
+        }
+        let top = physics.borrow_body(frozen).get_position().y + 0.5;
+
+        let geometry = physics.freeze_to_static(&[frozen]);
+        assert!(!physics.is_valid_body_handle(frozen));
+        assert!(!physics.static_geoms[geometry].triangles.is_empty());
+
+        let falling = physics.add_body(sphere(Vec3::new(0.2, 3.0, -0.3), 0.5));
+        for _ in 0..180 {
+            physics.step(DT);
+        }
+        let body = physics.borrow_body(falling);
+        assert!((body.get_position().y - (top + 0.5)).abs() < 0.02);
+        assert!(body.static_contacts().any(|contact| contact.static_geom == geometry));
+    }
+
+    #[test]
+    fn response_disabled_body_records_triangle_contact_without_moving() {
+        let world = |with_platform: bool| {
+            let mut physics = Physics::new();
+            if with_platform {
+                physics.add_static_geometry(platform(0.0, 2.0));
+            }
+            let mut body = sphere(Vec3::new(0.3, 0.2, -0.4), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            body.collision_flags = CollisionFlags::DISABLE_COLLISION_RESPONSE;
+            let handle = physics.add_body(body);
+            (physics, handle)
+        };
+        // Same body without platform, so rounding of integration is same for both.
+        let (mut physics, handle) = world(true);
+        let (mut reference, reference_handle) = world(false);
+
+        for _ in 0..10 {
+            physics.step(DT);
+            reference.step(DT);
+            let body = physics.borrow_body(handle);
+            assert_eq!(body.get_position(), reference.borrow_body(reference_handle).get_position());
+            assert_eq!(body.static_contacts().count(), 1);
+        }
+
+        // Falling body passes through triangles, gathering contacts on the way.
+        physics.borrow_body_mut(handle).set_gravity(Vec3::new(0.0, -9.81, 0.0));
+        let mut touched = 0;
+        for _ in 0..60 {
+            physics.step(DT);
+            if !physics.borrow_body(handle).get_contacts().is_empty() {
+                touched += 1;
+            }
+        }
+        assert!(touched > 0);
+        assert!(physics.borrow_body(handle).get_position().y < -1.0);
+    }
+
+    #[test]
+    fn body_spawned_inside_wall_is_pushed_out_without_velocity() {
+        let mut physics = Physics::new();
+        let mut wall = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 2.0, 2.0))));
+        wall.set_gravity(Vec3::ZERO);
+        let wall = physics.add_body(wall);
+        // Center is inside of wall, closer to its +X face.
+        let handle = physics.add_body(sphere(Vec3::new(0.3, 0.0, 0.0), 0.5));
+
+        assert!(physics.depenetrate_body(handle));
+        let body = physics.borrow_body(handle);
+        assert!((body.get_position().x - 1.0).abs() < 1.0e-3);
+        assert!(body.get_position().y.abs() < 1.0e-3 && body.get_position().z.abs() < 1.0e-3);
+        assert_eq!(body.get_velocity(), Vec3::ZERO);
+        assert_eq!(physics.borrow_body(wall).get_position(), Vec3::ZERO);
+
+        // Nothing to fix anymore.
+        assert!(!physics.depenetrate_body(handle));
+    }
+
+    #[test]
+    fn predicted_toi_matches_closing_distance() {
+        let mut physics = Physics::new();
+        let mut add = |body: RigidBody, velocity: Vec3| {
+            let mut body = body;
+            body.set_gravity(Vec3::ZERO).set_velocity(velocity);
+            physics.add_body(body)
+        };
+        let a = add(sphere(Vec3::new(0.0, 1.0, 0.0), 0.5), Vec3::new(0.1, 0.0, 0.0));
+        let b = add(sphere(Vec3::new(10.0, 1.0, 0.0), 0.5), Vec3::new(-0.15, 0.0, 0.0));
+        // Corner of box rotated by 45 degrees points at sphere.
+        let mut rotated = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        rotated.set_position(Vec3::new(0.0, 1.0, 20.0)).set_rotation(Quat::from_axis_angle(Vec3::UP, std::f32::consts::FRAC_PI_4));
+        let c = add(rotated, Vec3::ZERO);
+        let d = add(sphere(Vec3::new(6.0, 1.0, 20.0), 0.5), Vec3::new(-0.2, 0.0, 0.0));
+        // Passes by far above others.
+        let e = add(sphere(Vec3::new(5.0, 10.0, 0.0), 0.5), Vec3::new(0.0, 0.0, 0.1));
+
+        // Gap of 9 units is closed at 0.25 units per step.
+        let toi = physics.predict_toi(a, b, 100.0).unwrap();
+        assert!((toi - 9.0 / 0.25).abs() < 0.05);
+        assert_eq!(physics.predict_toi(a, b, 30.0), None);
+
+        let gap = 6.0 - 0.5 * std::f32::consts::SQRT_2 - 0.5;
+        let toi = physics.predict_toi(c, d, 100.0).unwrap();
+        assert!((toi - gap / 0.2).abs() < 0.05);
+
+        assert_eq!(physics.predict_toi(a, e, 1000.0), None);
+    }
+
+    #[test]
+    fn aabb_only_sensor_overlaps_by_boxes_without_gjk() {
+        let mut physics = Physics::new();
+        let queue = ContactEventQueue::new(16);
+        physics.set_contact_event_queue(Some(queue.clone()));
+        // Box rotated by 45 degrees has bounding box much larger than itself.
+        let mut sensor = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(2.0, 2.0, 2.0))));
+        sensor.set_rotation(Quat::from_axis_angle(Vec3::UP, std::f32::consts::FRAC_PI_4)).set_gravity(Vec3::ZERO);
+        sensor.collision_flags = CollisionFlags::AABB_ONLY | CollisionFlags::DISABLE_COLLISION_RESPONSE;
+        let sensor = physics.add_body(sensor);
+        // Inside of corner of bounding box, far outside of box itself.
+        let mut body = sphere(Vec3::new(2.4, 0.0, 2.4), 0.2);
+        body.set_gravity(Vec3::ZERO);
+        let handle = physics.add_body(body);
+        let shape_transform = ShapeTransform::new(Vec3::new(2.4, 0.0, 2.4), Quat::IDENTITY);
+        let sensor_transform = physics.borrow_body(sensor).get_transform();
+        assert!(!physics.borrow_body(sensor).shape.intersects(&sensor_transform, &physics.borrow_body(handle).shape, &shape_transform));
+
+        assert_eq!(count_gjk_calls(|| { physics.step(DT); }), 0);
+        let contacts = physics.borrow_body(handle).get_contacts();
+        assert!(!contacts.is_empty());
+        for contact in contacts.iter() {
+            assert_eq!(contact.body, sensor);
+            // Normal is world axis.
+            assert_eq!([contact.normal.x.abs(), contact.normal.y.abs(), contact.normal.z.abs()].iter().filter(|c| **c == 1.0).count(), 1);
+        }
+        let events = queue.drain();
+        assert_eq!(events.len(), 1);
+        assert!((events[0].body == sensor && events[0].other_body == handle) || (events[0].body == handle && events[0].other_body == sensor));
+    }
+
+    #[test]
+    fn contact_list_capacity_does_not_shrink_between_steps() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        // Sphere bounces between floor and neighbours, so its amount of contacts churns.
+        let ball = physics.add_body(sphere(Vec3::new(0.0, 3.0, 0.0), 0.5));
+        for &x in [-1.05, 1.05].iter() {
+            physics.add_body(sphere(Vec3::new(x, 0.5, 0.0), 0.5));
+        }
+
+        let mut lengths = Vec::new();
+        let mut capacities = Vec::new();
+        for _ in 0..120 {
+            physics.step(DT);
+            let contacts = &physics.borrow_body(ball).contacts;
+            lengths.push(contacts.len());
+            capacities.push(contacts.capacity());
+        }
+
+        assert_ne!(lengths.iter().min(), lengths.iter().max());
+        assert!(capacities.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    #[should_panic(expected = "has non-finite state")]
+    fn debug_checks_catch_non_finite_body_state() {
+        let mut physics = Physics::new();
+        let mut body = sphere(Vec3::new(f32::NAN, 0.0, 0.0), 0.5);
+        body.set_gravity(Vec3::ZERO);
+        physics.add_body(body);
+        physics.step(DT);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    #[should_panic(expected = "sphere has invalid radius")]
+    fn debug_checks_catch_zero_radius() {
+        let mut physics = Physics::new();
+        let mut body = sphere(Vec3::ZERO, 0.0);
+        body.set_gravity(Vec3::ZERO);
+        physics.add_body(body);
+        physics.step(DT);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    #[should_panic(expected = "has invalid penetration depth")]
+    fn debug_checks_catch_negative_penetration_depth() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        physics.add_body(sphere(Vec3::new(0.0, 0.4, 0.0), 0.5));
+        physics.set_contact_modification(Box::new(|info| {
+            info.depth = -1.0;
+            true
+        }));
+        physics.step(DT);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-checks")]
+    #[should_panic(expected = "refers removed body")]
+    fn debug_checks_catch_contact_with_removed_body() {
+        let mut physics = Physics::new();
+        let body = physics.add_body(sphere(Vec3::ZERO, 0.5));
+        let removed = physics.add_body(sphere(Vec3::new(5.0, 0.0, 0.0), 0.5));
+        physics.step(DT);
+        physics.remove_body(removed);
+
+        physics.borrow_body_mut(body).contacts.push(crate::contact::Contact { body: removed, ..Default::default() });
+        physics.check_invariants();
+    }
+
+    #[test]
+    fn bodies_around_gravity_point_accelerate_towards_center() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let fields = vec![
+            GravityField::Point { center, strength: 100.0, falloff: GravityFalloff::InverseSquare },
+            GravityField::Point { center, strength: 10.0, falloff: GravityFalloff::InverseLinear },
+            GravityField::Custom(Box::new(move |position| (center - position).scale(0.1))),
+        ];
+        let offsets = [
+            Vec3::new(10.0, 0.0, 0.0), Vec3::new(-10.0, 0.0, 0.0),
+            Vec3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -10.0, 0.0),
+            Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -10.0),
+            Vec3::new(6.0, -6.0, 6.0),
+        ];
+
+        for field in fields {
+            let mut physics = Physics::new();
+            physics.set_gravity_field(field);
+            let handles: Vec<_> = offsets.iter().map(|&offset| physics.add_body(sphere(center + offset, 0.5))).collect();
+
+            let mut last_speeds = vec![0.0; handles.len()];
+            for _ in 0..5 {
+                physics.step(DT);
+                for (i, &handle) in handles.iter().enumerate() {
+                    let body = physics.borrow_body(handle);
+                    let velocity = body.get_velocity();
+                    let to_center = (center - body.get_position()).normalized().unwrap();
+                    assert!(velocity.normalized().unwrap().dot(&to_center) > 0.999);
+                    assert!(velocity.len() > last_speeds[i]);
+                    last_speeds[i] = velocity.len();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn motor_drives_loaded_platform_up_at_constant_speed() {
+        let speed = 1.0;
+        let mut physics = Physics::new();
+        let mut platform = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(2.0, 0.25, 2.0))));
+        platform.set_position(Vec3::new(0.0, 1.0, 0.0));
+        let platform = physics.add_body(platform);
+        let mut cargo = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        cargo.set_position(Vec3::new(0.0, 1.75, 0.0));
+        let cargo = physics.add_body(cargo);
+        physics.add_constraint(Constraint::Motor(MotorConstraint::new(platform, Vec3::UP, speed, 100.0)));
+
+        // Let motor reach target speed together with load.
+        for _ in 0..60 {
+            physics.step(DT);
+        }
+
+        for _ in 0..120 {
+            physics.step(DT);
+            let platform_body = physics.borrow_body(platform);
+            let cargo = physics.borrow_body(cargo);
+            assert!((platform_body.get_velocity().y - speed * DT).abs() < 1.0e-3 * DT);
+            assert!(cargo.get_contacts().iter().any(|contact| contact.body == platform));
+            assert!((cargo.get_position().y - platform_body.get_position().y - 0.75).abs() < 0.05);
+            assert!((cargo.get_velocity().y - speed * DT).abs() < 0.05 * DT);
+        }
+    }
+
+    #[test]
+    fn sticky_body_moves_with_wall_until_released() {
+        let mut physics = Physics::new();
+        let mut wall = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.25, 2.0, 2.0))));
+        wall.set_gravity(Vec3::ZERO);
+        let wall = physics.add_body(wall);
+        let mut hook = sphere(Vec3::ZERO, 0.25);
+        hook.set_gravity(Vec3::ZERO);
+        hook.collision_flags = CollisionFlags::STICKY;
+        hook.launch(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(0.05, 0.0, 0.0));
+        let hook = physics.add_body(hook);
+
+        let offset = |physics: &Physics| physics.borrow_body(hook).get_position() - physics.borrow_body(wall).get_position();
+        let steps = (0..200).position(|_| {
+            physics.step(DT);
+            physics.borrow_body(hook).get_contacts().iter().any(|contact| contact.body == wall)
+        });
+        assert!(steps.is_some());
+
+        // Pulling hook away drags wall along while they are stuck.
+        let stuck_offset = offset(&physics);
+        for _ in 0..60 {
+            physics.borrow_body_mut(hook).set_velocity(Vec3::new(-0.02, 0.0, 0.0));
+            physics.step(DT);
+            assert!((offset(&physics) - stuck_offset).len() < 0.01);
+        }
+        assert!(physics.borrow_body(wall).get_position().x < -0.5);
+
+        // Once released, hook leaves stopped wall behind.
+        assert!(physics.release_stuck(hook));
+        physics.borrow_body_mut(wall).set_velocity(Vec3::ZERO);
+        let wall_position = physics.borrow_body(wall).get_position();
+        for _ in 0..60 {
+            physics.borrow_body_mut(hook).set_velocity(Vec3::new(-0.02, 0.0, 0.0));
+            physics.step(DT);
+        }
+        assert!((physics.borrow_body(wall).get_position() - wall_position).len() < 1.0e-4);
+        assert!(offset(&physics).x < stuck_offset.x - 0.5);
+    }
+
+    /// Rolls sphere along floor for three seconds, returns travelled distance and final speed
+    /// (in units per second).
+    fn roll_sphere(rolling_friction: f32) -> (f32, f32) {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut ball = sphere(Vec3::new(0.0, 0.5, 0.0), 0.5);
+        // No sliding friction, so only rolling resistance slows sphere down.
+        ball.set_friction(Vec3::ZERO)
+            .set_rolling_friction(rolling_friction)
+            .set_velocity(Vec3::new(2.0 * DT, 0.0, 0.0));
+        let ball = physics.add_body(ball);
+        for _ in 0..180 {
+            physics.step(DT);
+        }
+        let ball = physics.borrow_body(ball);
+        (ball.get_position().x, ball.get_velocity().len() / DT)
+    }
+
+    #[test]
+    fn rolling_friction_stops_sphere_within_bounded_distance() {
+        // Stopping distance is v^2 / (2 * k * g) = 4 / (2 * 0.5 * 9.81) ~ 0.41.
+        let (distance, speed) = roll_sphere(0.5);
+        assert!(distance > 0.3 && distance < 0.6);
+        assert!(speed < 1.0e-3);
+    }
+
+    #[test]
+    fn sphere_without_rolling_friction_keeps_rolling() {
+        let (distance, speed) = roll_sphere(0.0);
+        assert!(distance > 5.5);
+        assert!((speed - 2.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn fast_body_keeps_speed_up_to_max_linear_velocity() {
+        let max_speed = 100.0;
+        for &speed in [10.0, 50.0, 99.0, 101.0, 200.0, 10000.0].iter() {
+            let mut physics = Physics::new();
+            physics.set_max_linear_velocity(max_speed);
+            let mut bullet = sphere(Vec3::ZERO, 0.1);
+            bullet.set_gravity(Vec3::ZERO).set_velocity(Vec3::new(speed * DT, 0.0, 0.0));
+            let bullet = physics.add_body(bullet);
+
+            physics.step(DT);
+
+            let actual = physics.borrow_body(bullet).get_velocity().len() / DT;
+            if speed <= max_speed {
+                // Only air friction slows body down.
+                assert!(actual < speed && actual > speed * 0.99);
+            } else {
+                assert!((actual - max_speed).abs() < 1.0e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn soft_body_yields_by_its_response_coefficient() {
+        // Returns displacements of hard and soft bodies after one step, and amount of steps
+        // until they separate.
+        let collide = |response_coefficient: f32| {
+            let mut physics = Physics::new();
+            let mut hard = sphere(Vec3::new(-0.4, 0.0, 0.0), 0.5);
+            hard.set_gravity(Vec3::ZERO);
+            let hard = physics.add_body(hard);
+            let mut soft = sphere(Vec3::new(0.4, 0.0, 0.0), 0.5);
+            soft.set_gravity(Vec3::ZERO).set_response_coefficient(response_coefficient);
+            let soft = physics.add_body(soft);
+
+            physics.step(DT);
+            let hard_shift = physics.borrow_body(hard).get_position().x + 0.4;
+            let soft_shift = physics.borrow_body(soft).get_position().x - 0.4;
+            let steps = (0..100).position(|_| {
+                // Stop bodies, so only position correction separates them.
+                physics.borrow_body_mut(hard).set_velocity(Vec3::ZERO);
+                physics.borrow_body_mut(soft).set_velocity(Vec3::ZERO);
+                physics.step(DT);
+                let distance = physics.borrow_body(soft).get_position().x - physics.borrow_body(hard).get_position().x;
+                distance >= 1.0 - 1.0e-3
+            });
+            (hard_shift, soft_shift, steps)
+        };
+
+        let (full_hard_shift, full_soft_shift, _) = collide(1.0);
+        assert!((full_hard_shift + full_soft_shift).abs() < 1.0e-5);
+
+        // Soft body takes fifth of correction hard body takes, and less than it would
+        // take with full response, yet bodies still separate.
+        let (hard_shift, soft_shift, steps) = collide(0.2);
+        assert!((soft_shift + 0.2 * hard_shift).abs() < 1.0e-4);
+        assert!(soft_shift > 0.0 && soft_shift < 0.3 * full_soft_shift);
+        assert!(steps.is_some());
+    }
+
+    #[test]
+    fn resting_box_sits_resting_clearance_above_floor() {
+        for &clearance in [0.0, 0.005, 0.02].iter() {
+            let mut physics = Physics::new();
+            physics.set_resting_clearance(clearance);
+            physics.add_body(floor());
+            let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+            body.set_friction(Vec3::new(1.0, 1.0, 1.0)).set_position(Vec3::new(0.0, 0.6, 0.0));
+            let body = physics.add_body(body);
+            for _ in 0..120 {
+                physics.step(DT);
+            }
+
+            let body = physics.borrow_body(body);
+            assert!(!body.get_contacts().is_empty());
+            let gap = body.get_position().y - 0.5;
+            assert!((gap - clearance).abs() < 1.0e-3, "clearance {}, gap {}", clearance, gap);
+        }
+    }
+
+    #[test]
+    fn resting_boxes_keep_contacts_every_frame_with_contact_margin() {
+        let mut physics = Physics::new();
+        physics.set_contact_margin(0.01);
+        physics.add_body(floor());
+        // Boxes of stack exactly touch floor and each other.
+        let boxes: Vec<_> = (0..2).map(|i| {
+            let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+            body.set_position(Vec3::new(0.0, 0.5 + i as f32, 0.0));
+            physics.add_body(body)
+        }).collect();
+
+        for _ in 0..300 {
+            physics.step(DT);
+            for &handle in boxes.iter() {
+                assert!(!physics.borrow_body(handle).get_contacts().is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn contact_hysteresis_keeps_friction_state_at_touching_threshold() {
+        // Returns per frame contact states of weightless sphere which jitters around height
+        // at which it touches floor, so it touches floor only on every second frame.
+        let jitter = |hysteresis_steps: u32| {
+            let mut physics = Physics::new();
+            physics.set_contact_hysteresis(hysteresis_steps, 0.01);
+            physics.add_body(floor());
+            let mut body = sphere(Vec3::new(0.0, 0.5, 0.0), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            let body = physics.add_body(body);
+            (0..60).map(|i| {
+                let offset = if i % 2 == 0 { -0.001 } else { 0.001 };
+                physics.borrow_body_mut(body).set_position(Vec3::new(0.0, 0.5 + offset, 0.0));
+                physics.step(DT);
+                physics.borrow_body(body).is_in_contact()
+            }).collect::<Vec<_>>()
+        };
+
+        let states = jitter(0);
+        assert!(states.windows(2).all(|pair| pair[0] != pair[1]));
+
+        assert!(jitter(2).iter().all(|&in_contact| in_contact));
+    }
+
+    /// Drops bouncy ball on floor, returns heights of ball on each step.
+    fn drop_bouncy_ball(height: f32, restitution_threshold: f32, steps: usize) -> Vec<f32> {
+        let mut physics = Physics::new();
+        physics.set_restitution_threshold(restitution_threshold);
+        physics.add_body(floor());
+        let mut ball = sphere(Vec3::new(0.0, 0.5 + height, 0.0), 0.5);
+        ball.set_restitution(0.9);
+        let ball = physics.add_body(ball);
+        (0..steps).map(|_| {
+            physics.step(DT);
+            physics.borrow_body(ball).get_position().y - 0.5
+        }).collect()
+    }
+
+    #[test]
+    fn slow_impact_below_restitution_threshold_does_not_bounce() {
+        // Impact speed is sqrt(2 * 9.81 * 0.02) ~ 0.63 units per second.
+        let heights = drop_bouncy_ball(0.02, 1.0, 120);
+        let landing = heights.iter().position(|&height| height < 1.0e-3).unwrap();
+        assert!(heights[landing..].iter().all(|&height| height < 1.0e-3));
+
+        // Same drop bounces without threshold.
+        let heights = drop_bouncy_ball(0.02, 0.0, 120);
+        let landing = heights.iter().position(|&height| height < 1.0e-3).unwrap();
+        assert!(heights[landing..].iter().any(|&height| height > 0.005));
+    }
+
+    #[test]
+    fn bouncy_ball_stops_bouncing_within_bounded_steps() {
+        let heights = drop_bouncy_ball(2.0, 1.0, 1200);
+        // Fast impacts still bounce high.
+        let landing = heights.iter().position(|&height| height < 1.0e-3).unwrap();
+        assert!(heights[landing..].iter().any(|&height| height > 0.5));
+        let last_bounce = heights.iter().rposition(|&height| height > 1.0e-3).unwrap();
+        assert!(last_bounce < 900);
+    }
+
+    #[test]
+    fn settled_stack_is_reported_resting_without_penetration() {
+        let mut physics = Physics::new();
+        // Single pass leaves stack sagging under weight of upper boxes.
+        physics.set_solver_iterations(10);
+        physics.add_body(floor());
+        let boxes: Vec<_> = (0..3).map(|i| {
+            let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+            body.set_position(Vec3::new(0.0, 0.6 + i as f32 * 1.1, 0.0));
+            physics.add_body(body)
+        }).collect();
+        for _ in 0..600 {
+            physics.step(DT);
+        }
+
+        let report = physics.stability_report();
+        assert!(boxes.iter().all(|&handle| !physics.borrow_body(handle).get_contacts().is_empty()));
+        // Floor has contacts too and counts as resting.
+        assert_eq!(report.resting_bodies, boxes.len() + 1);
+        assert_eq!(report.penetrating_bodies, 0);
+        assert!(report.max_penetration < Physics::SOLVER_TOLERANCE);
+    }
+
+    #[test]
+    fn over_relaxation_resolves_stack_in_fewer_iterations() {
+        // Returns amount of solver iterations (solves of single body) spent to push apart
+        // overlapping stack, iterations stop once penetrations are below tolerance.
+        let iterations = |relaxation: f32| {
+            let mut physics = Physics::new();
+            physics.set_solver_relaxation(relaxation);
+            physics.set_solver_iterations(100);
+            physics.add_body(floor());
+            for i in 0..5 {
+                let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+                body.set_position(Vec3::new(0.0, 0.4 + i as f32 * 0.9, 0.0));
+                physics.add_body(body);
+            }
+            physics.step(DT);
+            physics.get_step_stats().solver_iterations
+        };
+
+        let (normal, over) = (iterations(1.0), iterations(1.5));
+        // Six bodies are solved on each of at most 100 passes.
+        assert!(normal < 600);
+        assert!(over * 2 < normal);
+    }
+
+    #[test]
+    fn calling_phases_in_order_matches_step() {
+        let simulate = |phases: bool| {
+            let mut physics = Physics::new();
+            physics.add_static_geometry(platform(0.0, 5.0));
+            let mut handles = Vec::new();
+            for i in 0..6 {
+                let position = Vec3::new((i % 3) as f32 * 0.7, 1.0 + i as f32 * 1.1, 0.0);
+                let body = if i % 2 == 0 {
+                    sphere(position, 0.5)
+                } else {
+                    let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.4, 0.4, 0.4))));
+                    body.set_position(position);
+                    body
+                };
+                handles.push(physics.add_body(body));
+            }
+            physics.weld(handles[0], handles[1]);
+            for _ in 0..240 {
+                if phases {
+                    physics.integrate(DT);
+                    physics.detect_collisions();
+                    physics.resolve(DT);
+                } else {
+                    physics.step(DT);
+                }
+            }
+            handles.iter()
+                .map(|&handle| {
+                    let body = physics.borrow_body(handle);
+                    (body.get_position(), body.get_rotation(), body.get_contacts().len())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(simulate(true), simulate(false));
+    }
+
+    #[test]
+    fn zero_time_scale_freezes_simulation() {
+        let mut physics = Physics::new();
+        let mut body = sphere(Vec3::new(0.0, 10.0, 0.0), 0.5);
+        body.set_velocity(Vec3::new(0.01, 0.0, 0.0));
+        let body = physics.add_body(body);
+        let (position, velocity) = (physics.borrow_body(body).get_position(), physics.borrow_body(body).get_velocity());
+
+        physics.set_time_scale(0.0);
+        for _ in 0..60 {
+            assert_eq!(physics.update(DT), 0);
+        }
+        assert_eq!(physics.borrow_body(body).get_position(), position);
+        assert_eq!(physics.borrow_body(body).get_velocity(), velocity);
+
+        // Negative scale is rejected, simulation stays paused.
+        physics.set_time_scale(-1.0);
+        assert_eq!(physics.get_time_scale(), 0.0);
+
+        physics.set_time_scale(1.0);
+        assert!(physics.update(0.1) > 0);
+        assert!(physics.borrow_body(body).get_position().y < position.y);
+    }
+
+    #[test]
+    fn half_time_scale_takes_twice_real_time_to_fall() {
+        // Returns real (frame) time it takes for body to fall given distance.
+        let fall_time = |time_scale: f32| {
+            let mut physics = Physics::new();
+            physics.set_time_scale(time_scale);
+            let body = physics.add_body(sphere(Vec3::new(0.0, 10.0, 0.0), 0.5));
+            let frames = (0..1000).position(|_| {
+                physics.update(DT);
+                physics.borrow_body(body).get_position().y < 5.0
+            }).unwrap();
+            frames as f32 * DT
+        };
+
+        let (normal, slow) = (fall_time(1.0), fall_time(0.5));
+        assert!((slow - 2.0 * normal).abs() <= 2.0 * DT);
+    }
+
+    #[test]
+    fn xy_constraint_plane_strips_z_motion() {
+        let mut physics = Physics::new();
+        physics.set_constraint_plane(Some(Plane::from_normal_and_point(&Vec3::new(0.0, 0.0, 1.0), &Vec3::ZERO).unwrap()));
+        // Slope facing towards +Z pushes bodies resting on it along Z.
+        let mut slope = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(Vec3::new(0.0, 1.0, 1.0))));
+        slope.set_gravity(Vec3::ZERO);
+        physics.add_body(slope);
+        let mut thrown = sphere(Vec3::new(0.0, 3.0, 0.0), 0.5);
+        thrown.set_velocity(Vec3::new(0.01, 0.0, 0.05));
+        let thrown = physics.add_body(thrown);
+        let dropped = physics.add_body(sphere(Vec3::new(2.0, 3.0, 0.0), 0.5));
+
+        let mut touched = false;
+        for _ in 0..120 {
+            physics.step(DT);
+            for &handle in [thrown, dropped].iter() {
+                let body = physics.borrow_body(handle);
+                touched |= !body.get_contacts().is_empty();
+                assert_eq!(body.get_position().z, 0.0);
+                assert_eq!(body.get_velocity().z, 0.0);
+            }
+        }
+        assert!(touched);
+        // Motion along plane is kept.
+        assert!(physics.borrow_body(thrown).get_position().x > 0.25);
+    }
+
+    #[test]
+    fn body_falls_along_z_and_rests_on_z_up_floor() {
+        let mut physics = Physics::new();
+        physics.set_up_vector(Vec3::new(0.0, 0.0, 1.0));
+        let a = Vec3::new(-5.0, -5.0, 0.0);
+        let b = Vec3::new(5.0, -5.0, 0.0);
+        let c = Vec3::new(5.0, 5.0, 0.0);
+        let d = Vec3::new(-5.0, 5.0, 0.0);
+        physics.add_static_geometry(StaticGeometry::new(vec![
+            StaticTriangle::from_points(&a, &b, &c).unwrap(),
+            StaticTriangle::from_points(&a, &c, &d).unwrap(),
+        ]));
+        let body = physics.add_body(sphere(Vec3::new(1.0, 2.0, 2.0), 0.5));
+
+        physics.step(DT);
+        let velocity = physics.borrow_body(body).get_velocity();
+        assert!(velocity.z < 0.0 && velocity.x == 0.0 && velocity.y == 0.0);
+
+        for _ in 0..120 {
+            physics.step(DT);
+        }
+        let body_ref = physics.borrow_body(body);
+        assert!((body_ref.get_position() - Vec3::new(1.0, 2.0, 0.5)).len() < 1.0e-2);
+        let ground = physics.ground_contact(body, physics.get_up_vector(), 45.0).unwrap();
+        assert!((ground.normal - Vec3::new(0.0, 0.0, 1.0)).len() < 1.0e-3);
+    }
+
+    #[test]
+    fn contacts_are_classified_as_ground_by_slope() {
+        // Returns contact of sphere which slightly penetrates half-space with given normal.
+        let touch = |normal: Vec3| {
+            let mut physics = Physics::new();
+            let mut surface = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(normal)));
+            surface.set_gravity(Vec3::ZERO);
+            physics.add_body(surface);
+            let mut body = sphere(normal.scale(0.45), 0.5);
+            body.set_gravity(Vec3::ZERO);
+            let body = physics.add_body(body);
+            physics.step(DT);
+            physics.borrow_body(body).get_contacts()[0].clone()
+        };
+        let up = Vec3::UP;
+        let angle = 30.0f32.to_radians();
+
+        assert!(touch(Vec3::UP).is_ground(up, 45.0));
+        let ramp = touch(Vec3::new(angle.sin(), angle.cos(), 0.0));
+        assert!(ramp.is_ground(up, 45.0));
+        assert!(!ramp.is_ground(up, 20.0));
+        assert!(!touch(Vec3::RIGHT).is_ground(up, 45.0));
+    }
+
+    #[test]
+    fn ground_contact_prefers_floor_over_wall() {
+        let mut physics = Physics::new();
+        let floor = physics.add_body(floor());
+        let mut wall = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(Vec3::RIGHT)));
+        wall.set_gravity(Vec3::ZERO);
+        let wall = physics.add_body(wall);
+        let body = physics.add_body(sphere(Vec3::new(2.0, 2.0, 0.0), 0.5));
+        physics.step(DT);
+        assert!(physics.ground_contact(body, Vec3::UP, 45.0).is_none());
+
+        // Sphere in corner presses against wall and rests on floor.
+        physics.borrow_body_mut(body).set_position(Vec3::new(0.45, 0.5, 0.0));
+        physics.step(DT);
+        let contacts = physics.borrow_body(body).get_contacts();
+        assert!(contacts.iter().any(|contact| contact.body == wall));
+        assert!(contacts.iter().any(|contact| contact.body == floor));
+
+        let ground = physics.ground_contact(body, Vec3::UP, 45.0).unwrap();
+        assert_eq!(ground.body, floor);
+        assert!((ground.normal - Vec3::UP).len() < 1.0e-3);
+    }
+
+    #[test]
+    fn static_and_body_contacts_are_split() {
+        let mut physics = Physics::new();
+        let ground = physics.add_static_geometry(platform(0.0, 5.0));
+        let body = physics.add_body(sphere(Vec3::new(0.0, 0.5, 0.0), 0.5));
+        let neighbour = physics.add_body(sphere(Vec3::new(0.95, 0.5, 0.0), 0.5));
+        physics.step(DT);
+
+        let body = physics.borrow_body(body);
+        let static_contacts: Vec<_> = body.static_contacts().collect();
+        let body_contacts: Vec<_> = body.body_contacts().collect();
+        assert!(!static_contacts.is_empty() && !body_contacts.is_empty());
+        assert!(static_contacts.iter().all(|contact| contact.is_with_static() && contact.static_geom == ground));
+        assert!(body_contacts.iter().all(|contact| !contact.is_with_static() && contact.body == neighbour));
+        assert_eq!(static_contacts.len() + body_contacts.len(), body.get_contacts().len());
+    }
+
+    #[test]
+    fn contact_normal_points_up_for_body_on_floor() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        physics.add_static_geometry(platform(3.0, 1.0));
+        let on_floor = physics.add_body(sphere(Vec3::new(10.0, 0.5, 0.0), 0.5));
+        let on_platform = physics.add_body(sphere(Vec3::new(0.0, 3.5, 0.0), 0.5));
+        physics.step(DT);
+
+        // Both floor body and static triangles give up normal.
+        for &handle in [on_floor, on_platform].iter() {
+            let contacts = physics.borrow_body(handle).get_contacts();
+            assert!(!contacts.is_empty());
+            assert!(contacts.iter().all(|contact| (contact.normal - Vec3::UP).len() < 1.0e-3));
+        }
+        assert!(physics.borrow_body(on_platform).get_contacts().iter().all(|contact| contact.is_with_static()));
+    }
+
+    #[test]
+    fn contact_normals_of_side_by_side_bodies_point_away_from_each_other() {
+        let mut physics = Physics::new();
+        let mut left = sphere(Vec3::new(-0.45, 0.0, 0.0), 0.5);
+        left.set_gravity(Vec3::ZERO);
+        let left = physics.add_body(left);
+        let mut right = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.5, 0.5))));
+        right.set_gravity(Vec3::ZERO).set_position(Vec3::new(0.45, 0.0, 0.0));
+        let right = physics.add_body(right);
+        physics.step(DT);
+
+        let normals = |handle| physics.borrow_body(handle).get_contacts().iter().map(|contact| contact.normal).collect::<Vec<_>>();
+        let (left_normals, right_normals) = (normals(left), normals(right));
+        assert!(!left_normals.is_empty() && !right_normals.is_empty());
+        assert!(left_normals.iter().all(|normal| (*normal - Vec3::new(-1.0, 0.0, 0.0)).len() < 1.0e-3));
+        assert!(right_normals.iter().all(|normal| (*normal - Vec3::RIGHT).len() < 1.0e-3));
+    }
+
+    #[test]
+    fn local_contact_point_stays_fixed_on_moving_body() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut body = sphere(Vec3::new(0.0, 0.5, 0.0), 0.5);
+        body.set_friction(Vec3::ZERO).set_velocity(Vec3::new(0.05, 0.0, 0.0));
+        let body = physics.add_body(body);
+        physics.step(DT);
+        let first = physics.borrow_body(body).get_contacts()[0].clone();
+
+        for _ in 0..30 {
+            physics.step(DT);
+            let contact = &physics.borrow_body(body).get_contacts()[0];
+            assert!((contact.local_position_a - first.local_position_a).len() < 1.0e-3);
+        }
+        // World point moves together with body, as well as point in space of floor.
+        let last = &physics.borrow_body(body).get_contacts()[0];
+        assert!(last.position.x - first.position.x > 1.0);
+        assert!((last.local_position_b - last.position).len() < 1.0e-5);
+    }
+
+    #[test]
+    fn new_contact_pair_is_listed_once_on_first_touch() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut base = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 0.5, 1.0))));
+        base.set_position(Vec3::new(0.0, 0.5, 0.0));
+        let base = physics.add_body(base);
+        let ball = physics.add_body(sphere(Vec3::new(0.0, 2.0, 0.0), 0.5));
+
+        let pair_count = |physics: &Physics| physics.new_contacts_this_step().iter()
+            .filter(|&&(a, b)| (a, b) == (ball, base) || (a, b) == (base, ball))
+            .count();
+        let touching = |physics: &Physics| physics.borrow_body(ball).get_contacts().iter().any(|contact| contact.body == base);
+
+        // Pair is listed only on step on which contact begins, not while it persists.
+        let (mut was_touching, mut touches, mut persisting_steps) = (false, 0, 0);
+        for _ in 0..120 {
+            physics.step(DT);
+            let is_touching = touching(&physics);
+            let began = is_touching && !was_touching;
+            assert_eq!(pair_count(&physics), began as usize);
+            touches += began as usize;
+            persisting_steps += (is_touching && was_touching) as usize;
+            was_touching = is_touching;
+        }
+        assert!(touches > 0 && persisting_steps > 0);
+    }
+
+    #[test]
+    fn strongest_contacts_are_sorted_by_impulse() {
+        let mut physics = Physics::new();
+        physics.add_static_geometry(platform(0.0, 20.0));
+        // Spheres touching platform land with different speeds on same step.
+        let handles: Vec<_> = [2.0, 4.0, 1.0, 3.0].iter().enumerate().map(|(i, &speed)| {
+            let mut body = sphere(Vec3::new(i as f32 * 2.0 - 3.0, 0.5, 0.0), 0.5);
+            body.set_velocity(Vec3::new(0.0, -speed * DT, 0.0));
+            physics.add_body(body)
+        }).collect();
+        physics.step(DT);
+
+        let strongest = physics.strongest_contacts(2);
+        assert_eq!(strongest.len(), 2);
+        assert_eq!(strongest[0].0, handles[1]);
+        assert_eq!(strongest[1].0, handles[3]);
+        assert!(strongest.iter().all(|(_, other, _)| other.is_none()));
+        assert!(strongest[0].2.impulse > strongest[1].2.impulse);
+        // Weaker contacts exist, but are not returned.
+        let weakest = physics.borrow_body(handles[2]).get_contacts().iter().map(|contact| contact.impulse).fold(0.0, f32::max);
+        assert!(weakest > 0.0 && weakest < strongest[1].2.impulse);
+    }
+
+    #[test]
+    fn response_filter_disables_response_of_one_pair_only() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut wall = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.25, 1.0, 5.0))));
+        wall.set_position(Vec3::new(0.0, 1.0, 0.0));
+        let wall = physics.add_body(wall);
+        let mut launch = |z: f32| {
+            let mut body = sphere(Vec3::new(-2.0, 0.5, z), 0.5);
+            body.set_friction(Vec3::ZERO).set_velocity(Vec3::new(0.05, 0.0, 0.0));
+            physics.add_body(body)
+        };
+        let (ghost, blocked) = (launch(-2.0), launch(2.0));
+        physics.set_response_filter(Box::new(move |info| {
+            !((info.body == ghost && info.other_body == wall) || (info.body == wall && info.other_body == ghost))
+        }));
+
+        let mut ghost_touched_wall = false;
+        for _ in 0..120 {
+            physics.step(DT);
+            ghost_touched_wall |= physics.borrow_body(ghost).get_contacts().iter().any(|contact| contact.body == wall);
+        }
+
+        // Ghost passes through wall while still resting on floor, other body is stopped.
+        assert!(ghost_touched_wall);
+        let ghost = physics.borrow_body(ghost).get_position();
+        assert!(ghost.x > 2.0 && (ghost.y - 0.5).abs() < 1.0e-2);
+        assert!(physics.borrow_body(blocked).get_position().x < physics.borrow_body(wall).get_position().x);
+    }
+
+    #[test]
+    fn overlap_depth_grows_as_spheres_move_closer() {
+        let mut physics = Physics::new();
+        let a = physics.add_body(sphere(Vec3::ZERO, 0.5));
+        let b = physics.add_body(sphere(Vec3::new(1.2, 0.0, 0.0), 0.5));
+        assert!(physics.overlap_depth(a, b).is_none());
+
+        let mut last_depth = 0.0;
+        for i in 1..10 {
+            let distance = 1.0 - i as f32 * 0.1;
+            physics.borrow_body_mut(b).set_position(Vec3::new(distance, 0.0, 0.0));
+            let depth = physics.overlap_depth(a, b).unwrap();
+            assert!(depth > last_depth);
+            // EPA approximates spheres by polytope, so depth is close to exact one.
+            assert!((depth - (1.0 - distance)).abs() < 1.0e-2);
+            last_depth = depth;
+        }
+    }
+
+    #[test]
+    fn overlap_aabb_is_intersection_of_box_bounds() {
+        let mut physics = Physics::new();
+        let mut add_box = |position: Vec3, half_extents: Vec3| {
+            let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(half_extents)));
+            body.set_position(position);
+            physics.add_body(body)
+        };
+        let a = add_box(Vec3::ZERO, Vec3::new(1.0, 1.0, 1.0));
+        let b = add_box(Vec3::new(1.5, 0.5, 0.0), Vec3::new(1.0, 0.5, 2.0));
+        let c = add_box(Vec3::new(5.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+
+        // Bounds are x in [-1; 1] and [0.5; 2.5], y in [-1; 1] and [0; 1], z in [-1; 1] and [-2; 2].
+        let overlap = physics.overlap_aabb(a, b).unwrap();
+        assert!((overlap.min - Vec3::new(0.5, 0.0, -1.0)).len() < 1.0e-5);
+        assert!((overlap.max - Vec3::new(1.0, 1.0, 1.0)).len() < 1.0e-5);
+        let reversed = physics.overlap_aabb(b, a).unwrap();
+        assert_eq!((reversed.min, reversed.max), (overlap.min, overlap.max));
+
+        assert!(physics.overlap_aabb(a, c).is_none());
+    }
+
+    #[test]
+    fn world_aabb_encloses_bodies_tightly() {
+        let mut physics = Physics::new();
+        let empty = physics.world_aabb();
+        assert!(empty.min.x > empty.max.x);
+
+        physics.add_body(sphere(Vec3::new(-3.0, 1.0, 0.0), 0.5));
+        physics.add_body(sphere(Vec3::new(2.0, -1.0, 4.0), 1.0));
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 2.0, 0.5))));
+        body.set_position(Vec3::new(0.0, 3.0, -2.0));
+        physics.add_body(body);
+
+        let aabb = physics.world_aabb();
+        assert!((aabb.min - Vec3::new(-3.5, -2.0, -2.5)).len() < 1.0e-5);
+        assert!((aabb.max - Vec3::new(3.0, 5.0, 5.0)).len() < 1.0e-5);
+    }
+
+    #[test]
+    fn sphere_rests_on_half_space_of_kinematic_body() {
+        let mut physics = Physics::new();
+        // Water plane which is moved by game code, collisions do not move half-spaces.
+        let mut water = RigidBody::new(ConvexShape::HalfSpace(HalfSpaceShape::new(Vec3::UP)));
+        water.set_gravity(Vec3::ZERO);
+        let water = physics.add_body(water);
+        let ball = physics.add_body(sphere(Vec3::new(0.0, 1.0, 0.0), 0.5));
+
+        for i in 0..240 {
+            // Plane stays still for a second, then rises by one unit during next two seconds.
+            let height = (i as f32 - 60.0).clamp(0.0, 120.0) / 120.0;
+            physics.borrow_body_mut(water).set_position(Vec3::new(0.0, height, 0.0));
+            physics.step(DT);
+            assert!((physics.borrow_body(water).get_position() - Vec3::new(0.0, height, 0.0)).len() < 1.0e-6);
+            if i == 59 {
+                assert!((physics.borrow_body(ball).get_position().y - 0.5).abs() < 1.0e-2);
+            }
+        }
+
+        let ball = physics.borrow_body(ball);
+        assert!(ball.get_contacts().iter().any(|contact| contact.body == water));
+        assert!((ball.get_position().y - 1.5).abs() < 1.0e-2);
+    }
+
+    #[test]
+    fn for_each_body_visits_every_body() {
+        let mut physics = Physics::new();
+        let handles: Vec<_> = (0..5).map(|i| physics.add_body(sphere(Vec3::new(i as f32, 2.0 * i as f32, -(i as f32)), 0.5))).collect();
+        physics.remove_body(handles[2]);
+
+        let mut sum = Vec3::ZERO;
+        physics.for_each_body(|_, body| sum += body.get_position());
+        let mut expected = Vec3::ZERO;
+        for &handle in handles.iter().filter(|&&handle| physics.is_valid_body_handle(handle)) {
+            expected += physics.borrow_body(handle).get_position();
+        }
+        assert_eq!(sum, expected);
+        assert_eq!(sum, Vec3::new(8.0, 16.0, -8.0));
+    }
+
+    #[test]
+    fn ray_hit_reports_user_flags_of_target() {
+        let mut physics = Physics::new();
+        let mut target = sphere(Vec3::new(0.0, 2.0, 0.0), 0.5);
+        target.user_flags = 0xBEEF;
+        target.collision_group = 0b100;
+        let target = physics.add_body(target);
+        let mut other = sphere(Vec3::new(0.0, 4.0, 0.0), 0.5);
+        other.user_flags = 7;
+        physics.add_body(other);
+        let mut ground = platform(0.0, 1.0);
+        ground.user_flags = 42;
+        let ground = physics.add_static_geometry(ground);
+
+        let mut result = Vec::new();
+        // Ray misses diagonal edge of platform, so it hits one triangle.
+        let ray = Ray::from_two_points(&Vec3::new(0.2, 10.0, -0.2), &Vec3::new(0.2, -1.0, -0.2)).unwrap();
+        assert!(physics.ray_cast(&ray, RayCastOptions::default(), &mut result));
+        // Ray enters and leaves each sphere.
+        assert_eq!(result.len(), 5);
+        for hit in result.iter() {
+            match hit.kind {
+                HitKind::Body(handle) if handle == target => assert_eq!((hit.user_flags, hit.collision_group), (0xBEEF, 0b100)),
+                HitKind::Body(_) => assert_eq!(hit.user_flags, 7),
+                HitKind::StaticTriangle { static_geometry, .. } => {
+                    assert_eq!(static_geometry, ground);
+                    assert_eq!((hit.user_flags, hit.collision_group), (42, 0));
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,81 @@
+//! Seedable pseudo-random generator owned by physics world, so sampling is reproducible
+//! for replays: same seed and same inputs give same results on every run.
+
+use rg3d_core::visitor::{Visit, VisitResult, Visitor};
+
+/// SplitMix64 generator. It is tiny and fast, and good enough for sampling of points.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Prng {
+    state: u64,
+}
+
+impl Default for Prng {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SEED)
+    }
+}
+
+impl Visit for Prng {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.state.visit("State", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl Prng {
+    pub const DEFAULT_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns uniformly distributed number in `[0; 1)` range.
+    pub fn next_f32(&mut self) -> f32 {
+        // 24 bits is precision of f32 mantissa, so every value is exact.
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::math::vec3::Vec3;
+    use crate::{
+        Physics,
+        rigid_body::RigidBody,
+        convex_shape::{ConvexShape, BoxShape},
+        test::sphere,
+    };
+
+    /// Draws points inside of two bodies of world seeded with given seed.
+    fn draw_points(seed: u64) -> Vec<[u32; 3]> {
+        let mut physics = Physics::new();
+        let ball = physics.add_body(sphere(Vec3::new(1.0, 2.0, 3.0), 0.5));
+        let mut cube = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 0.5, 2.0))));
+        cube.set_position(Vec3::new(-4.0, 0.0, 0.0));
+        let cube = physics.add_body(cube);
+        physics.set_seed(seed);
+        (0..100)
+            .map(|i| physics.random_point_inside_body(if i % 2 == 0 { ball } else { cube }).unwrap())
+            .map(|p| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()])
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_interior_points() {
+        assert_eq!(draw_points(12345), draw_points(12345));
+        assert_ne!(draw_points(12345), draw_points(54321));
+    }
+}
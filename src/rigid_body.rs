@@ -1,16 +1,27 @@
 use rg3d_core::{
-    math::vec3::Vec3,
+    math::{
+        vec3::Vec3,
+        quat::Quat,
+        aabb::AxisAlignedBoundingBox,
+        plane::Plane,
+        mat3::Mat3,
+    },
     visitor::{Visit, VisitResult, Visitor},
     pool::Handle
 };
+use std::{any::Any, collections::HashSet};
 use crate::{
-    contact::Contact,
-    convex_shape::{ConvexShape, TriangleShape},
-    gjk_epa,
+    contact::{Contact, ContactInfo, ContactFilter, ResponseFilter},
+    convex_shape::{ConvexShape, HalfSpaceShape, Inflated, ShapeTransform, SupportMapping},
+    gjk_epa::{self, EpaScratch},
+    float_ops::{Backend, FloatOps},
     static_geometry::{
         StaticTriangle,
-        StaticGeometry
-    }
+        StaticGeometry,
+        TriangleFeatureNormals
+    },
+    gravity::{DEFAULT_GRAVITY, Attractor},
+    constraint::KinematicTarget,
 };
 
 bitflags! {
@@ -18,24 +29,137 @@ bitflags! {
         const NONE = 0;
         /// Collision response will be disabled but body still will gather contact information.
         const DISABLE_COLLISION_RESPONSE = 1;
+        /// Body won't be integrated and won't take part in contact solving, but it
+        /// still will be found by queries (ray casts, etc).
+        const QUERY_ONLY = 2;
+        /// Overlaps with body are found by testing bounding boxes only, without exact
+        /// shape tests. Useful for huge axis-aligned sensors, where exact test is a waste.
+        /// Contacts are still generated: contact point is center of intersection of boxes
+        /// and normal is world axis of least overlap. Has no effect against half-spaces.
+        const AABB_ONLY = 4;
+        /// Body sticks to other bodies it touches: when contact begins, bodies are welded
+        /// in their relative transform until released by `Physics::release_stuck`.
+        const STICKY = 8;
+    }
+}
+
+/// Settings of penetration queries between bodies and static geometry.
+#[derive(Copy, Clone, Debug)]
+pub struct PenetrationSettings {
+    /// Whether to use sampled penetration search when EPA fails, see `Physics::set_epa_fallback`.
+    pub epa_fallback: bool,
+    /// Separation which is kept between resting bodies, see `Physics::set_resting_clearance`.
+    pub clearance: f32,
+    /// Up vector of world, used as contact normal when no other direction can be found.
+    pub up: Vec3,
+}
+
+impl Default for PenetrationSettings {
+    fn default() -> Self {
+        Self {
+            epa_fallback: false,
+            clearance: 0.0,
+            up: Vec3::UP,
+        }
+    }
+}
+
+/// Shared state of collision solver which is passed into collision solving methods.
+pub struct SolverContext<'a> {
+    pub scratch: &'a mut EpaScratch,
+    pub contact_filter: Option<&'a ContactFilter>,
+    pub response_filter: Option<&'a ResponseFilter>,
+    /// Contacts are only gathered when false, see `Physics::detect_only`.
+    pub apply_response: bool,
+    /// Distance at which shapes generate contact before they touch, see `Physics::set_contact_margin`.
+    pub contact_margin: f32,
+    /// Multiplier of penetration correction, see `Physics::set_solver_relaxation`.
+    pub relaxation: f32,
+    pub penetration: PenetrationSettings,
+    /// Index of current solver iteration, see `Physics::set_solver_iterations`.
+    pub iteration: usize,
+    /// Deepest penetration found during current solver iteration.
+    pub max_depth: f32,
+    /// Pairs of welded bodies in both orders, they don't collide with each other.
+    pub welded_pairs: &'a HashSet<(Handle<RigidBody>, Handle<RigidBody>)>,
+}
+
+impl<'a> SolverContext<'a> {
+    /// Passes contact through contact filter (if any), returns `false` if contact was rejected.
+    fn filter(&self, info: &mut ContactInfo) -> bool {
+        let accepted = match self.contact_filter {
+            Some(filter) => filter(info),
+            None => true,
+        };
+        #[cfg(feature = "debug-checks")]
+        {
+            if accepted {
+                assert!(info.depth >= 0.0 && info.depth.is_finite(),
+                        "debug-checks: contact of body {:?} has invalid penetration depth {} (other body {:?}, static geometry {:?})",
+                        info.body, info.depth, info.other_body, info.static_geom);
+            }
+        }
+        accepted
+    }
+
+    /// Returns penetration settings with shapes inflated by contact margin.
+    fn speculative_settings(&self) -> PenetrationSettings {
+        PenetrationSettings {
+            clearance: self.penetration.clearance + self.contact_margin,
+            ..self.penetration
+        }
+    }
+
+    /// Asks response filter (if any) whether position correction applies to contact.
+    fn allows_response(&self, info: &ContactInfo) -> bool {
+        if !self.apply_response {
+            return false;
+        }
+        match self.response_filter {
+            Some(filter) => filter(info),
+            None => true,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct RigidBody {
     pub(in crate) position: Vec3,
+    pub(in crate) rotation: Quat,
     pub(in crate) shape: ConvexShape,
+    pub(in crate) margin: f32,
     pub(in crate) last_position: Vec3,
+    /// Position before last integration. Last position of body integrated in substeps is
+    /// extrapolated from its final velocity, so it is not where body started the step.
+    pub(in crate) step_start_position: Vec3,
     pub(in crate) acceleration: Vec3,
     pub(in crate) contacts: Vec<Contact>,
     pub(in crate) friction: Vec3,
+    pub(in crate) rolling_friction: f32,
+    pub(in crate) response_coefficient: f32,
+    pub(in crate) restitution: f32,
+    pub(in crate) shape_scale: f32,
     pub(in crate) gravity: Vec3,
+    pub(in crate) gravity_scale: f32,
+    pub(in crate) fall_gravity_scale: f32,
     pub(in crate) speed_limit: f32,
+    pub(in crate) substeps: u32,
     pub(in crate) lifetime: Option<f32>,
+    pub(in crate) attractor: Option<Attractor>,
+    /// Target of grab, see [`Physics::grab`](crate::Physics::grab).
+    pub(in crate) grab: Option<KinematicTarget>,
+    pub(in crate) enabled: bool,
+    /// Steps left during which body is considered touching after its contacts has ended.
+    pub(in crate) contact_grace: u32,
+    pub(in crate) last_contact_position: Vec3,
+    pub(in crate) hysteresis_active: bool,
     pub user_flags: u64,
+    pub(in crate) user_data: Option<Box<dyn Any + Send>>,
     pub collision_group: u64,
     pub collision_mask: u64,
     pub collision_flags: CollisionFlags,
+    #[cfg(feature = "debug-history")]
+    pub(in crate) contact_history: Vec<Vec<Contact>>,
 }
 
 impl Default for RigidBody {
@@ -54,14 +178,31 @@ impl Visit for RigidBody {
             self.shape = ConvexShape::new(id)?;
         }
         self.shape.visit("Shape", visitor)?;
+        let _ = self.margin.visit("Margin", visitor); // let _ for backward compatibility.
 
         self.position.visit("Position", visitor)?;
+        let _ = self.rotation.visit("Rotation", visitor); // let _ for backward compatibility.
         self.last_position.visit("LastPosition", visitor)?;
+        if visitor.is_reading() {
+            // Older files don't have it, last position is close enough.
+            self.step_start_position = self.last_position;
+        }
+        let _ = self.step_start_position.visit("StepStartPosition", visitor);
         self.acceleration.visit("Acceleration", visitor)?;
         self.contacts.visit("Contacts", visitor)?;
         self.friction.visit("Friction", visitor)?;
+        let _ = self.rolling_friction.visit("RollingFriction", visitor); // let _ for backward compatibility.
+        let _ = self.response_coefficient.visit("ResponseCoefficient", visitor);
+        let _ = self.restitution.visit("Restitution", visitor);
+        let _ = self.shape_scale.visit("ShapeScale", visitor);
+        let _ = self.attractor.visit("Attractor", visitor);
+        let _ = self.grab.visit("Grab", visitor);
         self.gravity.visit("Gravity", visitor)?;
+        let _ = self.gravity_scale.visit("GravityScale", visitor);
+        let _ = self.fall_gravity_scale.visit("FallGravityScale", visitor);
         self.speed_limit.visit("SpeedLimit", visitor)?;
+        let _ = self.substeps.visit("Substeps", visitor);
+        let _ = self.enabled.visit("Enabled", visitor); // let _ for backward compatibility.
         self.user_flags.visit("UserFlags", visitor)?;
         self.collision_group.visit("CollisionGroup", visitor)?;
         self.collision_mask.visit("CollisionMask", visitor)?;
@@ -76,42 +217,86 @@ impl Visit for RigidBody {
     }
 }
 
+/// Copy does not belong to any world yet, so state which only makes sense in world is
+/// not copied: contacts, user data and grab (copy of grabbed body is not grabbed).
 impl Clone for RigidBody {
     fn clone(&self) -> Self {
         Self {
             position: self.position,
+            rotation: self.rotation,
             last_position: self.last_position,
+            step_start_position: self.step_start_position,
             acceleration: self.acceleration,
             contacts: Vec::new(),
             friction: self.friction,
+            rolling_friction: self.rolling_friction,
+            response_coefficient: self.response_coefficient,
+            restitution: self.restitution,
+            shape_scale: self.shape_scale,
             gravity: self.gravity,
+            gravity_scale: self.gravity_scale,
+            fall_gravity_scale: self.fall_gravity_scale,
             shape: self.shape.clone(),
+            margin: self.margin,
             speed_limit: self.speed_limit,
+            substeps: self.substeps,
             lifetime: self.lifetime,
+            attractor: self.attractor,
+            grab: None,
+            enabled: self.enabled,
+            contact_grace: 0,
+            last_contact_position: self.last_contact_position,
+            hysteresis_active: false,
             user_flags: self.user_flags,
+            user_data: None,
             collision_group: self.collision_group,
             collision_mask: self.collision_mask,
-            collision_flags: CollisionFlags::NONE
+            collision_flags: self.collision_flags,
+            #[cfg(feature = "debug-history")]
+            contact_history: Vec::new(),
         }
     }
 }
 
 impl RigidBody {
+    /// Barycentric weight below which contact point is considered to lie on edge or
+    /// vertex of triangle.
+    pub const FEATURE_TOLERANCE: f32 = 0.001;
+
     pub fn new(shape: ConvexShape) -> Self {
         Self {
             position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
             last_position: Vec3::ZERO,
+            step_start_position: Vec3::ZERO,
             acceleration: Vec3::ZERO,
             friction: Vec3::new(0.2, 0.2, 0.2),
-            gravity: Vec3::new(0.0, -9.81, 0.0),
+            rolling_friction: 0.0,
+            response_coefficient: 1.0,
+            restitution: 0.0,
+            shape_scale: 1.0,
+            gravity: Vec3::new(0.0, -DEFAULT_GRAVITY, 0.0),
+            gravity_scale: 1.0,
+            fall_gravity_scale: 1.0,
+            margin: shape.default_margin(),
             shape,
             contacts: Vec::new(),
-            speed_limit: 0.75,
+            speed_limit: f32::MAX,
+            substeps: 1,
             lifetime: None,
+            attractor: None,
+            grab: None,
+            enabled: true,
+            contact_grace: 0,
+            last_contact_position: Vec3::ZERO,
+            hysteresis_active: false,
             user_flags: 0,
+            user_data: None,
             collision_group: 1,
-            collision_mask: std::u64::MAX,
-            collision_flags: CollisionFlags::NONE
+            collision_mask: u64::MAX,
+            collision_flags: CollisionFlags::NONE,
+            #[cfg(feature = "debug-history")]
+            contact_history: Vec::new(),
         }
     }
 
@@ -120,10 +305,40 @@ impl RigidBody {
         self.position
     }
 
+    /// Sets orientation of body. Orientation is not integrated by solver, it stays the
+    /// same until changed by this method.
+    pub fn set_rotation(&mut self, rotation: Quat) -> &mut Self {
+        self.rotation = rotation;
+        self
+    }
+
+    #[inline]
+    pub fn get_rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    /// Returns world space transform of shape of body.
+    #[inline]
+    pub fn get_transform(&self) -> ShapeTransform {
+        ShapeTransform::new(self.position, self.rotation)
+    }
+
     #[inline]
     pub fn set_position(&mut self, p: Vec3) -> &mut Self {
         self.position = p;
         self.last_position = p;
+        self.step_start_position = p;
+        self
+    }
+
+    /// Teleports body to given position and gives it given velocity (in units per step,
+    /// see [`get_velocity`](Self::get_velocity)). Contacts are cleared since they are no
+    /// longer valid at new position.
+    pub fn launch(&mut self, position: Vec3, velocity: Vec3) -> &mut Self {
+        self.position = position;
+        self.last_position = position - velocity;
+        self.step_start_position = position;
+        self.contacts.clear();
         self
     }
 
@@ -142,9 +357,60 @@ impl RigidBody {
     #[inline]
     pub fn set_shape(&mut self, shape: ConvexShape) -> &mut Self {
         self.shape = shape;
+        self.shape_scale = 1.0;
         self
     }
 
+    /// Sets uniform scale of shape relative to shape which was given to body (scale
+    /// is reset to one by [`set_shape`](Self::set_shape)), like for growing or shrinking
+    /// character. Shape is rescaled in place, see [`ConvexShape::scale_uniform`]. Bodies
+    /// have unit mass regardless of shape, so nothing else depends on scale. Zero scale
+    /// is ignored, since shape can't be restored from it. Grown body may penetrate things
+    /// it touches and will be pushed out with high speed, use [`Physics::depenetrate_body`](crate::Physics::depenetrate_body)
+    /// right after scaling to avoid that.
+    pub fn set_shape_scale(&mut self, scale: f32) -> &mut Self {
+        let scale = scale.abs();
+        if scale > 0.0 {
+            self.shape.scale_uniform(scale / self.shape_scale);
+            self.shape_scale = scale;
+        }
+        self
+    }
+
+    #[inline]
+    pub fn get_shape_scale(&self) -> f32 {
+        self.shape_scale
+    }
+
+    /// Returns inertia tensor of body in its local space, see [`ConvexShape::inertia_tensor`].
+    /// Bodies have unit mass. Solver does not simulate rotational dynamics, so tensor is
+    /// not used by physics itself.
+    pub fn get_inertia_tensor(&self) -> Mat3 {
+        self.shape.inertia_tensor(1.0)
+    }
+
+    /// Attaches arbitrary data to body, like entity id, replacing previous data. User data
+    /// is neither cloned nor serialized: copies of body and loaded bodies have no data.
+    pub fn set_user_data<T: Any + Send>(&mut self, data: T) -> &mut Self {
+        self.user_data = Some(Box::new(data));
+        self
+    }
+
+    /// Removes user data from body and returns it.
+    pub fn take_user_data(&mut self) -> Option<Box<dyn Any + Send>> {
+        self.user_data.take()
+    }
+
+    /// Returns user data if it has given type.
+    pub fn user_data_ref<T: Any>(&self) -> Option<&T> {
+        self.user_data.as_ref().and_then(|data| data.downcast_ref())
+    }
+
+    /// Returns user data if it has given type.
+    pub fn user_data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.user_data.as_mut().and_then(|data| data.downcast_mut())
+    }
+
     #[inline]
     pub fn get_shape(&self) -> &ConvexShape {
         &self.shape
@@ -155,11 +421,50 @@ impl RigidBody {
         &mut self.shape
     }
 
+    /// Sets collision margin. Shape is inflated by margin for collision detection, this
+    /// makes detection robust for flat and thin shapes, but body will rest at margin
+    /// distance from other shapes. Contact points are still reported on surface of shape.
+    /// Default margin depends on shape, see [`ConvexShape::default_margin`].
+    pub fn set_margin(&mut self, margin: f32) -> &mut Self {
+        self.margin = margin.max(0.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_margin(&self) -> f32 {
+        self.margin
+    }
+
+    /// Returns world space bounding box of body's shape.
+    #[inline]
+    pub fn get_aabb(&self) -> AxisAlignedBoundingBox {
+        let mut aabb = self.shape.compute_aabb(&self.get_transform());
+        if self.margin > 0.0 && !self.shape.is_half_space() {
+            let margin = Vec3::new(self.margin, self.margin, self.margin);
+            aabb.min -= margin;
+            aabb.max += margin;
+        }
+        aabb
+    }
+
+    /// Returns bounding box of motion of body during last step - union of bounding boxes
+    /// of body at last position and at current position. Any obstacle which body could
+    /// have touched on its way intersects this box, so it is a volume for selection of
+    /// candidates for continuous collision detection.
+    pub fn get_swept_aabb(&self) -> AxisAlignedBoundingBox {
+        let mut aabb = self.get_aabb();
+        let offset = self.last_position - self.position;
+        let (min, max) = (aabb.min, aabb.max);
+        aabb.add_point(min + offset);
+        aabb.add_point(max + offset);
+        aabb
+    }
+
     #[inline]
     pub fn set_friction(&mut self, friction: Vec3) -> &mut Self {
-        self.friction.x = friction.x.max(0.0).min(1.0);
-        self.friction.y = friction.y.max(0.0).min(1.0);
-        self.friction.z = friction.z.max(0.0).min(1.0);
+        self.friction.x = friction.x.clamp(0.0, 1.0);
+        self.friction.y = friction.y.clamp(0.0, 1.0);
+        self.friction.z = friction.z.clamp(0.0, 1.0);
         self
     }
 
@@ -168,6 +473,78 @@ impl RigidBody {
         self.friction
     }
 
+    /// Sets rolling resistance coefficient. Rolling resistance decelerates motion of body
+    /// along supporting surface proportionally to normal force (gravity pressing body to
+    /// surface) until body stops. Unlike sliding friction, it acts only when body has
+    /// contacts.
+    pub fn set_rolling_friction(&mut self, rolling_friction: f32) -> &mut Self {
+        self.rolling_friction = rolling_friction.max(0.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_rolling_friction(&self) -> f32 {
+        self.rolling_friction
+    }
+
+    /// Sets fraction (in `[0; 1]` range) of collision push which is applied to this body,
+    /// lower values make body yield softly and separate from other parties of contact
+    /// over several steps. Default is 1.0 - full push.
+    pub fn set_response_coefficient(&mut self, response_coefficient: f32) -> &mut Self {
+        self.response_coefficient = response_coefficient.clamp(0.0, 1.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_response_coefficient(&self) -> f32 {
+        self.response_coefficient
+    }
+
+    /// Sets fraction (in `[0; 1]` range) of approach speed which body keeps after impact,
+    /// bouncing away from obstacle. Larger restitution of two bodies is used for their
+    /// contact. Slow impacts do not bounce, see [`Physics::set_restitution_threshold`](crate::Physics::set_restitution_threshold).
+    /// Default is 0.0 - no bounce.
+    pub fn set_restitution(&mut self, restitution: f32) -> &mut Self {
+        self.restitution = restitution.clamp(0.0, 1.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_restitution(&self) -> f32 {
+        self.restitution
+    }
+
+    /// Applies rolling resistance using contact which presses body the most against
+    /// gravity. Velocity along contact normal is not affected.
+    pub(in crate) fn apply_rolling_friction(&mut self, gravity: Vec3, sqr_delta_time: f32) {
+        if self.rolling_friction <= 0.0 {
+            return;
+        }
+
+        let support = self.contacts.iter()
+            .map(|contact| (contact.normal, -gravity.dot(&contact.normal)))
+            .fold(None, |best: Option<(Vec3, f32)>, (normal, normal_accel)| {
+                if normal_accel > best.map_or(0.0, |(_, accel)| accel) {
+                    Some((normal, normal_accel))
+                } else {
+                    best
+                }
+            });
+
+        if let Some((normal, normal_accel)) = support {
+            let velocity = self.get_velocity();
+            let tangent_velocity = velocity - normal.scale(velocity.dot(&normal));
+            let deceleration = self.rolling_friction * normal_accel * sqr_delta_time;
+            let speed = Backend::sqrt(tangent_velocity.sqr_len());
+            let change = if speed <= deceleration {
+                tangent_velocity
+            } else {
+                tangent_velocity.scale(deceleration / speed)
+            };
+            self.last_position += change;
+        }
+    }
+
     #[inline]
     pub fn set_x_velocity(&mut self, x: f32) -> &mut Self {
         self.last_position.x = self.position.x - x;
@@ -192,16 +569,108 @@ impl RigidBody {
         self
     }
 
+    /// Sets maximum speed of body (in units per step, see [`get_velocity`](Self::get_velocity)),
+    /// faster bodies are slowed down to it on each step. There is no limit by default,
+    /// except world-wide safety clamp, see `Physics::set_max_linear_velocity`.
+    pub fn set_speed_limit(&mut self, speed_limit: f32) -> &mut Self {
+        self.speed_limit = speed_limit.abs();
+        self
+    }
+
+    #[inline]
+    pub fn get_speed_limit(&self) -> f32 {
+        self.speed_limit
+    }
+
+    /// Sets amount of smaller steps in which body is integrated within each step of world,
+    /// other bodies are not affected. More substeps make trajectory of body more accurate
+    /// and its motion is tested for collisions in same amount of pieces, which is useful
+    /// for small fast precision objects. Default is 1, zero is treated as 1.
+    pub fn set_substeps(&mut self, substeps: u32) -> &mut Self {
+        self.substeps = substeps.max(1);
+        self
+    }
+
+    #[inline]
+    pub fn get_substeps(&self) -> u32 {
+        self.substeps
+    }
+
     #[inline]
     pub fn get_velocity(&self) -> Vec3 {
         self.position - self.last_position
     }
 
+    /// Applies instant impulse to body. Bodies have unit mass, so impulse directly
+    /// changes velocity (in units per step, see [`get_velocity`](Self::get_velocity)).
+    pub fn apply_impulse(&mut self, impulse: Vec3) -> &mut Self {
+        self.last_position -= impulse;
+        self
+    }
+
     #[inline]
     pub fn get_contacts(&self) -> &[Contact] {
         self.contacts.as_slice()
     }
 
+    /// Returns amount of contacts which body can store without reallocation, see
+    /// [`Physics::set_contact_shrink_interval`](crate::Physics::set_contact_shrink_interval).
+    #[inline]
+    pub fn get_contact_capacity(&self) -> usize {
+        self.contacts.capacity()
+    }
+
+    /// Returns contacts with static geometry, see [`Contact::is_with_static`].
+    pub fn static_contacts(&self) -> impl Iterator<Item=&Contact> {
+        self.contacts.iter().filter(|contact| contact.is_with_static())
+    }
+
+    /// Returns contacts with other bodies.
+    pub fn body_contacts(&self) -> impl Iterator<Item=&Contact> {
+        self.contacts.iter().filter(|contact| !contact.is_with_static())
+    }
+
+    /// Returns indices of static triangles on which body stands - triangles of static
+    /// contacts which are ground with given up vector and slope limit (in degrees), see
+    /// [`Contact::is_ground`]. Each triangle is reported once, together with per-triangle
+    /// materials this gives surface of footsteps. Triangles of different static geometries
+    /// may have same index, use [`static_contacts`](Self::static_contacts) to tell them apart.
+    pub fn supporting_triangles(&self, up: Vec3, max_slope_deg: f32) -> Vec<u32> {
+        let mut triangles = Vec::new();
+        for contact in self.static_contacts().filter(|contact| contact.is_ground(up, max_slope_deg)) {
+            if !triangles.contains(&contact.triangle_index) {
+                triangles.push(contact.triangle_index);
+            }
+        }
+        triangles
+    }
+
+    /// Returns contacts of last few steps, oldest first. Last entry is the same as
+    /// [`Self::get_contacts`]. Amount of steps is set by `Physics::set_contact_history_depth`.
+    #[cfg(feature = "debug-history")]
+    #[inline]
+    pub fn contact_history(&self) -> &[Vec<Contact>] {
+        &self.contact_history
+    }
+
+    /// Moves heap storage (contact list) of removed body into this body, so it is reused
+    /// instead of allocating new one.
+    pub(in crate) fn reuse_storage(&mut self, mut removed: RigidBody) {
+        if self.contacts.is_empty() && self.contacts.capacity() < removed.contacts.capacity() {
+            removed.contacts.clear();
+            self.contacts = removed.contacts;
+        }
+    }
+
+    #[cfg(feature = "debug-history")]
+    pub(in crate) fn record_contact_history(&mut self, depth: usize) {
+        self.contact_history.push(self.contacts.clone());
+        if self.contact_history.len() > depth {
+            let excess = self.contact_history.len() - depth;
+            self.contact_history.drain(0..excess);
+        }
+    }
+
     #[inline]
     pub fn set_gravity(&mut self, gravity: Vec3) -> &mut Self {
         self.gravity = gravity;
@@ -213,6 +682,43 @@ impl RigidBody {
         self.gravity
     }
 
+    /// Sets multiplier of gravity which is used while body moves up or rests. Default is 1.
+    #[inline]
+    pub fn set_gravity_scale(&mut self, scale: f32) -> &mut Self {
+        self.gravity_scale = scale;
+        self
+    }
+
+    #[inline]
+    pub fn get_gravity_scale(&self) -> f32 {
+        self.gravity_scale
+    }
+
+    /// Sets multiplier of gravity which is used instead of [`gravity scale`](Self::set_gravity_scale)
+    /// while body moves down (along up vector of world, see [`Physics::set_up_vector`](crate::Physics::set_up_vector)).
+    /// Values above 1 make body fall faster than it rises, which makes jumps snappier.
+    /// Default is 1.
+    #[inline]
+    pub fn set_fall_gravity_scale(&mut self, scale: f32) -> &mut Self {
+        self.fall_gravity_scale = scale;
+        self
+    }
+
+    #[inline]
+    pub fn get_fall_gravity_scale(&self) -> f32 {
+        self.fall_gravity_scale
+    }
+
+    /// Scales given gravity by gravity scale or fall gravity scale depending on direction
+    /// of motion of body along up vector.
+    pub(in crate) fn scale_gravity(&self, gravity: Vec3, up: Vec3) -> Vec3 {
+        if self.get_velocity().dot(&up) < 0.0 {
+            gravity.scale(self.fall_gravity_scale)
+        } else {
+            gravity.scale(self.gravity_scale)
+        }
+    }
+
     #[inline]
     pub fn set_lifetime(&mut self, time_seconds: f32) -> &mut Self {
         self.lifetime = Some(time_seconds);
@@ -224,87 +730,805 @@ impl RigidBody {
         self.lifetime
     }
 
+    /// Makes body pull other bodies, see [`Attractor`]. `None` removes attractor.
+    pub fn set_attractor(&mut self, attractor: Option<Attractor>) -> &mut Self {
+        self.attractor = attractor;
+        self
+    }
+
+    #[inline]
+    pub fn get_attractor(&self) -> Option<Attractor> {
+        self.attractor
+    }
+
+    /// Returns target to which body is driven, see [`Physics::grab`](crate::Physics::grab).
+    #[inline]
+    pub fn get_grab(&self) -> Option<KinematicTarget> {
+        self.grab
+    }
+
+    /// Enables or disables body. Disabled body keeps its handle and state, but is not
+    /// integrated and does not collide with anything. When body is enabled again it
+    /// starts from rest at its current position, so time spent disabled won't turn into
+    /// velocity.
+    pub fn set_enabled(&mut self, enabled: bool) -> &mut Self {
+        if enabled && !self.enabled {
+            self.last_position = self.position;
+            self.acceleration = Vec3::ZERO;
+        }
+        if !enabled {
+            self.contacts.clear();
+        }
+        self.enabled = enabled;
+        self
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns true if body touches something or is still considered touching because of
+    /// contact hysteresis, see `Physics::set_contact_hysteresis`. Surface friction is
+    /// applied to body in this state, air friction otherwise.
+    #[inline]
+    pub fn is_in_contact(&self) -> bool {
+        !self.contacts.is_empty() || self.hysteresis_active
+    }
+
+    /// Keeps body in contact state for given amount of steps after its contacts has ended,
+    /// unless body has moved farther than given distance from where it lost contact. Must
+    /// be called after contacts of body are gathered.
+    pub(in crate) fn update_contact_hysteresis(&mut self, steps: u32, distance: f32) {
+        self.hysteresis_active = false;
+        if !self.contacts.is_empty() {
+            self.contact_grace = steps;
+            self.last_contact_position = self.position;
+        } else if self.contact_grace > 0 && self.position.sqr_distance(&self.last_contact_position) <= distance * distance {
+            self.contact_grace -= 1;
+            self.hysteresis_active = true;
+        } else {
+            self.contact_grace = 0;
+        }
+    }
+
+    /// Merges contacts with same other party (body or static geometry) which lie within
+    /// given distance from each other and which normals differ by no more than given angle
+    /// (cosine of it is passed). Merged contact has averaged position and normal, and
+    /// largest impulse. Sizes of clusters are stored in given scratch buffer, so it can be
+    /// reused for every body.
+    pub(in crate) fn reduce_contacts(&mut self, distance: f32, min_normal_cos: f32, counts: &mut Vec<u32>) {
+        if self.contacts.len() < 2 {
+            return;
+        }
+
+        let sqr_distance = distance * distance;
+        counts.clear();
+        let mut reduced = 0;
+        for i in 0..self.contacts.len() {
+            let contact = self.contacts[i].clone();
+            let cluster = (0..reduced).find(|&j| {
+                let other = &self.contacts[j];
+                other.body == contact.body && other.static_geom == contact.static_geom &&
+                    other.position.scale(1.0 / counts[j] as f32).sqr_distance(&contact.position) <= sqr_distance &&
+                    Backend::normalize(other.normal).unwrap_or(other.normal).dot(&contact.normal) >= min_normal_cos
+            });
+            match cluster {
+                Some(j) => {
+                    // Keep running sums, they are turned into averages below.
+                    let merged = &mut self.contacts[j];
+                    merged.position += contact.position;
+                    merged.normal += contact.normal;
+                    merged.local_position_a += contact.local_position_a;
+                    merged.local_position_b += contact.local_position_b;
+                    merged.impulse = merged.impulse.max(contact.impulse);
+                    counts[j] += 1;
+                }
+                None => {
+                    self.contacts[reduced] = contact;
+                    counts.push(1);
+                    reduced += 1;
+                }
+            }
+        }
+        self.contacts.truncate(reduced);
+
+        for (contact, &count) in self.contacts.iter_mut().zip(counts.iter()) {
+            if count > 1 {
+                let k = 1.0 / count as f32;
+                contact.position = contact.position.scale(k);
+                contact.local_position_a = contact.local_position_a.scale(k);
+                contact.local_position_b = contact.local_position_b.scale(k);
+                contact.normal = Backend::normalize(contact.normal).unwrap_or(contact.normal);
+            }
+        }
+    }
+
     pub fn verlet(&mut self, sqr_delta_time: f32, air_friction: f32) {
+        self.verlet_in_substeps(sqr_delta_time, air_friction, 1);
+    }
+
+    /// Same as [`verlet`](Self::verlet), but body is integrated in at least given amount
+    /// of substeps.
+    pub(in crate) fn verlet_in_substeps(&mut self, sqr_delta_time: f32, air_friction: f32, min_substeps: u32) {
         let friction =
-            if !self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) && !self.contacts.is_empty() {
+            if !self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) && self.is_in_contact() {
                 self.friction
             } else {
                 Vec3::new(air_friction, air_friction, air_friction)
             };
 
-        let last_position = self.position;
+        self.step_start_position = self.position;
+        let substeps = self.substeps.max(min_substeps);
+        if substeps > 1 {
+            self.verlet_substeps(friction, sqr_delta_time, substeps);
+        } else {
+            let last_position = self.position;
 
-        // Verlet integration
-        self.position = Vec3 {
-            x: (2.0 - friction.x) * self.position.x - (1.0 - friction.x) * self.last_position.x + self.acceleration.x * sqr_delta_time,
-            y: (2.0 - friction.y) * self.position.y - (1.0 - friction.y) * self.last_position.y + self.acceleration.y * sqr_delta_time,
-            z: (2.0 - friction.z) * self.position.z - (1.0 - friction.z) * self.last_position.z + self.acceleration.z * sqr_delta_time,
-        };
+            // Verlet integration
+            self.position = Vec3 {
+                x: Backend::verlet(self.position.x, self.last_position.x, friction.x, self.acceleration.x, sqr_delta_time),
+                y: Backend::verlet(self.position.y, self.last_position.y, friction.y, self.acceleration.y, sqr_delta_time),
+                z: Backend::verlet(self.position.z, self.last_position.z, friction.z, self.acceleration.z, sqr_delta_time),
+            };
 
-        self.last_position = last_position;
+            self.last_position = last_position;
+        }
 
         self.acceleration = Vec3::ZERO;
 
-        let velocity = self.last_position - self.position;
+        self.clamp_velocity(self.speed_limit);
+    }
+
+    /// Integrates body in given amount of equal pieces of step. Velocity is converted to
+    /// units per substep and back, so after integration last position is extrapolated from
+    /// velocity at the end of step, as if body moved whole step with it.
+    fn verlet_substeps(&mut self, friction: Vec3, sqr_delta_time: f32, substep_count: u32) {
+        let substeps = substep_count as f32;
+        let sqr_sub_delta_time = sqr_delta_time / (substeps * substeps);
+        // Friction removes same part of velocity per whole step.
+        let sub_friction = |friction: f32| 1.0 - (1.0 - friction.clamp(0.0, 1.0)).powf(1.0 / substeps);
+        let friction = Vec3::new(sub_friction(friction.x), sub_friction(friction.y), sub_friction(friction.z));
+
+        let mut last_position = self.position - self.get_velocity().scale(1.0 / substeps);
+        for _ in 0..substep_count {
+            let position = self.position;
+            self.position = Vec3 {
+                x: Backend::verlet(self.position.x, last_position.x, friction.x, self.acceleration.x, sqr_sub_delta_time),
+                y: Backend::verlet(self.position.y, last_position.y, friction.y, self.acceleration.y, sqr_sub_delta_time),
+                z: Backend::verlet(self.position.z, last_position.z, friction.z, self.acceleration.z, sqr_sub_delta_time),
+            };
+            last_position = position;
+        }
+
+        self.last_position = self.position - (self.position - last_position).scale(substeps);
+    }
+
+    /// Makes friction pull velocity of body towards given velocity (in units per step) of
+    /// surface it stands on, instead of zero. Must be called right after [`verlet`](Self::verlet).
+    pub(in crate) fn apply_surface_velocity(&mut self, velocity: Vec3) {
+        if !self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) && self.is_in_contact() {
+            self.position += Vec3::new(
+                self.friction.x * velocity.x,
+                self.friction.y * velocity.y,
+                self.friction.z * velocity.z,
+            );
+        }
+    }
+
+    /// Projects position and velocity of body onto given plane.
+    pub(in crate) fn project_onto_plane(&mut self, plane: &Plane) {
+        self.position -= plane.normal.scale(plane.dot(&self.position));
+        self.last_position -= plane.normal.scale(plane.dot(&self.last_position));
+    }
+
+    /// Limits speed (in units per step) keeping direction of velocity. Non-finite
+    /// velocity is reset to zero.
+    pub(in crate) fn clamp_velocity(&mut self, max_speed: f32) {
+        let velocity = self.get_velocity();
         let sqr_speed = velocity.sqr_len();
-        if sqr_speed > self.speed_limit * self.speed_limit {
-            if let Some(direction) = velocity.normalized() {
-                self.last_position = self.position - direction.scale(self.speed_limit);
+        if !sqr_speed.is_finite() {
+            self.last_position = self.position;
+        } else if sqr_speed > max_speed * max_speed {
+            if let Some(direction) = Backend::normalize(velocity) {
+                self.last_position = self.position - direction.scale(max_speed);
             }
         }
     }
 
-    pub fn solve_triangle_collision(&mut self, triangle: &StaticTriangle, triangle_index: usize, static_geom: Handle<StaticGeometry>) {
-        let triangle_shape = ConvexShape::Triangle(TriangleShape {
-            vertices: triangle.points
+    /// Returns contact point, direction in which body must be pushed out of triangle and
+    /// penetration depth. Body is inflated by clearance, so depth is positive when body
+    /// is closer to triangle than clearance.
+    pub(in crate) fn triangle_penetration(&self, triangle: &StaticTriangle, features: Option<&TriangleFeatureNormals>,
+                                          scratch: &mut EpaScratch, settings: PenetrationSettings) -> Option<(Vec3, Vec3, f32)> {
+        if self.collision_flags.contains(CollisionFlags::AABB_ONLY) {
+            let mut triangle_aabb = AxisAlignedBoundingBox::default();
+            for point in triangle.points.iter() {
+                triangle_aabb.add_point(*point);
+            }
+            return Self::aabb_penetration(&self.get_aabb(), &triangle_aabb);
+        }
+
+        let self_transform = self.get_transform();
+        let triangle_transform = ShapeTransform::default();
+        let inflation = self.margin + settings.clearance;
+        let shape = Inflated { shape: &self.shape, margin: inflation };
+        let simplex = gjk_epa::gjk_is_intersects(&shape, &self_transform, triangle, &triangle_transform)?;
+        let penetration_info = gjk_epa::epa_get_penetration_info_with_scratch(
+            simplex, &shape, &self_transform, triangle, &triangle_transform, scratch)
+            .or_else(|| if settings.epa_fallback {
+                gjk_epa::sampled_penetration_info(&shape, &self_transform, triangle, &triangle_transform)
+            } else {
+                None
+            })?;
+        // Penetration vector vanishes when body exactly touches triangle, use normal of
+        // triangle facing the body then.
+        let normal = Backend::normalize(-penetration_info.penetration_vector).unwrap_or_else(|| {
+            if triangle.plane.dot(&self.position) >= 0.0 {
+                triangle.plane.normal
+            } else {
+                -triangle.plane.normal
+            }
         });
+        let position = penetration_info.contact_point + normal.scale(inflation);
+        match features {
+            Some(features) => self.feature_penetration(triangle, features, inflation, position, normal, penetration_info.penetration_vector.len()),
+            None => Some((position, normal, penetration_info.penetration_vector.len())),
+        }
+    }
 
-        if let Some(simplex) = gjk_epa::gjk_is_intersects(&self.shape, self.position, &triangle_shape, Vec3::ZERO) {
-            if let Some(penetration_info) = gjk_epa::epa_get_penetration_info(simplex, &self.shape, self.position, &triangle_shape, Vec3::ZERO) {
-                self.position -= penetration_info.penetration_vector;
-
-                self.contacts.push(Contact {
-                    static_geom,
-                    body: Handle::NONE,
-                    position: penetration_info.contact_point,
-                    normal: (-penetration_info.penetration_vector).normalized().unwrap_or(Vec3::ZERO),
-                    triangle_index: triangle_index as u32,
-                })
+    /// Classifies contact point as interior, edge or vertex of triangle by its barycentric
+    /// coordinates and measures penetration along normal of that feature: face normal
+    /// inside of triangle, averaged normal of adjacent faces on shared edges and vertices.
+    /// Penetration vector is kept on boundary features of mesh.
+    fn feature_penetration(&self, triangle: &StaticTriangle, features: &TriangleFeatureNormals, inflation: f32,
+                           position: Vec3, normal: Vec3, depth: f32) -> Option<(Vec3, Vec3, f32)> {
+        let weights = triangle.barycentric(position);
+        let weights = [weights.x, weights.y, weights.z];
+        let on_feature = |i: usize| weights[i] <= Self::FEATURE_TOLERANCE;
+        let (feature_normal, feature_point) = match (0..3).filter(|i| on_feature(*i)).count() {
+            0 => (Some(triangle.plane.normal), triangle.points[0]),
+            1 => {
+                // Edge which is opposite to vertex with zero weight.
+                let edge = ((0..3).find(|i| on_feature(*i)).unwrap() + 1) % 3;
+                (features.edges[edge], triangle.points[edge])
+            }
+            _ => {
+                let vertex = (0..3).max_by(|a, b| weights[*a].total_cmp(&weights[*b])).unwrap();
+                (features.vertices[vertex], triangle.points[vertex])
             }
+        };
+
+        // Penetration vector near edge is unreliable, so side is decided by center of body.
+        let side = if triangle.plane.dot(&self.position) >= 0.0 { 1.0 } else { -1.0 };
+        let feature_normal = match feature_normal {
+            Some(feature_normal) if feature_normal.dot(&triangle.plane.normal) * side >= 0.0 => feature_normal,
+            Some(feature_normal) => -feature_normal,
+            None => return Some((position, normal, depth)),
+        };
+        let deepest = self.shape.support_point(&self.get_transform(), -feature_normal);
+        let depth = feature_normal.dot(&(feature_point - deepest)) + inflation;
+        if depth > 0.0 {
+            Some((position, feature_normal, depth))
+        } else {
+            // Body is separated along feature normal, contact with neighbour face is
+            // the one that matters.
+            None
         }
     }
 
-    pub fn solve_rigid_body_collision(&mut self, self_handle: Handle<RigidBody>, other: &mut Self, other_handle: Handle<RigidBody>) {
-        if let Some(simplex) = gjk_epa::gjk_is_intersects(&self.shape, self.position, &other.shape, other.position) {
-            if let Some(penetration_info) = gjk_epa::epa_get_penetration_info(simplex, &self.shape, self.position, &other.shape, other.position) {
-                let half_push = penetration_info.penetration_vector.scale(0.5);
-                let response_disabled =
-                    self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) ||
-                    other.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE);
+    pub fn solve_triangle_collision(&mut self, self_handle: Handle<RigidBody>, triangle: &StaticTriangle,
+                                    features: Option<&TriangleFeatureNormals>, triangle_index: usize, static_geom: Handle<StaticGeometry>,
+                                    context: &mut SolverContext) {
+        let settings = context.speculative_settings();
+        if let Some((position, normal, depth)) = self.triangle_penetration(triangle, features, context.scratch, settings) {
+            let mut info = ContactInfo {
+                body: self_handle,
+                other_body: Handle::NONE,
+                static_geom,
+                triangle_index: triangle_index as u32,
+                position,
+                normal,
+                // Shallower contacts are speculative, they are reported but not pushed.
+                depth: (depth - context.contact_margin).max(0.0),
+                relative_velocity: self.get_velocity(),
+            };
 
-                if !response_disabled {
-                    self.position -= half_push;
-                }
-                self.contacts.push(Contact {
-                    body: other_handle,
-                    position: penetration_info.contact_point,
-                    // TODO: WRONG NORMAL
-                    normal: (-penetration_info.penetration_vector).normalized().unwrap_or(Vec3::UP),
-                    triangle_index: 0,
-                    static_geom: Default::default()
-                });
-                if !response_disabled {
-                    other.position += half_push;
+            if !context.filter(&mut info) {
+                return;
+            }
+            let response_enabled = !self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) &&
+                context.allows_response(&info);
+            if response_enabled {
+                context.max_depth = context.max_depth.max(info.depth);
+            }
+
+            // Local point is computed before push, so it lies on surface of body.
+            let local_position_a = self.get_transform().inverse_transform_point(info.position);
+
+            if response_enabled {
+                self.position += info.normal.scale(info.depth * context.relaxation * self.response_coefficient);
+            }
+
+            self.add_contact(Contact {
+                static_geom,
+                body: Handle::NONE,
+                position: info.position,
+                normal: info.normal,
+                triangle_index: info.triangle_index,
+                local_position_a,
+                local_position_b: info.position,
+                impulse: (-info.relative_velocity.dot(&info.normal)).max(0.0),
+            }, context.iteration)
+        }
+    }
+
+    /// Tests body against half-space, returns contact point, direction in which body must
+    /// be pushed out and penetration depth. Body is inflated by its margin and given clearance.
+    fn half_space_penetration(&self, half_space: &HalfSpaceShape, half_space_transform: &ShapeTransform,
+                              clearance: f32) -> Option<(Vec3, Vec3, f32)> {
+        let normal = half_space_transform.basis.transform_vector(half_space.get_normal());
+        let deepest = self.shape.support_point(&self.get_transform(), -normal);
+        let depth = normal.dot(&(half_space_transform.position - deepest)) + self.margin + clearance;
+        if depth > 0.0 {
+            Some((deepest, normal, depth))
+        } else {
+            None
+        }
+    }
+
+    fn is_aabb_only(&self, other: &Self) -> bool {
+        self.collision_flags.contains(CollisionFlags::AABB_ONLY) || other.collision_flags.contains(CollisionFlags::AABB_ONLY)
+    }
+
+    /// Penetration of bounding boxes, used for bodies with [`CollisionFlags::AABB_ONLY`].
+    /// Contact point is center of intersection of boxes, normal is world axis along which
+    /// boxes overlap least (pointing towards `self`), depth is overlap along it.
+    pub(in crate) fn aabb_penetration(self_aabb: &AxisAlignedBoundingBox, other_aabb: &AxisAlignedBoundingBox) -> Option<(Vec3, Vec3, f32)> {
+        if !self_aabb.intersect_aabb(other_aabb) {
+            return None;
+        }
+
+        let min = Vec3::new(self_aabb.min.x.max(other_aabb.min.x), self_aabb.min.y.max(other_aabb.min.y), self_aabb.min.z.max(other_aabb.min.z));
+        let max = Vec3::new(self_aabb.max.x.min(other_aabb.max.x), self_aabb.max.y.min(other_aabb.max.y), self_aabb.max.z.min(other_aabb.max.z));
+        let overlap = max - min;
+        let offset = (self_aabb.min + self_aabb.max) - (other_aabb.min + other_aabb.max);
+        let sign = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+        let (normal, depth) = if overlap.x <= overlap.y && overlap.x <= overlap.z {
+            (Vec3::new(sign(offset.x), 0.0, 0.0), overlap.x)
+        } else if overlap.y <= overlap.z {
+            (Vec3::new(0.0, sign(offset.y), 0.0), overlap.y)
+        } else {
+            (Vec3::new(0.0, 0.0, sign(offset.z)), overlap.z)
+        };
+        Some(((min + max).scale(0.5), normal, depth))
+    }
+
+    /// Checks if bodies intersect, taking margins into account. Only boolean test is done,
+    /// penetration depth is not computed. Collision groups are not checked.
+    pub fn intersects(&self, other: &Self) -> bool {
+        match (&self.shape, &other.shape) {
+            (ConvexShape::HalfSpace(_), ConvexShape::HalfSpace(_)) => false,
+            (ConvexShape::HalfSpace(half_space), _) => other.half_space_penetration(half_space, &self.get_transform(), 0.0).is_some(),
+            (_, ConvexShape::HalfSpace(half_space)) => self.half_space_penetration(half_space, &other.get_transform(), 0.0).is_some(),
+            _ if self.is_aabb_only(other) => self.get_aabb().intersect_aabb(&other.get_aabb()),
+            _ => {
+                let self_shape = Inflated { shape: &self.shape, margin: self.margin };
+                let other_shape = Inflated { shape: &other.shape, margin: other.margin };
+                gjk_epa::gjk_is_intersects(&self_shape, &self.get_transform(), &other_shape, &other.get_transform()).is_some()
+            }
+        }
+    }
+
+    /// Returns contact point, direction in which `self` must be pushed out of `other` and
+    /// penetration depth. Only `self` is inflated by clearance, so clearance is kept
+    /// between bodies only once.
+    pub(in crate) fn body_penetration(&self, other: &Self, scratch: &mut EpaScratch,
+                                      settings: PenetrationSettings) -> Option<(Vec3, Vec3, f32)> {
+        let self_transform = self.get_transform();
+        let other_transform = other.get_transform();
+        match (&self.shape, &other.shape) {
+            (ConvexShape::HalfSpace(_), ConvexShape::HalfSpace(_)) => None,
+            (ConvexShape::HalfSpace(half_space), _) => other.half_space_penetration(half_space, &self_transform, settings.clearance)
+                .map(|(position, normal, depth)| (position, -normal, depth)),
+            (_, ConvexShape::HalfSpace(half_space)) => self.half_space_penetration(half_space, &other_transform, settings.clearance),
+            _ if self.is_aabb_only(other) => Self::aabb_penetration(&self.get_aabb(), &other.get_aabb()),
+            _ => {
+                let inflation = self.margin + settings.clearance;
+                let self_shape = Inflated { shape: &self.shape, margin: inflation };
+                let other_shape = Inflated { shape: &other.shape, margin: other.margin };
+                let simplex = gjk_epa::gjk_is_intersects(&self_shape, &self_transform, &other_shape, &other_transform)?;
+                let penetration_info = gjk_epa::epa_get_penetration_info_with_scratch(
+                    simplex, &self_shape, &self_transform, &other_shape, &other_transform, scratch)
+                    .or_else(|| if settings.epa_fallback {
+                        gjk_epa::sampled_penetration_info(&self_shape, &self_transform, &other_shape, &other_transform)
+                    } else {
+                        None
+                    })?;
+                // Penetration vector vanishes when bodies exactly touch, use direction between
+                // centers then.
+                let normal = Backend::normalize(-penetration_info.penetration_vector)
+                    .or_else(|| Backend::normalize(self.position - other.position))
+                    .unwrap_or(settings.up);
+                Some((penetration_info.contact_point + normal.scale(inflation), normal, penetration_info.penetration_vector.len()))
+            }
+        }
+    }
+
+    pub fn solve_rigid_body_collision(&mut self, self_handle: Handle<RigidBody>, other: &mut Self,
+                                      other_handle: Handle<RigidBody>, context: &mut SolverContext) {
+        let settings = context.speculative_settings();
+        if let Some((position, normal, depth)) = self.body_penetration(other, context.scratch, settings) {
+            let mut info = ContactInfo {
+                body: self_handle,
+                other_body: other_handle,
+                static_geom: Handle::NONE,
+                triangle_index: 0,
+                position,
+                normal,
+                // Shallower contacts are speculative, they are reported but not pushed.
+                depth: (depth - context.contact_margin).max(0.0),
+                relative_velocity: self.get_velocity() - other.get_velocity(),
+            };
+
+            if !context.filter(&mut info) {
+                return;
+            }
+            let response_disabled =
+                self.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) ||
+                other.collision_flags.contains(CollisionFlags::DISABLE_COLLISION_RESPONSE) ||
+                !context.allows_response(&info);
+            // Penetration which won't be corrected must not keep solver passes going.
+            if !response_disabled {
+                context.max_depth = context.max_depth.max(info.depth);
+            }
+
+            // Half-spaces are immovable, other party of contact takes whole push.
+            let self_share = if self.shape.is_half_space() {
+                0.0
+            } else if other.shape.is_half_space() {
+                1.0
+            } else {
+                0.5
+            };
+
+            // Local points are computed before push, so they lie on surfaces of bodies.
+            let self_local_position = self.get_transform().inverse_transform_point(info.position);
+            let other_local_position = other.get_transform().inverse_transform_point(info.position);
+            let impulse = (-info.relative_velocity.dot(&info.normal)).max(0.0);
+
+            if !response_disabled {
+                self.position += info.normal.scale(info.depth * self_share * context.relaxation * self.response_coefficient);
+            }
+            self.add_contact(Contact {
+                body: other_handle,
+                position: info.position,
+                normal: info.normal,
+                triangle_index: 0,
+                static_geom: Default::default(),
+                local_position_a: self_local_position,
+                local_position_b: other_local_position,
+                impulse,
+            }, context.iteration);
+            if !response_disabled {
+                other.position -= info.normal.scale(info.depth * (1.0 - self_share) * context.relaxation * other.response_coefficient);
+            }
+            other.add_contact(Contact {
+                body: self_handle,
+                position: info.position,
+                normal: -info.normal,
+                triangle_index: 0,
+                static_geom: Default::default(),
+                local_position_a: other_local_position,
+                local_position_b: self_local_position,
+                impulse,
+            }, context.iteration)
+        }
+    }
+
+    /// Adds contact generated on given solver iteration. Contacts of first iteration are
+    /// always added, later iterations only add contacts with new parties, so repeated
+    /// iterations do not duplicate contacts.
+    fn add_contact(&mut self, contact: Contact, iteration: usize) {
+        if iteration > 0 && self.contacts.iter().any(|existing| existing.body == contact.body &&
+            existing.static_geom == contact.static_geom && existing.triangle_index == contact.triangle_index) {
+            return;
+        }
+        self.contacts.push(contact);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::math::vec3::Vec3;
+    use crate::{
+        Physics,
+        rigid_body::RigidBody,
+        contact::Contact,
+        convex_shape::{ConvexShape, BoxShape, TriangleShape},
+        gravity::DEFAULT_GRAVITY,
+        gjk_epa::test::count_allocations,
+        test::{DT, sphere, floor},
+    };
+
+    #[test]
+    fn larger_margin_leaves_gap_above_floor() {
+        let rest_height = |margin: f32| {
+            let mut physics = Physics::new();
+            physics.add_body(floor());
+            let mut plate = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(1.0, 0.02, 1.0))));
+            plate.set_position(Vec3::new(0.0, 1.0, 0.0)).set_margin(margin);
+            let plate = physics.add_body(plate);
+            for _ in 0..240 {
+                physics.step(DT);
+            }
+            let plate = physics.borrow_body(plate);
+            assert!(plate.get_velocity().len() < 1.0e-3);
+            plate.get_position().y - 0.02
+        };
+
+        let thin = rest_height(0.0);
+        let thick = rest_height(0.1);
+        // Flat box rests on floor, it does not sink through.
+        assert!(thin > -0.01 && thin < 0.02);
+        assert!((thick - thin - 0.1).abs() < 0.02);
+    }
+
+    #[test]
+    fn disabled_body_stays_and_resumes_falling_without_jump() {
+        let mut physics = Physics::new();
+        let ball = physics.add_body(sphere(Vec3::new(0.0, 10.0, 0.0), 0.5));
+        for _ in 0..10 {
+            physics.step(DT);
+        }
+
+        physics.borrow_body_mut(ball).set_enabled(false);
+        let position = physics.borrow_body(ball).get_position();
+        for _ in 0..30 {
+            physics.step(DT);
+        }
+        assert_eq!(physics.borrow_body(ball).get_position(), position);
+
+        physics.borrow_body_mut(ball).set_enabled(true);
+        physics.step(DT);
+        // Falls from rest, as if it was just released.
+        let body = physics.borrow_body(ball);
+        assert!(body.get_velocity().y < 0.0);
+        assert!(body.get_velocity().len() < 9.81 * DT * DT * 1.5);
+        assert!(position.y - body.get_position().y < 9.81 * DT * DT * 1.5);
+    }
+
+    #[test]
+    fn stronger_fall_gravity_shortens_descent() {
+        // Returns amount of steps of rise to apex and of fall back to launch height.
+        let flight = |fall_gravity_scale: f32| {
+            let mut physics = Physics::new();
+            let mut body = sphere(Vec3::ZERO, 0.5);
+            body.set_velocity(Vec3::new(0.0, 0.2, 0.0)).set_fall_gravity_scale(fall_gravity_scale);
+            let body = physics.add_body(body);
+            let (mut rise, mut fall) = (0i32, 0i32);
+            for _ in 0..1000 {
+                physics.step(DT);
+                let body = physics.borrow_body(body);
+                if body.get_velocity().y > 0.0 {
+                    rise += 1;
+                } else if body.get_position().y > 0.0 {
+                    fall += 1;
+                } else {
+                    break;
                 }
-                other.contacts.push(Contact {
-                    body: self_handle,
-                    position: penetration_info.contact_point,
-                    // TODO: WRONG NORMAL
-                    normal: (penetration_info.penetration_vector).normalized().unwrap_or(Vec3::UP),
-                    triangle_index: 0,
-                    static_geom: Default::default()
-                })
             }
+            (rise, fall)
+        };
+
+        let (rise, fall) = flight(1.0);
+        // Air friction makes fall a bit longer than rise.
+        assert!((rise - fall).abs() <= rise / 10);
+        let (fast_rise, fast_fall) = flight(2.0);
+        assert_eq!(fast_rise, rise);
+        // Time of fall from same height is shorter by square root of scale.
+        assert!((fast_fall as f32 - fall as f32 / 2.0f32.sqrt()).abs() <= 2.0);
+    }
+
+    #[test]
+    fn substeps_follow_analytic_arc_closer() {
+        // Returns largest deviation from analytic trajectory of body dropped from rest.
+        let deviation = |substeps: u32| {
+            let mut physics = Physics::new();
+            let mut body = sphere(Vec3::ZERO, 0.5);
+            body.set_substeps(substeps);
+            let body = physics.add_body(body);
+            let mut deviation = 0.0f32;
+            for i in 1..=10 {
+                physics.step(DT);
+                let time = i as f32 * DT;
+                let expected = -DEFAULT_GRAVITY * time * time / 2.0;
+                deviation = deviation.max((physics.borrow_body(body).get_position().y - expected).abs());
+            }
+            deviation
+        };
+
+        assert!(deviation(16) < deviation(1) * 0.5);
+    }
+
+    #[test]
+    fn substepped_body_keeps_sliding_on_floor() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let mut plain = sphere(Vec3::new(0.0, 0.5, 0.0), 0.5);
+        plain.set_velocity(Vec3::new(0.2, 0.0, 0.0));
+        let mut substepped = plain.clone();
+        substepped.set_position(Vec3::new(0.0, 0.5, 5.0)).set_velocity(Vec3::new(0.2, 0.0, 0.0)).set_substeps(8);
+        let (plain, substepped) = (physics.add_body(plain), physics.add_body(substepped));
+
+        for _ in 0..30 {
+            physics.step(DT);
+        }
+
+        // Floor contact stops only motion into floor.
+        let plain = physics.borrow_body(plain).get_position();
+        let substepped = physics.borrow_body(substepped).get_position();
+        assert!(plain.x > 0.5);
+        assert!((substepped.x - plain.x).abs() < 0.1 * plain.x);
+        assert!((substepped.y - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn flat_triangle_bodies_collide_edge_on() {
+        let mut physics = Physics::new();
+        // Both triangles lie in XZ plane, vertical edges at local x = 0.5 and x = -0.5 face
+        // each other.
+        let mut triangle = |x: f32, direction: f32| {
+            let vertices = [Vec3::new(0.5 * direction, 0.0, -0.5), Vec3::new(0.5 * direction, 0.0, 0.5), Vec3::new(-0.5 * direction, 0.0, 0.0)];
+            let mut body = RigidBody::new(ConvexShape::Triangle(TriangleShape::new(vertices)));
+            body.set_position(Vec3::new(x, 0.0, 0.0))
+                .set_gravity(Vec3::ZERO)
+                .set_velocity(Vec3::new(0.02 * direction, 0.0, 0.0));
+            physics.add_body(body)
+        };
+        let a = triangle(-1.0, 1.0);
+        let b = triangle(1.0, -1.0);
+
+        let mut touched = false;
+        for _ in 0..240 {
+            physics.step(DT);
+            let (a, b) = (physics.borrow_body(a), physics.borrow_body(b));
+            touched |= !a.get_contacts().is_empty() && !b.get_contacts().is_empty();
+            // Edges never pass each other, margins of both keep them apart.
+            let gap = (b.get_position().x - 0.5) - (a.get_position().x + 0.5);
+            assert!(gap > a.get_margin() + b.get_margin() - 0.01);
+            assert!(a.get_position().z.abs() < 1.0e-3);
         }
+        assert!(touched);
+        assert!(physics.borrow_body(a).get_velocity().x < 0.01);
+    }
+
+    #[test]
+    fn clustered_contacts_are_reduced_to_one_per_cluster() {
+        let mut physics = Physics::new();
+        let other = physics.add_body(sphere(Vec3::ZERO, 0.5));
+        let contact = |x: f32, normal: Vec3, body| Contact {
+            body,
+            position: Vec3::new(x, 0.0, 0.0),
+            normal,
+            ..Default::default()
+        };
+
+        let mut body = sphere(Vec3::ZERO, 0.5);
+        body.contacts = vec![
+            // Three close contacts with floor.
+            contact(0.0, Vec3::UP, Default::default()),
+            contact(0.01, Vec3::UP, Default::default()),
+            contact(0.02, Vec3::UP, Default::default()),
+            // Two close contacts far from first cluster.
+            contact(1.0, Vec3::UP, Default::default()),
+            contact(1.01, Vec3::UP, Default::default()),
+            // Close to first cluster, but with different normal or other party.
+            contact(0.0, Vec3::RIGHT, Default::default()),
+            contact(0.0, Vec3::UP, other),
+        ];
+        let mut counts = Vec::new();
+        body.reduce_contacts(0.05, 0.9, &mut counts);
+
+        let contacts = body.get_contacts();
+        assert_eq!(contacts.len(), 4);
+        assert!((contacts[0].position.x - 0.01).abs() < 1.0e-6);
+        assert!((contacts[1].position.x - 1.005).abs() < 1.0e-6);
+        assert_eq!(contacts[2].normal, Vec3::RIGHT);
+        assert_eq!(contacts[3].body, other);
+
+        // Scratch buffer has enough space now, so it is reused without allocations.
+        body.contacts.push(contact(0.0, Vec3::UP, Default::default()));
+        assert_eq!(count_allocations(|| body.reduce_contacts(0.05, 0.9, &mut counts)), 0);
+        assert_eq!(body.get_contacts().len(), 4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn swept_aabb_encloses_start_and_end_boxes() {
+        let start_position = Vec3::new(1.0, 2.0, 3.0);
+        let motion = Vec3::new(0.3, -0.2, 0.1);
+        let mut body = RigidBody::new(ConvexShape::Box(BoxShape::new(Vec3::new(0.5, 0.25, 1.0))));
+        body.set_position(start_position);
+        let start = body.get_aabb();
+        // Body has moved from start position by motion during last step.
+        body.launch(start_position + motion, motion);
+        let end = body.get_aabb();
+
+        let swept = body.get_swept_aabb();
+        for aabb in [start, end].iter() {
+            assert!(swept.min.x <= aabb.min.x && swept.min.y <= aabb.min.y && swept.min.z <= aabb.min.z);
+            assert!(swept.max.x >= aabb.max.x && swept.max.y >= aabb.max.y && swept.max.z >= aabb.max.z);
+        }
+        // Box is tight - it is union of both boxes.
+        assert!((swept.min - Vec3::new(start.min.x, end.min.y, start.min.z)).len() < 1.0e-5);
+        assert!((swept.max - Vec3::new(end.max.x, start.max.y, end.max.z)).len() < 1.0e-5);
+    }
+
+    #[test]
+    fn grown_sphere_has_scaled_aabb_and_resting_height() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let body = physics.add_body(sphere(Vec3::new(0.0, 0.5, 0.0), 0.5));
+        let settle = |physics: &mut Physics| {
+            for _ in 0..120 {
+                physics.step(DT);
+            }
+            physics.borrow_body(body).get_position().y
+        };
+        let height = settle(&mut physics);
+        let size = |physics: &Physics| {
+            let aabb = physics.borrow_body(body).get_aabb();
+            aabb.max - aabb.min
+        };
+        let small_size = size(&physics);
+
+        // Grown sphere is dropped from above, so it is not launched out of floor.
+        physics.borrow_body_mut(body).set_shape_scale(2.0).set_position(Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(physics.borrow_body(body).get_shape().as_sphere().get_radius(), 1.0);
+        assert!((size(&physics) - small_size.scale(2.0)).len() < 1.0e-5);
+        let grown_height = settle(&mut physics);
+        assert!((grown_height - 2.0 * height).abs() < 1.0e-2);
+    }
+
+    #[test]
+    fn launched_body_has_requested_velocity_before_step() {
+        let mut physics = Physics::new();
+        physics.add_body(floor());
+        let body = physics.add_body(sphere(Vec3::new(0.0, 0.5, 0.0), 0.5));
+        physics.step(DT);
+        assert!(!physics.borrow_body(body).get_contacts().is_empty());
+
+        let velocity = Vec3::new(0.25, 0.5, -0.125);
+        physics.borrow_body_mut(body).launch(Vec3::new(1.0, 2.0, 3.0), velocity);
+        let launched = physics.borrow_body(body);
+        assert_eq!(launched.get_position(), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(launched.get_velocity(), velocity);
+        assert!(launched.get_contacts().is_empty());
+    }
+
+    #[test]
+    fn typed_user_data_is_attached_and_retrieved() {
+        #[derive(Debug, PartialEq)]
+        struct Entity {
+            id: u32,
+            name: String,
+        }
+
+        let mut physics = Physics::new();
+        let mut body = sphere(Vec3::ZERO, 0.5);
+        body.set_user_data(Entity { id: 7, name: "crate".to_owned() });
+        let body = physics.add_body(body);
+        physics.step(DT);
+
+        physics.borrow_body_mut(body).user_data_mut::<Entity>().unwrap().id = 8;
+        let entity = physics.borrow_body(body).user_data_ref::<Entity>();
+        assert_eq!(entity, Some(&Entity { id: 8, name: "crate".to_owned() }));
+        assert!(physics.borrow_body(body).user_data_ref::<u32>().is_none());
+        // Copies of body do not get data.
+        assert!(physics.borrow_body(body).clone().user_data_ref::<Entity>().is_none());
+
+        let taken = physics.borrow_body_mut(body).take_user_data().unwrap();
+        assert_eq!(taken.downcast_ref::<Entity>().map(|entity| entity.id), Some(8));
+        assert!(physics.borrow_body(body).user_data_ref::<Entity>().is_none());
+    }
+}
@@ -0,0 +1,354 @@
+//! Soft bodies made of Verlet particles linked by distance constraints.
+
+use rg3d_core::{
+    math::vec3::Vec3,
+    visitor::{Visit, VisitResult, Visitor},
+};
+use crate::{
+    convex_shape::{ConvexShape, SphereShape, ShapeTransform},
+    gjk_epa::{self, EpaScratch},
+    float_ops::{Backend, FloatOps},
+    static_geometry::StaticGeometry,
+    gravity::DEFAULT_GRAVITY,
+};
+
+/// Distance constraint which keeps two particles of soft body at rest length.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SoftBodyLink {
+    pub a: usize,
+    pub b: usize,
+    pub rest_length: f32,
+}
+
+impl Visit for SoftBodyLink {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        let mut a = self.a as u32;
+        a.visit("A", visitor)?;
+        let mut b = self.b as u32;
+        b.visit("B", visitor)?;
+        if visitor.is_reading() {
+            self.a = a as usize;
+            self.b = b as usize;
+        }
+        self.rest_length.visit("RestLength", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SoftBody {
+    pub(in crate) positions: Vec<Vec3>,
+    pub(in crate) last_positions: Vec<Vec3>,
+    pub(in crate) pinned: Vec<bool>,
+    pub(in crate) links: Vec<SoftBodyLink>,
+    pub(in crate) gravity: Vec3,
+    pub(in crate) particle_radius: f32,
+    pub(in crate) stiffness: f32,
+    pub(in crate) iterations: u32,
+}
+
+impl Default for SoftBody {
+    fn default() -> Self {
+        Self {
+            positions: Vec::new(),
+            last_positions: Vec::new(),
+            pinned: Vec::new(),
+            links: Vec::new(),
+            gravity: Vec3::new(0.0, -DEFAULT_GRAVITY, 0.0),
+            particle_radius: 0.05,
+            stiffness: 1.0,
+            iterations: 8,
+        }
+    }
+}
+
+impl Visit for SoftBody {
+    fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
+        visitor.enter_region(name)?;
+
+        self.positions.visit("Positions", visitor)?;
+        self.last_positions.visit("LastPositions", visitor)?;
+        self.pinned.visit("Pinned", visitor)?;
+        self.links.visit("Links", visitor)?;
+        self.gravity.visit("Gravity", visitor)?;
+        self.particle_radius.visit("ParticleRadius", visitor)?;
+        self.stiffness.visit("Stiffness", visitor)?;
+        self.iterations.visit("Iterations", visitor)?;
+
+        visitor.leave_region()
+    }
+}
+
+impl SoftBody {
+    /// Creates soft body from given particles without any links.
+    pub fn new(positions: Vec<Vec3>) -> Self {
+        Self {
+            last_positions: positions.clone(),
+            pinned: vec![false; positions.len()],
+            positions,
+            ..Default::default()
+        }
+    }
+
+    /// Creates rectangular cloth with `columns * rows` particles. `width` and `height`
+    /// are edge vectors of cloth, first row starts at `origin` and goes along `width`,
+    /// next rows are shifted along `height`. Particle at column `i` and row `j` has index
+    /// `j * columns + i`. Particles are linked by structural (adjacent), shear (diagonal)
+    /// and bend (every second) links.
+    pub fn cloth(origin: Vec3, width: Vec3, height: Vec3, columns: usize, rows: usize) -> Self {
+        let columns = columns.max(2);
+        let rows = rows.max(2);
+
+        let mut positions = Vec::with_capacity(columns * rows);
+        for j in 0..rows {
+            for i in 0..columns {
+                let u = i as f32 / (columns - 1) as f32;
+                let v = j as f32 / (rows - 1) as f32;
+                positions.push(origin + width.scale(u) + height.scale(v));
+            }
+        }
+
+        let mut cloth = Self::new(positions);
+        let index = |i: usize, j: usize| j * columns + i;
+        for j in 0..rows {
+            for i in 0..columns {
+                // Structural
+                if i + 1 < columns {
+                    cloth.link(index(i, j), index(i + 1, j));
+                }
+                if j + 1 < rows {
+                    cloth.link(index(i, j), index(i, j + 1));
+                }
+                // Shear
+                if i + 1 < columns && j + 1 < rows {
+                    cloth.link(index(i, j), index(i + 1, j + 1));
+                    cloth.link(index(i + 1, j), index(i, j + 1));
+                }
+                // Bend
+                if i + 2 < columns {
+                    cloth.link(index(i, j), index(i + 2, j));
+                }
+                if j + 2 < rows {
+                    cloth.link(index(i, j), index(i, j + 2));
+                }
+            }
+        }
+        cloth
+    }
+
+    /// Links two particles with distance constraint, current distance between them
+    /// becomes rest length.
+    pub fn link(&mut self, a: usize, b: usize) -> &mut Self {
+        self.links.push(SoftBodyLink {
+            a,
+            b,
+            rest_length: self.positions[a].distance(&self.positions[b]),
+        });
+        self
+    }
+
+    /// Anchors particle at its current position. Pinned particle is never moved by solver.
+    pub fn pin(&mut self, particle_index: usize) -> &mut Self {
+        self.pinned[particle_index] = true;
+        self.last_positions[particle_index] = self.positions[particle_index];
+        self
+    }
+
+    pub fn unpin(&mut self, particle_index: usize) -> &mut Self {
+        self.pinned[particle_index] = false;
+        self
+    }
+
+    #[inline]
+    pub fn is_pinned(&self, particle_index: usize) -> bool {
+        self.pinned[particle_index]
+    }
+
+    /// Moves particle to given position without giving it any velocity. Can be used to
+    /// move pinned particles.
+    pub fn set_particle_position(&mut self, particle_index: usize, position: Vec3) -> &mut Self {
+        self.positions[particle_index] = position;
+        self.last_positions[particle_index] = position;
+        self
+    }
+
+    /// Moves every particle by given offset without changing velocities.
+    pub fn translate(&mut self, offset: Vec3) -> &mut Self {
+        for (position, last_position) in self.positions.iter_mut().zip(self.last_positions.iter_mut()) {
+            *position += offset;
+            *last_position += offset;
+        }
+        self
+    }
+
+    /// Returns positions of particles, can be used for rendering.
+    #[inline]
+    pub fn get_positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    #[inline]
+    pub fn get_links(&self) -> &[SoftBodyLink] {
+        &self.links
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vec3) -> &mut Self {
+        self.gravity = gravity;
+        self
+    }
+
+    #[inline]
+    pub fn get_gravity(&self) -> Vec3 {
+        self.gravity
+    }
+
+    /// Sets radius of each particle used for collision with static geometry.
+    pub fn set_particle_radius(&mut self, radius: f32) -> &mut Self {
+        self.particle_radius = radius.max(0.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_particle_radius(&self) -> f32 {
+        self.particle_radius
+    }
+
+    /// Sets stiffness of links in `[0; 1]` range. Stiffness is fraction of length error
+    /// which is corrected on each iteration.
+    pub fn set_stiffness(&mut self, stiffness: f32) -> &mut Self {
+        self.stiffness = stiffness.clamp(0.0, 1.0);
+        self
+    }
+
+    #[inline]
+    pub fn get_stiffness(&self) -> f32 {
+        self.stiffness
+    }
+
+    /// Sets amount of constraint solver iterations per step. More iterations make
+    /// cloth less stretchy.
+    pub fn set_iterations(&mut self, iterations: u32) -> &mut Self {
+        self.iterations = iterations;
+        self
+    }
+
+    #[inline]
+    pub fn get_iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    pub(in crate) fn verlet(&mut self, sqr_delta_time: f32, air_friction: f32) {
+        let acceleration = self.gravity;
+        for ((position, last_position), pinned) in self.positions.iter_mut()
+            .zip(self.last_positions.iter_mut())
+            .zip(self.pinned.iter()) {
+            if *pinned {
+                continue;
+            }
+
+            let current = *position;
+            *position = Vec3 {
+                x: Backend::verlet(position.x, last_position.x, air_friction, acceleration.x, sqr_delta_time),
+                y: Backend::verlet(position.y, last_position.y, air_friction, acceleration.y, sqr_delta_time),
+                z: Backend::verlet(position.z, last_position.z, air_friction, acceleration.z, sqr_delta_time),
+            };
+            *last_position = current;
+        }
+    }
+
+    pub(in crate) fn solve_links(&mut self) {
+        for _ in 0..self.iterations {
+            for link in self.links.iter() {
+                let pinned_a = self.pinned[link.a];
+                let pinned_b = self.pinned[link.b];
+                if pinned_a && pinned_b {
+                    continue;
+                }
+
+                let delta = self.positions[link.b] - self.positions[link.a];
+                let length = Backend::sqrt(delta.sqr_len());
+                if length < f32::EPSILON {
+                    continue;
+                }
+
+                let correction = delta.scale(self.stiffness * (length - link.rest_length) / length);
+                if pinned_a {
+                    self.positions[link.b] -= correction;
+                } else if pinned_b {
+                    self.positions[link.a] += correction;
+                } else {
+                    let half = correction.scale(0.5);
+                    self.positions[link.a] += half;
+                    self.positions[link.b] -= half;
+                }
+            }
+        }
+    }
+
+    pub(in crate) fn solve_static_collision(&mut self, static_geometry: &StaticGeometry,
+                                            query_buffer: &mut Vec<u32>, scratch: &mut EpaScratch) {
+        let particle_shape = ConvexShape::Sphere(SphereShape::new(self.particle_radius));
+        let triangle_transform = ShapeTransform::default();
+
+        for (position, pinned) in self.positions.iter_mut().zip(self.pinned.iter()) {
+            if *pinned {
+                continue;
+            }
+
+            static_geometry.octree.sphere_query(*position, self.particle_radius, query_buffer);
+            for triangle in query_buffer.iter().map(|i| &static_geometry.triangles[*i as usize]) {
+                let transform = ShapeTransform::from_position(*position);
+                if let Some(simplex) = gjk_epa::gjk_is_intersects(&particle_shape, &transform, triangle, &triangle_transform) {
+                    if let Some(info) = gjk_epa::epa_get_penetration_info_with_scratch(
+                        simplex, &particle_shape, &transform, triangle, &triangle_transform, scratch) {
+                        *position -= info.penetration_vector;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::math::vec3::Vec3;
+    use crate::{
+        Physics,
+        soft_body::SoftBody,
+        test::DT,
+    };
+
+    #[test]
+    fn pinned_cloth_hangs_keeping_link_lengths() {
+        let (columns, rows) = (5, 5);
+        let mut cloth = SoftBody::cloth(Vec3::new(0.0, 5.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), columns, rows);
+        for i in 0..columns {
+            cloth.pin(i);
+        }
+        let mut physics = Physics::new();
+        let handle = physics.add_soft_body(cloth);
+
+        for _ in 0..600 {
+            physics.step(DT);
+        }
+
+        let cloth = physics.borrow_soft_body(handle);
+        let positions = cloth.get_positions();
+        for i in 0..columns {
+            assert_eq!(positions[i].y, 5.0);
+            // Bottom edge swings down under top one.
+            let bottom = positions[(rows - 1) * columns + i];
+            assert!(bottom.y < 3.5);
+            assert!(bottom.z.abs() < 0.5);
+        }
+        // Structural, shear and bend links.
+        assert_eq!(cloth.get_links().len(), 2 * 4 * 5 + 2 * 4 * 4 + 2 * 3 * 5);
+        for link in cloth.get_links() {
+            let length = positions[link.a].distance(&positions[link.b]);
+            assert!((length - link.rest_length).abs() < 0.1 * link.rest_length);
+        }
+    }
+}
@@ -1,46 +1,387 @@
 use rg3d_core::{
     math::{
         vec3::Vec3,
-        plane::Plane
+        plane::Plane,
+        aabb::AxisAlignedBoundingBox,
+        self,
+        TriangleDefinition
     },
     visitor::{Visit, VisitResult, Visitor, VisitError},
     octree::Octree
 };
+use std::collections::{HashMap, VecDeque};
+use crate::{
+    convex_shape::SupportMapping,
+    float_ops::{Backend, FloatOps},
+};
+
+/// Options of [`StaticGeometry::validate_and_repair`].
+#[derive(Copy, Clone, Debug)]
+pub struct RepairOptions {
+    /// Vertices closer than this distance are merged into one.
+    pub weld_distance: f32,
+    /// Triangles with smaller area are removed.
+    pub min_area: f32,
+    /// Flip triangles so that every connected part of mesh has consistent winding.
+    pub fix_winding: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self {
+            weld_distance: 1.0e-4,
+            min_area: 1.0e-8,
+            fix_winding: true,
+        }
+    }
+}
+
+/// Describes changes made by [`StaticGeometry::validate_and_repair`].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RepairReport {
+    /// Amount of removed degenerated triangles.
+    pub removed_triangles: usize,
+    /// Amount of vertices which were merged into other vertex, either duplicate or close
+    /// one. Geometry keeps no indices, so only mesh given to
+    /// [`StaticGeometry::from_mesh_validated`] can have exact duplicates.
+    pub welded_vertices: usize,
+    /// Amount of triangles which winding was flipped.
+    pub flipped_triangles: usize,
+}
+
+impl RepairReport {
+    /// Returns true if geometry was not changed.
+    pub fn is_clean(&self) -> bool {
+        *self == Self::default()
+    }
+}
 
 #[derive(Default, Clone, Debug)]
 pub struct StaticGeometry {
     pub(in crate) triangles: Vec<StaticTriangle>,
     pub(in crate) octree: Octree,
+    /// Normals of edges and vertices of each triangle, same order as triangles.
+    pub(in crate) feature_normals: Vec<TriangleFeatureNormals>,
+    /// Arbitrary flags (material id, etc.) which are reported in ray cast results.
+    pub user_flags: u64,
+    pub(in crate) surface_velocity: Vec3,
 }
 
 impl StaticGeometry {
     pub const OCTREE_THRESHOLD: usize = 64;
 
     pub fn new(triangles: Vec<StaticTriangle>) -> Self {
-        let raw_triangles: Vec<[Vec3; 3]> = triangles.iter().map(|st| st.points).collect();
-
         Self {
-            octree: Octree::new(&raw_triangles, Self::OCTREE_THRESHOLD),
-            triangles
+            octree: build_octree(&triangles),
+            feature_normals: build_feature_normals(&triangles),
+            triangles,
+            user_flags: 0,
+            surface_velocity: Vec3::ZERO,
+        }
+    }
+
+    /// Sets velocity (in units per second) of surface of geometry, like velocity of
+    /// conveyor belt. Geometry itself does not move, but friction drags bodies which
+    /// touch it along the surface with this velocity. Component along normal of surface
+    /// is ignored.
+    pub fn set_surface_velocity(&mut self, velocity: Vec3) {
+        self.surface_velocity = velocity;
+    }
+
+    pub fn get_surface_velocity(&self) -> Vec3 {
+        self.surface_velocity
+    }
+
+    /// Creates static geometry from indexed mesh. Degenerated triangles are skipped.
+    pub fn from_mesh(vertices: &[Vec3], triangles: &[TriangleDefinition]) -> Self {
+        Self::from_mesh_scaled(vertices, triangles, Vec3::UNIT)
+    }
+
+    /// Creates static geometry from indexed mesh scaling each vertex by given scale.
+    /// Degenerated triangles (including ones which became degenerated after scaling)
+    /// are skipped.
+    pub fn from_mesh_scaled(vertices: &[Vec3], triangles: &[TriangleDefinition], scale: Vec3) -> Self {
+        let static_triangles = triangles.iter()
+            .filter_map(|triangle| {
+                let a = vertices[triangle[0] as usize];
+                let b = vertices[triangle[1] as usize];
+                let c = vertices[triangle[2] as usize];
+                StaticTriangle::from_points_scaled(&a, &b, &c, scale)
+            })
+            .collect();
+
+        Self::new(static_triangles)
+    }
+
+    /// Same as [`from_mesh`](Self::from_mesh), but mesh is repaired first, see
+    /// [`validate_and_repair`](Self::validate_and_repair).
+    pub fn from_mesh_validated(vertices: &[Vec3], triangles: &[TriangleDefinition], options: RepairOptions) -> (Self, RepairReport) {
+        let indices = triangles.iter()
+            .map(|triangle| [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize])
+            .collect();
+        let (static_triangles, report) = repair_triangles(vertices, indices, options);
+        (Self::new(static_triangles), report)
+    }
+
+    /// Fixes common problems of imported meshes which cause collision glitches: welds
+    /// vertices which are closer than weld distance, removes triangles which became
+    /// degenerated (zero-area) and optionally makes winding of triangles consistent, so
+    /// normals of connected triangles point to same side. Winding of each connected part
+    /// of mesh follows majority of its triangles. Octree is rebuilt if anything changed.
+    pub fn validate_and_repair(&mut self, options: RepairOptions) -> RepairReport {
+        // Triangles share points by value, so identical points are one vertex.
+        let key = |v: Vec3| (v.x.to_bits(), v.y.to_bits(), v.z.to_bits());
+        let mut vertex_map = HashMap::new();
+        let mut vertices = Vec::new();
+        let indices = self.triangles.iter()
+            .map(|triangle| triangle.points.map(|point| *vertex_map.entry(key(point)).or_insert_with(|| {
+                vertices.push(point);
+                vertices.len() - 1
+            })))
+            .collect();
+        let (static_triangles, report) = repair_triangles(&vertices, indices, options);
+        if !report.is_clean() {
+            self.triangles = static_triangles;
+            self.octree = build_octree(&self.triangles);
+            self.feature_normals = build_feature_normals(&self.triangles);
         }
+        report
+    }
+
+    /// Computes bounding box of every triangle of geometry. Returns invalid bounding
+    /// box (min > max) if there is no triangles.
+    pub fn get_aabb(&self) -> AxisAlignedBoundingBox {
+        let mut aabb = AxisAlignedBoundingBox::default();
+        for triangle in self.triangles.iter() {
+            for point in triangle.points.iter() {
+                aabb.add_point(*point);
+            }
+        }
+        aabb
+    }
+
+    /// Scales every triangle of geometry and rebuilds its octree. Negative scale
+    /// components flip winding of triangles so normals stay outward-facing.
+    pub fn scale(&mut self, scale: Vec3) {
+        self.triangles = self.triangles.iter()
+            .filter_map(|triangle| {
+                let [a, b, c] = triangle.points;
+                StaticTriangle::from_points_scaled(&a, &b, &c, scale)
+            })
+            .collect();
+        self.octree = build_octree(&self.triangles);
+        self.feature_normals = build_feature_normals(&self.triangles);
+    }
+
+    /// Moves every triangle of geometry by given offset and rebuilds its octree.
+    pub fn translate(&mut self, offset: Vec3) {
+        self.triangles = self.triangles.iter()
+            .filter_map(|triangle| {
+                let [a, b, c] = triangle.points;
+                StaticTriangle::from_points(&(a + offset), &(b + offset), &(c + offset))
+            })
+            .collect();
+        self.octree = build_octree(&self.triangles);
+        self.feature_normals = build_feature_normals(&self.triangles);
     }
 }
 
+fn repair_triangles(mesh_vertices: &[Vec3], mut indices: Vec<[usize; 3]>, options: RepairOptions) -> (Vec<StaticTriangle>, RepairReport) {
+    let mut report = RepairReport::default();
+
+    // Weld vertices using spatial hash, each vertex is compared with vertices from
+    // neighbour cells only.
+    let cell_size = options.weld_distance.max(1.0e-6);
+    let cell_of = |p: Vec3| ((p.x / cell_size).floor() as i64, (p.y / cell_size).floor() as i64, (p.z / cell_size).floor() as i64);
+    let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    let mut vertices: Vec<Vec3> = Vec::new();
+    let mut weld = |p: Vec3, report: &mut RepairReport| -> usize {
+        let (x, y, z) = cell_of(p);
+        for cell in (-1..=1).flat_map(|i| (-1..=1).flat_map(move |j| (-1..=1).map(move |k| (x + i, y + j, z + k)))) {
+            if let Some(indices) = grid.get(&cell) {
+                if let Some(index) = indices.iter().find(|index| vertices[**index].sqr_distance(&p) <= options.weld_distance * options.weld_distance) {
+                    report.welded_vertices += 1;
+                    return *index;
+                }
+            }
+        }
+        vertices.push(p);
+        grid.entry((x, y, z)).or_default().push(vertices.len() - 1);
+        vertices.len() - 1
+    };
+
+    // Each mesh vertex is welded once, no matter how many triangles use it.
+    let welded = mesh_vertices.iter().map(|p| weld(*p, &mut report)).collect::<Vec<_>>();
+    for triangle in indices.iter_mut() {
+        *triangle = triangle.map(|index| welded[index]);
+    }
+
+    let count_before = indices.len();
+    indices.retain(|[a, b, c]| {
+        let (a, b, c) = (vertices[*a], vertices[*b], vertices[*c]);
+        (b - a).cross(&(c - a)).len() * 0.5 > options.min_area
+    });
+    report.removed_triangles = count_before - indices.len();
+
+    if options.fix_winding {
+        report.flipped_triangles = fix_winding(&mut indices);
+    }
+
+    let triangles = indices.iter()
+        .filter_map(|[a, b, c]| StaticTriangle::from_points(&vertices[*a], &vertices[*b], &vertices[*c]))
+        .collect();
+
+    (triangles, report)
+}
+
+/// Flips triangles so that neighbour triangles pass their common edge in opposite
+/// directions. Returns amount of flipped triangles.
+fn fix_winding(triangles: &mut [[usize; 3]]) -> usize {
+    let edges_of = |t: &[usize; 3]| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])];
+
+    let mut edge_map: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (i, triangle) in triangles.iter().enumerate() {
+        for (a, b) in edges_of(triangle).iter() {
+            edge_map.entry((*a.min(b), *a.max(b))).or_default().push(i);
+        }
+    }
+
+    let mut flip = vec![false; triangles.len()];
+    let mut visited = vec![false; triangles.len()];
+    let mut queue = VecDeque::new();
+    let mut component = Vec::new();
+    for start in 0..triangles.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        queue.push_back(start);
+        component.clear();
+        while let Some(current) = queue.pop_front() {
+            component.push(current);
+            let mut oriented = triangles[current];
+            if flip[current] {
+                oriented.swap(1, 2);
+            }
+            for (a, b) in edges_of(&oriented).iter() {
+                for neighbour in edge_map[&(*a.min(b), *a.max(b))].iter() {
+                    if !visited[*neighbour] {
+                        visited[*neighbour] = true;
+                        // Neighbour must pass common edge from b to a.
+                        flip[*neighbour] = edges_of(&triangles[*neighbour]).contains(&(*a, *b));
+                        queue.push_back(*neighbour);
+                    }
+                }
+            }
+        }
+
+        // Keep winding of majority of triangles.
+        let flipped = component.iter().filter(|i| flip[**i]).count();
+        if flipped * 2 > component.len() {
+            for i in component.iter() {
+                flip[*i] = !flip[*i];
+            }
+        }
+    }
+
+    let mut flipped = 0;
+    for (triangle, flip) in triangles.iter_mut().zip(flip) {
+        if flip {
+            triangle.swap(1, 2);
+            flipped += 1;
+        }
+    }
+    flipped
+}
+
+fn build_octree(triangles: &[StaticTriangle]) -> Octree {
+    let raw_triangles: Vec<[Vec3; 3]> = triangles.iter().map(|st| st.points).collect();
+    Octree::new(&raw_triangles, StaticGeometry::OCTREE_THRESHOLD)
+}
+
+/// Normals which are used for contacts on edges and vertices of triangle instead of
+/// penetration vector. Penetration vector of contact on edge shared by two triangles can
+/// point into neighbour triangle and push body sideways (so called internal edge problem),
+/// averaged normal of faces adjacent to edge or vertex does not. Features on boundary of
+/// mesh have no normal, penetration vector is used for them.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TriangleFeatureNormals {
+    /// Normals of edges, edge `i` goes from point `i` to point `(i + 1) % 3`.
+    pub edges: [Option<Vec3>; 3],
+    pub vertices: [Option<Vec3>; 3],
+}
+
+/// Points are matched exactly, welded meshes (see [`StaticGeometry::validate_and_repair`])
+/// share edges.
+fn build_feature_normals(triangles: &[StaticTriangle]) -> Vec<TriangleFeatureNormals> {
+    let key = |v: Vec3| (v.x.to_bits(), v.y.to_bits(), v.z.to_bits());
+    let edge_key = |a: Vec3, b: Vec3| {
+        let (a, b) = (key(a), key(b));
+        if a < b { (a, b) } else { (b, a) }
+    };
+
+    // Sum of face normals and amount of faces per edge, sum of face normals and boundary
+    // flag per vertex.
+    let mut edges = HashMap::new();
+    let mut vertices = HashMap::new();
+    for triangle in triangles.iter() {
+        for i in 0..3 {
+            let (a, b) = (triangle.points[i], triangle.points[(i + 1) % 3]);
+            let edge = edges.entry(edge_key(a, b)).or_insert((Vec3::ZERO, 0));
+            edge.0 += triangle.plane.normal;
+            edge.1 += 1;
+            vertices.entry(key(a)).or_insert((Vec3::ZERO, false)).0 += triangle.plane.normal;
+        }
+    }
+    for triangle in triangles.iter() {
+        for i in 0..3 {
+            let (a, b) = (triangle.points[i], triangle.points[(i + 1) % 3]);
+            if edges[&edge_key(a, b)].1 < 2 {
+                vertices.get_mut(&key(a)).unwrap().1 = true;
+                vertices.get_mut(&key(b)).unwrap().1 = true;
+            }
+        }
+    }
+
+    triangles.iter()
+        .map(|triangle| {
+            let mut features = TriangleFeatureNormals::default();
+            for i in 0..3 {
+                let (a, b) = (triangle.points[i], triangle.points[(i + 1) % 3]);
+                let (sum, count) = edges[&edge_key(a, b)];
+                if count > 1 {
+                    features.edges[i] = Backend::normalize(sum);
+                }
+                let (sum, boundary) = vertices[&key(a)];
+                if !boundary {
+                    features.vertices[i] = Backend::normalize(sum);
+                }
+            }
+            features
+        })
+        .collect()
+}
+
 impl Visit for StaticGeometry {
     fn visit(&mut self, name: &str, visitor: &mut Visitor) -> VisitResult {
         visitor.enter_region(name)?;
 
         self.triangles.visit("Triangles", visitor)?;
+        let _ = self.user_flags.visit("UserFlags", visitor); // let _ for backward compatibility.
+        let _ = self.surface_velocity.visit("SurfaceVelocity", visitor); // let _ for backward compatibility.
 
         if visitor.is_reading() {
-            let raw_triangles: Vec<[Vec3; 3]> = self.triangles.iter().map(|st| st.points).collect();
-            self.octree = Octree::new(&raw_triangles, Self::OCTREE_THRESHOLD);
+            self.octree = build_octree(&self.triangles);
+            self.feature_normals = build_feature_normals(&self.triangles);
         }
 
         visitor.leave_region()
     }
 }
 
+/// Triangle of static geometry with precomputed data. Everything except `points` is
+/// derived from points at construction, so points must not be changed directly - create
+/// new triangle with [`from_points`](Self::from_points) instead.
 #[derive(Clone, Debug)]
 pub struct StaticTriangle {
     pub points: [Vec3; 3],
@@ -50,6 +391,8 @@ pub struct StaticTriangle {
     pub ca_dot_ba: f32,
     pub ba_dot_ba: f32,
     pub inv_denom: f32,
+    /// Plane of triangle with unit normal, collision code uses it instead of computing
+    /// normal from points.
     pub plane: Plane,
 }
 
@@ -118,6 +461,19 @@ impl StaticTriangle {
         None
     }
 
+    /// Same as [`Self::from_points`] but scales each point first. Mirroring scale (odd
+    /// amount of negative components) flips winding to keep normal outward-facing.
+    pub fn from_points_scaled(a: &Vec3, b: &Vec3, c: &Vec3, scale: Vec3) -> Option<StaticTriangle> {
+        let a = *a * scale;
+        let b = *b * scale;
+        let c = *c * scale;
+        if scale.x * scale.y * scale.z < 0.0 {
+            Self::from_points(&a, &c, &b)
+        } else {
+            Self::from_points(&a, &b, &c)
+        }
+    }
+
     /// Checks if point lies inside or at edge of triangle. Uses a lot of precomputed data.
     pub fn contains_point(&self, p: Vec3) -> bool {
         let vp = p - self.points[0];
@@ -127,4 +483,149 @@ impl StaticTriangle {
         let v = (self.ca_dot_ca * dot12 - self.ca_dot_ba * dot02) * self.inv_denom;
         u >= 0.0 && v >= 0.0 && u + v < 1.0
     }
-}
\ No newline at end of file
+
+    /// Returns barycentric coordinates of point projected onto plane of triangle, i.e.
+    /// weights of each vertex of triangle: `p = points[0] * x + points[1] * y + points[2] * z`.
+    /// Can be used to interpolate texture coordinates and other vertex attributes.
+    pub fn barycentric(&self, p: Vec3) -> Vec3 {
+        let vp = p - self.points[0];
+        let dot02 = self.ca.dot(&vp);
+        let dot12 = self.ba.dot(&vp);
+        let u = (self.ba_dot_ba * dot02 - self.ca_dot_ba * dot12) * self.inv_denom;
+        let v = (self.ca_dot_ca * dot12 - self.ca_dot_ba * dot02) * self.inv_denom;
+        Vec3::new(1.0 - u - v, v, u)
+    }
+}
+
+/// Static triangles are used in collision detection directly, without wrapping
+/// them into a shape on each query.
+impl SupportMapping for StaticTriangle {
+    fn local_support_point(&self, direction: Vec3) -> Vec3 {
+        math::get_farthest_point(&self.points, direction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rg3d_core::math::{vec3::Vec3, TriangleDefinition};
+    use crate::{
+        Physics,
+        static_geometry::{StaticGeometry, StaticTriangle, RepairOptions, RepairReport},
+        test::{DT, sphere},
+    };
+
+    /// Square of given half size at given height, normal points up.
+    fn quad_mesh(height: f32, half_size: f32) -> (Vec<Vec3>, Vec<TriangleDefinition>) {
+        let vertices = vec![
+            Vec3::new(-half_size, height, -half_size),
+            Vec3::new(-half_size, height, half_size),
+            Vec3::new(half_size, height, half_size),
+            Vec3::new(half_size, height, -half_size),
+        ];
+        (vertices, vec![TriangleDefinition([0, 1, 2]), TriangleDefinition([0, 2, 3])])
+    }
+
+    /// Tetrahedron with outward-facing normals.
+    fn tetrahedron_mesh() -> (Vec<Vec3>, Vec<TriangleDefinition>) {
+        let vertices = vec![Vec3::ZERO, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)];
+        let triangles = vec![TriangleDefinition([0, 2, 1]), TriangleDefinition([0, 1, 3]), TriangleDefinition([0, 3, 2]), TriangleDefinition([1, 2, 3])];
+        (vertices, triangles)
+    }
+
+    fn assert_outward(geometry: &StaticGeometry) {
+        let mut center = Vec3::ZERO;
+        for triangle in geometry.triangles.iter() {
+            center += triangle.points[0] + triangle.points[1] + triangle.points[2];
+        }
+        let center = center.scale(1.0 / (3 * geometry.triangles.len()) as f32);
+        for triangle in geometry.triangles.iter() {
+            let face_center = (triangle.points[0] + triangle.points[1] + triangle.points[2]).scale(1.0 / 3.0);
+            assert!(triangle.plane.normal.dot(&(face_center - center)) > 0.0);
+        }
+    }
+
+    fn resting_height(scale: Vec3) -> f32 {
+        let (vertices, triangles) = quad_mesh(1.0, 2.0);
+        let mut physics = Physics::new();
+        physics.add_static_geometry(StaticGeometry::from_mesh_scaled(&vertices, &triangles, scale));
+        let handle = physics.add_body(sphere(Vec3::new(0.0, 3.0, 0.0), 0.5));
+        for _ in 0..180 {
+            physics.step(DT);
+        }
+        physics.borrow_body(handle).get_position().y
+    }
+
+    #[test]
+    fn body_rests_on_scaled_mesh_at_scaled_height() {
+        let unit = resting_height(Vec3::UNIT);
+        assert!((unit - 1.5).abs() < 0.05);
+        // Surface is at height 2 instead of 1, gap between sphere and surface is same.
+        let doubled = resting_height(Vec3::new(2.0, 2.0, 2.0));
+        assert!(((doubled - 2.0) - (unit - 1.0)).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn negative_scale_keeps_normals_outward() {
+        let (vertices, triangles) = tetrahedron_mesh();
+        let geometry = StaticGeometry::from_mesh(&vertices, &triangles);
+        assert_outward(&geometry);
+
+        for scale in [Vec3::new(-2.0, 1.0, 1.0), Vec3::new(1.0, -1.0, 3.0), Vec3::new(-1.0, -1.0, -1.0), Vec3::new(-1.0, -2.0, 1.0)].iter() {
+            assert_outward(&StaticGeometry::from_mesh_scaled(&vertices, &triangles, *scale));
+            let mut scaled = geometry.clone();
+            scaled.scale(*scale);
+            assert_outward(&scaled);
+        }
+    }
+
+    #[test]
+    fn repair_removes_degenerated_triangle_and_welds_duplicates() {
+        let (mut vertices, mut triangles) = quad_mesh(0.0, 1.0);
+        // Exact duplicate of vertex 2 and vertex close to vertex 3, used by second triangle.
+        vertices.push(vertices[2]);
+        vertices.push(vertices[3] + Vec3::new(0.0, 0.0, 1.0e-5));
+        triangles[1] = TriangleDefinition([0, 4, 5]);
+        // Zero-area triangle.
+        vertices.push(Vec3::new(0.0, 0.0, 0.0));
+        triangles.push(TriangleDefinition([0, 2, 6]));
+
+        let (geometry, report) = StaticGeometry::from_mesh_validated(&vertices, &triangles, RepairOptions::default());
+        assert_eq!(report, RepairReport { removed_triangles: 1, welded_vertices: 2, flipped_triangles: 0 });
+        assert_eq!(geometry.triangles.len(), 2);
+        // Both triangles share an edge now, so it has averaged normal.
+        assert!(geometry.feature_normals.iter().all(|features| features.edges.iter().filter(|edge| edge.is_some()).count() == 1));
+    }
+
+    #[test]
+    fn validate_and_repair_removes_triangle_degenerated_by_welding() {
+        let (vertices, triangles) = quad_mesh(0.0, 1.0);
+        let mut geometry = StaticGeometry::from_mesh(&vertices, &triangles);
+        assert!(geometry.validate_and_repair(RepairOptions::default()).is_clean());
+
+        // Third point is within weld distance of first one.
+        geometry.triangles.push(StaticTriangle::from_points(&Vec3::new(3.0, 0.0, 0.0), &Vec3::new(4.0, 0.0, 0.0), &Vec3::new(3.0, 0.0, 5.0e-5)).unwrap());
+        let report = geometry.validate_and_repair(RepairOptions::default());
+        assert_eq!(report.removed_triangles, 1);
+        assert_eq!(report.welded_vertices, 1);
+        assert_eq!(geometry.triangles.len(), 2);
+        assert_eq!(geometry.feature_normals.len(), 2);
+    }
+
+    #[test]
+    fn cached_normal_matches_cross_product() {
+        let triangles = [
+            [Vec3::ZERO, Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+            [Vec3::new(-3.0, 2.0, 1.0), Vec3::new(5.0, -1.0, 0.5), Vec3::new(0.2, 7.0, -4.0)],
+            [Vec3::new(100.0, 0.0, 100.0), Vec3::new(100.0, 0.0, 100.01), Vec3::new(100.01, 0.0, 100.0)],
+        ];
+        for [a, b, c] in triangles.iter() {
+            let triangle = StaticTriangle::from_points(a, b, c).unwrap();
+            let normal = (*b - *a).cross(&(*c - *a)).normalized().unwrap();
+            assert!((triangle.plane.normal.len() - 1.0).abs() < 1.0e-5);
+            assert!(triangle.plane.normal.distance(&normal) < 1.0e-5);
+            for point in [a, b, c].iter() {
+                assert!(triangle.plane.distance(point) < 1.0e-3);
+            }
+        }
+    }
+}